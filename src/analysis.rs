@@ -0,0 +1,653 @@
+use crate::chart::{Bar, Chart};
+use crate::game_system::GameSystem;
+use crate::locale::Message;
+use crate::parser::{self, ast};
+use crate::render;
+use crate::roll;
+use crate::runtime::Value;
+use crate::sheet::Sheet;
+use rand::SeedableRng;
+use std::collections::BTreeMap;
+
+// 厳密に計算する分布の値の個数の上限
+const SUPPORT_LIMIT: i64 = 10_000;
+// モンテカルロ法の試行回数と時間の上限
+const TRIALS: usize = 10_000;
+const TIME_LIMIT: std::time::Duration = std::time::Duration::from_secs(2);
+// ヒストグラムの棒の数の上限
+const HISTOGRAM_ROWS: usize = 30;
+// ヒストグラムで省く両端の確率
+const TAIL: f64 = 0.001;
+const PERCENTILES: [u32; 5] = [5, 25, 50, 75, 95];
+
+const COLOR: u32 = 0x5865f2;
+
+// 値 → 確率
+pub type Distribution = BTreeMap<i64, f64>;
+
+pub enum Method {
+    Exact,
+    MonteCarlo(usize),
+}
+
+pub struct Analysis {
+    pub input: String,
+    pub method: Method,
+    pub distribution: Distribution,
+    // 結果が成功・失敗の場合は、分布は成功を1、失敗を0とする
+    pub boolean: bool,
+}
+
+// 厳密に計算している途中の値
+enum Exact {
+    Scalar(Distribution),
+    // nBmのような、ダイスの出目の配列
+    Pool { count: i64, sides: i64 },
+    // 大きい（小さい）方から選んだダイスの配列。合計の分布を持つ
+    Kept(Distribution),
+    Boolean(f64),
+}
+
+pub fn analyze(
+    syntax: &parser::Syntax,
+    system: Option<&dyn GameSystem>,
+    macros: &[String],
+    me: Option<&Sheet>,
+    content: &str,
+) -> Result<Analysis, String> {
+    let proc = parser::parse(syntax, content).map_err(|err| format!("{:?}", err))?;
+
+    let exact = match proc.as_slice() {
+        [expr] if macros.is_empty() || !uses_ident(expr) => exact(expr),
+        _ => None,
+    };
+    let (method, distribution, boolean) = match exact {
+        Some(Exact::Boolean(p)) => (Method::Exact, bernoulli(p), true),
+        Some(Exact::Scalar(dist)) | Some(Exact::Kept(dist)) => (Method::Exact, dist, false),
+        Some(Exact::Pool { count, sides }) => match dice_sum(count, sides) {
+            Some(dist) => (Method::Exact, dist, false),
+            None => monte_carlo(&proc, system, macros, me)?,
+        },
+        None => monte_carlo(&proc, system, macros, me)?,
+    };
+
+    Ok(Analysis {
+        input: String::from(content),
+        method,
+        distribution,
+        boolean,
+    })
+}
+
+// マクロで上書きされているかもしれない名前を使っているかどうか
+fn uses_ident(expr: &ast::Expr0) -> bool {
+    match expr {
+        ast::Expr0::Expr0 { left, right, .. } => uses_ident(left) || uses_ident(right),
+        ast::Expr0::Term(ast::Term::Expr0(expr), _) => uses_ident(expr),
+        ast::Expr0::Term(ast::Term::Literal(ast::Literal::Integer(_)), _) => false,
+        _ => true,
+    }
+}
+
+fn exact(expr: &ast::Expr0) -> Option<Exact> {
+    match expr {
+        ast::Expr0::Term(ast::Term::Expr0(expr), _) => exact(expr),
+        ast::Expr0::Term(ast::Term::Literal(ast::Literal::Integer(n)), _) => {
+            Some(Exact::Scalar(point(*n)))
+        }
+        ast::Expr0::Expr0 {
+            left,
+            right,
+            operator,
+            ..
+        } => match operator.as_str() {
+            "d" => dice_sum(constant(left)?, constant(right)?).map(Exact::Scalar),
+            "b" => {
+                let (count, sides) = (constant(left)?, constant(right)?);
+                if count >= 0 && sides >= 1 && within(&[count, sides], SUPPORT_LIMIT) {
+                    Some(Exact::Pool { count, sides })
+                } else {
+                    None
+                }
+            }
+            "+" | "-" | "*" | "/" => {
+                let left = scalar(exact(left)?)?;
+                let right = scalar(exact(right)?)?;
+                combine(&left, &right, operator).map(Exact::Scalar)
+            }
+            "==" | "!=" | "<=" | ">=" | "<" | ">" => {
+                let threshold = constant(right)?;
+                match exact(left)? {
+                    Exact::Scalar(dist) => Some(Exact::Boolean(
+                        dist.iter()
+                            .filter(|(value, _)| compare(**value, threshold, operator))
+                            .map(|(_, p)| p)
+                            .sum(),
+                    )),
+                    // 出目ごとに比較して、成功の数を数える
+                    Exact::Pool { count, sides } => {
+                        let hits = (1..=sides)
+                            .filter(|face| compare(*face, threshold, operator))
+                            .count();
+                        Some(Exact::Scalar(binomial(count, hits as f64 / sides as f64)))
+                    }
+                    _ => None,
+                }
+            }
+            // max_of k NbM, min_of k NbM
+            " " => {
+                if let ast::Expr0::Expr0 {
+                    left: function,
+                    right: keep,
+                    operator,
+                    ..
+                } = strip(left)
+                {
+                    let name = match strip(function) {
+                        ast::Expr0::Term(ast::Term::Literal(ast::Literal::Ident(ident)), _) => {
+                            ident.name()
+                        }
+                        _ => return None,
+                    };
+                    let highest = match name.as_str() {
+                        "max_of" => true,
+                        "min_of" => false,
+                        _ => return None,
+                    };
+                    if operator != " " {
+                        return None;
+                    }
+                    match exact(right)? {
+                        Exact::Pool { count, sides } => {
+                            keep_sum(count, sides, constant(keep)?, highest).map(Exact::Kept)
+                        }
+                        _ => None,
+                    }
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn strip(expr: &ast::Expr0) -> &ast::Expr0 {
+    match expr {
+        ast::Expr0::Term(ast::Term::Expr0(expr), _) => strip(expr),
+        _ => expr,
+    }
+}
+
+fn constant(expr: &ast::Expr0) -> Option<i64> {
+    match exact(expr)? {
+        Exact::Scalar(dist) if dist.len() == 1 => dist.keys().next().copied(),
+        _ => None,
+    }
+}
+
+fn scalar(exact: Exact) -> Option<Distribution> {
+    match exact {
+        Exact::Scalar(dist) => Some(dist),
+        _ => None,
+    }
+}
+
+fn compare(left: i64, right: i64, operator: &str) -> bool {
+    match operator {
+        "==" => left == right,
+        "!=" => left != right,
+        "<=" => left <= right,
+        ">=" => left >= right,
+        "<" => left < right,
+        _ => left > right,
+    }
+}
+
+fn point(value: i64) -> Distribution {
+    let mut dist = Distribution::new();
+    dist.insert(value, 1.0);
+    dist
+}
+
+fn bernoulli(p: f64) -> Distribution {
+    let mut dist = Distribution::new();
+    dist.insert(0, 1.0 - p);
+    dist.insert(1, p);
+    dist
+}
+
+fn combine(left: &Distribution, right: &Distribution, operator: &str) -> Option<Distribution> {
+    if (left.len() * right.len()) as i64 > SUPPORT_LIMIT * 100 {
+        return None;
+    }
+    let mut dist = Distribution::new();
+    for (l, p) in left {
+        for (r, q) in right {
+            let value = match operator {
+                "+" => l.checked_add(*r)?,
+                "-" => l.checked_sub(*r)?,
+                "*" => l.checked_mul(*r)?,
+                _ => l.checked_div(*r)?,
+            };
+            *dist.entry(value).or_insert(0.0) += p * q;
+        }
+    }
+    if dist.len() as i64 > SUPPORT_LIMIT {
+        None
+    } else {
+        Some(dist)
+    }
+}
+
+// 積が上限以下かどうか。溢れるほど大きいときは上限を超えたものとして、モンテカルロ法に任せる
+fn within(factors: &[i64], limit: i64) -> bool {
+    factors
+        .iter()
+        .try_fold(1_i64, |acc, factor| acc.checked_mul(*factor))
+        .is_some_and(|product| product <= limit)
+}
+
+// NdMの合計の分布。1個ずつ足していき、出目の範囲の確率の和を累積和で求める
+fn dice_sum(count: i64, sides: i64) -> Option<Distribution> {
+    if count < 0 || sides < 1 || !within(&[count, sides], SUPPORT_LIMIT) {
+        return None;
+    }
+    let sides = sides as usize;
+    // probs[k]は合計がk+（振った個数）になる確率
+    let mut probs = vec![1.0];
+    for _ in 0..count {
+        let mut acc = vec![0.0; probs.len() + 1];
+        for (idx, p) in probs.iter().enumerate() {
+            acc[idx + 1] = acc[idx] + p;
+        }
+        probs = (0..probs.len() + sides - 1)
+            .map(|k| {
+                (acc[(k + 1).min(probs.len())] - acc[k.saturating_sub(sides - 1)]) / sides as f64
+            })
+            .collect();
+    }
+    Some(
+        probs
+            .into_iter()
+            .enumerate()
+            .map(|(k, p)| (k as i64 + count, p))
+            .collect(),
+    )
+}
+
+// 成功率pのダイスをcount個振ったときの成功数の分布
+fn binomial(count: i64, p: f64) -> Distribution {
+    let mut probs = vec![1.0];
+    for _ in 0..count {
+        let mut next = vec![0.0; probs.len() + 1];
+        for (k, q) in probs.iter().enumerate() {
+            next[k] += q * (1.0 - p);
+            next[k + 1] += q * p;
+        }
+        probs = next;
+    }
+    probs
+        .into_iter()
+        .enumerate()
+        .map(|(k, q)| (k as i64, q))
+        .collect()
+}
+
+// NbMから大きい（小さい）方のkeep個を選んだときの合計の分布
+fn keep_sum(count: i64, sides: i64, keep: i64, highest: bool) -> Option<Distribution> {
+    if keep <= 0 || keep > count || !within(&[count, count, sides], SUPPORT_LIMIT * 10) {
+        return None;
+    }
+    let (count, keep) = (count as usize, keep as usize);
+    // 出目を大きい順（小さい順）に決めていき、（決めた個数, 選んだダイスの合計）の確率を持つ
+    let mut states: BTreeMap<(usize, i64), f64> = BTreeMap::new();
+    states.insert((0, 0), 1.0);
+    let faces: Vec<i64> = if highest {
+        (1..=sides).rev().collect()
+    } else {
+        (1..=sides).collect()
+    };
+    for (idx, face) in faces.iter().enumerate() {
+        let last = idx + 1 == faces.len();
+        // まだ決めていないダイスのうち、この出目になる確率
+        let p = 1.0 / (faces.len() - idx) as f64;
+        let mut next = BTreeMap::new();
+        for ((assigned, sum), q) in states {
+            let rest = count - assigned;
+            for taken in 0..=rest {
+                if last && taken != rest {
+                    continue;
+                }
+                let weight = if last {
+                    1.0
+                } else {
+                    choose(rest, taken)
+                        * p.powi(taken as i32)
+                        * (1.0 - p).powi((rest - taken) as i32)
+                };
+                let kept = keep.saturating_sub(assigned).min(taken) as i64;
+                *next
+                    .entry((assigned + taken, sum + kept * face))
+                    .or_insert(0.0) += q * weight;
+            }
+        }
+        states = next;
+    }
+    let mut dist = Distribution::new();
+    for ((_, sum), q) in states {
+        *dist.entry(sum).or_insert(0.0) += q;
+    }
+    Some(dist)
+}
+
+fn choose(n: usize, k: usize) -> f64 {
+    (0..k).fold(1.0, |acc, i| acc * (n - i) as f64 / (i + 1) as f64)
+}
+
+fn monte_carlo(
+    proc: &ast::Proc,
+    system: Option<&dyn GameSystem>,
+    macros: &[String],
+    me: Option<&Sheet>,
+) -> Result<(Method, Distribution, bool), String> {
+    let mut rng = rand::rngs::StdRng::from_entropy();
+    let env = roll::environment(system, macros, me);
+    let begin_time = std::time::Instant::now();
+
+    let mut counts: BTreeMap<i64, usize> = BTreeMap::new();
+    let mut boolean = true;
+    let mut trials = 0;
+    while trials < TRIALS && begin_time.elapsed() < TIME_LIMIT {
+        // 試行ごとに環境を捨てて、前の試行の定義が残らないようにする
+        let mut scoped_env = async_std::task::block_on(env.capture());
+        let val = proc.evalute(
+            &mut scoped_env,
+            &mut rng,
+            &mut vec![],
+            &std::time::Instant::now(),
+        );
+        async_std::task::block_on(scoped_env.free());
+
+        if let Value::Err(why) = val.as_ref() {
+            return Err(why.clone());
+        }
+        let (number, is_boolean) =
+            numeric(&val).ok_or_else(|| format!("{:?}は数値として扱えません", val))?;
+        boolean &= is_boolean;
+        *counts.entry(number).or_insert(0) += 1;
+        trials += 1;
+    }
+
+    let distribution = counts
+        .into_iter()
+        .map(|(value, count)| (value, count as f64 / trials as f64))
+        .collect();
+    Ok((Method::MonteCarlo(trials), distribution, boolean))
+}
+
+// 結果を数値にする。成功・失敗かどうかも返す
+fn numeric(val: &Value) -> Option<(i64, bool)> {
+    match val {
+        Value::Integer(n) => Some((*n, false)),
+        Value::Boolean(b) => Some((*b as i64, true)),
+        Value::Array(a) => {
+            // 成功の数、または出目の合計
+            if let Some(successes) = a
+                .iter()
+                .map(|item| match item.as_ref() {
+                    Value::Boolean(b) => Some(*b as i64),
+                    _ => None,
+                })
+                .sum::<Option<i64>>()
+            {
+                Some((successes, false))
+            } else {
+                a.iter()
+                    .map(|item| item.as_integer())
+                    .sum::<Option<i64>>()
+                    .map(|sum| (sum, false))
+            }
+        }
+        _ => None,
+    }
+}
+
+impl Analysis {
+    pub fn mean(&self) -> f64 {
+        self.distribution
+            .iter()
+            .map(|(value, p)| *value as f64 * p)
+            .sum()
+    }
+
+    pub fn standard_deviation(&self) -> f64 {
+        let mean = self.mean();
+        self.distribution
+            .iter()
+            .map(|(value, p)| (*value as f64 - mean).powi(2) * p)
+            .sum::<f64>()
+            .sqrt()
+    }
+
+    // 累積確率がq%以上になる最小の値
+    pub fn percentile(&self, q: u32) -> i64 {
+        self.quantile(q as f64 / 100.0)
+    }
+
+    fn quantile(&self, q: f64) -> i64 {
+        let mut acc = 0.0;
+        for (value, p) in &self.distribution {
+            acc += p;
+            if acc >= q - 1e-9 {
+                return *value;
+            }
+        }
+        self.distribution.keys().last().copied().unwrap_or(0)
+    }
+
+    fn method(&self) -> String {
+        match self.method {
+            Method::Exact => Message::Exact.text(),
+            Method::MonteCarlo(trials) => Message::MonteCarlo(trials).text(),
+        }
+    }
+
+    // 見出しと、統計量の行
+    fn summary(&self) -> (String, Vec<String>) {
+        if self.boolean {
+            let p = self.distribution.get(&1).copied().unwrap_or(0.0);
+            let mut line = Message::SuccessRate(p * 100.0).text();
+            if let Method::MonteCarlo(trials) = self.method {
+                // 95%信頼区間の幅
+                let error = 1.96 * (p * (1.0 - p) / trials.max(1) as f64).sqrt();
+                line += &Message::Margin(error * 100.0).text();
+            }
+            let title = Message::Probability {
+                input: &self.input,
+                method: &self.method(),
+            };
+            (title.text(), vec![line])
+        } else {
+            let percentiles: Vec<_> = PERCENTILES
+                .iter()
+                .map(|q| format!("{}%:{}", q, self.percentile(*q)))
+                .collect();
+            let title = Message::Distribution {
+                input: &self.input,
+                method: &self.method(),
+            };
+            let mean = Message::MeanAndDeviation {
+                mean: self.mean(),
+                deviation: self.standard_deviation(),
+            };
+            (
+                title.text(),
+                vec![
+                    mean.text(),
+                    Message::Percentiles(&percentiles.join(" ")).text(),
+                ],
+            )
+        }
+    }
+
+    // 値ごとの確率の棒グラフ
+    pub fn chart(&self) -> Chart {
+        if self.distribution.is_empty() {
+            return Chart { bars: vec![] };
+        }
+        // ほとんど出ない両端の値は省く
+        let (min, max) = (self.quantile(TAIL), self.quantile(1.0 - TAIL));
+        // 値の種類が多いときはまとめて1本にする
+        let width = ((max - min) / HISTOGRAM_ROWS as i64 + 1).max(1);
+        let mut rows: BTreeMap<i64, f64> = BTreeMap::new();
+        for (value, p) in self.distribution.range(min..=max) {
+            *rows.entry((value - min) / width).or_insert(0.0) += p;
+        }
+        let bars = rows
+            .into_iter()
+            .map(|(row, p)| {
+                let from = min + row * width;
+                Bar {
+                    label: if width == 1 {
+                        format!("{}", from)
+                    } else {
+                        format!("{}-{}", from, from + width - 1)
+                    },
+                    value: p,
+                    caption: format!("{:.2}%", p * 100.0),
+                }
+            })
+            .collect();
+        Chart { bars }
+    }
+
+    pub fn text(&self) -> String {
+        let (title, lines) = self.summary();
+        let mut res = format!("{}\n{}", title, lines.join("\n"));
+        if !self.boolean {
+            res += format!("\n```\n{}\n```", self.chart().text()).as_str();
+        }
+        res
+    }
+
+    pub fn embed(&self) -> serde_json::Value {
+        let (title, lines) = self.summary();
+        let mut embed = serde_json::json!({
+            "title": title,
+            "description": lines.join("\n"),
+            "color": COLOR,
+        });
+        if !self.boolean {
+            embed["fields"] = serde_json::json!([{
+                "name": Message::Histogram.text(),
+                // コードブロックの囲みの分を残して切り詰める
+                "value": format!(
+                    "```\n{}\n```",
+                    render::truncate(&self.chart().text(), render::FIELD_LIMIT - 8)
+                ),
+            }]);
+        }
+        embed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyze(content: &str) -> Analysis {
+        super::analyze(&parser::Syntax::Native, None, &[], None, content).unwrap()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn sum_of_dice() {
+        let analysis = analyze("3d6+2");
+        assert!(matches!(analysis.method, Method::Exact));
+        assert_close(analysis.mean(), 12.5);
+        assert_close(analysis.distribution[&5], 1.0 / 216.0);
+        assert_close(analysis.distribution[&12], 27.0 / 216.0);
+        assert_eq!(analysis.percentile(50), 12);
+    }
+
+    #[test]
+    fn probability_of_success() {
+        let analysis = analyze("2d6>=7");
+        assert!(analysis.boolean);
+        assert_close(analysis.distribution[&1], 21.0 / 36.0);
+    }
+
+    #[test]
+    fn count_successes() {
+        let analysis = analyze("4b6>=5");
+        assert_close(analysis.mean(), 4.0 / 3.0);
+        assert_close(analysis.distribution[&4], 1.0 / 81.0);
+    }
+
+    #[test]
+    fn fall_back_on_huge_operands() {
+        assert!(!within(&[9_999_999_999, 9_999_999_999], SUPPORT_LIMIT));
+        assert!(within(&[6, 6], SUPPORT_LIMIT));
+        assert!(!within(&[i64::MAX, i64::MAX, 2], SUPPORT_LIMIT * 10));
+        // 積が溢れる個数と面数でも、少ない個数ならモンテカルロ法で求められる
+        for content in &[
+            "2d4611686018427387904",
+            "2b9223372036854775807>=5",
+            "max_of 1 2b4611686018427387904",
+        ] {
+            let res = super::analyze(&parser::Syntax::Native, None, &[], None, content);
+            assert!(
+                matches!(
+                    res.as_ref().map(|analysis| &analysis.method),
+                    Ok(Method::MonteCarlo(_))
+                ),
+                "{}",
+                content
+            );
+        }
+    }
+
+    #[test]
+    fn keep_highest() {
+        // 4B6の大きい方から3個の合計
+        let analysis = analyze("max_of 3 4b6");
+        assert_close(analysis.distribution.values().sum(), 1.0);
+        assert_close(analysis.distribution[&18], 21.0 / 1296.0);
+        assert_close(analysis.distribution[&3], 1.0 / 1296.0);
+        assert!((analysis.mean() - 12.2446).abs() < 1e-3);
+
+        let analysis = analyze("min_of 1 2b20");
+        assert_close(analysis.distribution[&20], 1.0 / 400.0);
+        assert_close(analysis.distribution[&1], 39.0 / 400.0);
+    }
+
+    #[test]
+    fn fall_back_to_monte_carlo() {
+        let analysis = analyze("x := 1d6; x * x");
+        assert!(matches!(analysis.method, Method::MonteCarlo(_)));
+        assert!(analysis
+            .distribution
+            .keys()
+            .all(|value| [1, 4, 9, 16, 25, 36].contains(value)));
+    }
+
+    #[test]
+    fn truncate_wide_histogram() {
+        let analysis = analyze("1d1000000000");
+        let embed = analysis.embed();
+        let value = embed["fields"][0]["value"].as_str().unwrap();
+        assert!(value.chars().count() <= render::FIELD_LIMIT);
+        assert!(value.starts_with("```\n"));
+        assert!(value.ends_with("\n```"));
+    }
+}
@@ -0,0 +1,248 @@
+// 棒グラフの1本分
+pub struct Bar {
+    pub label: String,
+    pub value: f64,
+    // 棒の横に表示する値
+    pub caption: String,
+}
+
+// 分布を表す棒グラフ。テキストとPNG画像で出力できる
+pub struct Chart {
+    pub bars: Vec<Bar>,
+}
+
+// テキストの棒の長さの上限（文字数）
+const BAR_WIDTH: usize = 20;
+// 1文字を8分割して棒の端を表す
+const BLOCKS: [&str; 8] = ["", "▏", "▎", "▍", "▌", "▋", "▊", "▉"];
+
+// PNGの大きさと色
+const PLOT_HEIGHT: u32 = 200;
+const SLOT_WIDTH: u32 = 24;
+const MARGIN: u32 = 16;
+const SCALE: u32 = 2;
+const BACKGROUND: [u8; 3] = [0xff, 0xff, 0xff];
+const FOREGROUND: [u8; 3] = [0x58, 0x65, 0xf2];
+const AXIS: [u8; 3] = [0x4f, 0x54, 0x5c];
+
+impl Chart {
+    fn highest(&self) -> f64 {
+        self.bars.iter().map(|bar| bar.value).fold(0.0, f64::max)
+    }
+
+    // コードブロックに入れて表示するテキストの棒グラフ
+    pub fn text(&self) -> String {
+        let highest = self.highest();
+        let label_width = self
+            .bars
+            .iter()
+            .map(|bar| bar.label.chars().count())
+            .max()
+            .unwrap_or(0);
+        let caption_width = self
+            .bars
+            .iter()
+            .map(|bar| bar.caption.chars().count())
+            .max()
+            .unwrap_or(0);
+        self.bars
+            .iter()
+            .map(|bar| {
+                let eighths = if highest > 0.0 {
+                    (bar.value / highest * (BAR_WIDTH * 8) as f64).round() as usize
+                } else {
+                    0
+                };
+                let line = format!(
+                    "{:>label_width$} {:>caption_width$} {}{}",
+                    bar.label,
+                    bar.caption,
+                    "█".repeat(eighths / 8),
+                    BLOCKS[eighths % 8],
+                    label_width = label_width,
+                    caption_width = caption_width
+                );
+                String::from(line.trim_end())
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // 縦棒グラフのPNG画像
+    pub fn png(&self) -> Vec<u8> {
+        let count = self.bars.len().max(1) as u32;
+        let char_width = (GLYPH_WIDTH + 1) * SCALE;
+        let label_width = self
+            .bars
+            .iter()
+            .map(|bar| bar.label.len() as u32 * char_width)
+            .max()
+            .unwrap_or(0);
+        // ラベルが重ならないよう、何本おきにラベルを付けるか
+        let step = ((label_width + char_width) / SLOT_WIDTH + 1) as usize;
+
+        let width = MARGIN * 2 + count * SLOT_WIDTH;
+        let height = MARGIN * 3 + PLOT_HEIGHT + GLYPH_HEIGHT * SCALE;
+        let mut canvas = Canvas::new(width, height);
+        let baseline = MARGIN + PLOT_HEIGHT;
+
+        let highest = self.highest();
+        for (idx, bar) in self.bars.iter().enumerate() {
+            let left = MARGIN + idx as u32 * SLOT_WIDTH;
+            let bar_height = if highest > 0.0 {
+                (bar.value / highest * PLOT_HEIGHT as f64).round() as u32
+            } else {
+                0
+            };
+            canvas.fill(
+                left + 2,
+                baseline - bar_height,
+                SLOT_WIDTH - 4,
+                bar_height,
+                FOREGROUND,
+            );
+            if idx % step == 0 {
+                let text_width = bar.label.len() as u32 * char_width;
+                let center = left + SLOT_WIDTH / 2;
+                canvas.text(
+                    center.saturating_sub(text_width / 2),
+                    baseline + MARGIN,
+                    &bar.label,
+                );
+            }
+        }
+        canvas.fill(MARGIN, baseline, width - MARGIN * 2, 1, AXIS);
+
+        canvas.encode()
+    }
+}
+
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: BACKGROUND
+                .iter()
+                .cycle()
+                .take((width * height * 3) as usize)
+                .copied()
+                .collect(),
+        }
+    }
+
+    fn fill(&mut self, x: u32, y: u32, width: u32, height: u32, color: [u8; 3]) {
+        for row in y..(y + height).min(self.height) {
+            for column in x..(x + width).min(self.width) {
+                let idx = ((row * self.width + column) * 3) as usize;
+                self.pixels[idx..idx + 3].copy_from_slice(&color);
+            }
+        }
+    }
+
+    // フォントにない文字は空白にする
+    fn text(&mut self, x: u32, y: u32, text: &str) {
+        for (idx, c) in text.chars().enumerate() {
+            let left = x + idx as u32 * (GLYPH_WIDTH + 1) * SCALE;
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for column in 0..GLYPH_WIDTH {
+                    if bits & (1 << (GLYPH_WIDTH - 1 - column)) != 0 {
+                        self.fill(
+                            left + column * SCALE,
+                            y + row as u32 * SCALE,
+                            SCALE,
+                            SCALE,
+                            AXIS,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut png = vec![];
+        let mut encoder = png::Encoder::new(&mut png, self.width, self.height);
+        encoder.set_color(png::ColorType::RGB);
+        encoder.set_depth(png::BitDepth::Eight);
+        // メモリへの書き込みなので失敗しない
+        let res = encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.pixels));
+        if let Err(why) = res {
+            println!("Failed to encode a chart: {:?}", why);
+        }
+        png
+    }
+}
+
+const GLYPH_WIDTH: u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+
+// ラベルに使う文字の3x5ドットのフォント
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT as usize] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        _ => [0; GLYPH_HEIGHT as usize],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chart() -> Chart {
+        Chart {
+            bars: vec![
+                Bar {
+                    label: String::from("1"),
+                    value: 2.0,
+                    caption: String::from("2"),
+                },
+                Bar {
+                    label: String::from("10"),
+                    value: 1.0,
+                    caption: String::from("1"),
+                },
+                Bar {
+                    label: String::from("-3"),
+                    value: 0.0,
+                    caption: String::from("0"),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn text_bars() {
+        let bar = "█".repeat(BAR_WIDTH);
+        let half = "█".repeat(BAR_WIDTH / 2);
+        assert_eq!(chart().text(), format!(" 1 2 {}\n10 1 {}\n-3 0", bar, half));
+    }
+
+    #[test]
+    fn png_signature() {
+        let png = chart().png();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let decoder = png::Decoder::new(png.as_slice());
+        let (info, _) = decoder.read_info().unwrap();
+        assert_eq!(info.width, MARGIN * 2 + 3 * SLOT_WIDTH);
+    }
+}
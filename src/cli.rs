@@ -0,0 +1,216 @@
+use crate::analysis;
+use crate::history::{Entry, History};
+use crate::locale::{self, Language, Message};
+use crate::parser;
+use crate::render;
+use crate::stats;
+use crate::{importer, sheet};
+use std::path::Path;
+
+// 引数を付けて起動した場合は、BOTを起動せずにコマンドラインの機能を使う
+// 表示する言語は環境変数DICEBOT_LANGUAGEで指定する
+pub fn run(args: &[String]) {
+    let language = std::env::var("DICEBOT_LANGUAGE")
+        .ok()
+        .and_then(|name| Language::find(&name))
+        .unwrap_or_default();
+    locale::using(language, || match args.first().map(String::as_str) {
+        Some("import") => import(&args[1..]),
+        Some("replay") if args.len() == 2 => replay(&args[1]),
+        Some("prob") if (2..=3).contains(&args.len()) => prob(&args[1], args.get(2)),
+        Some("stats") if (2..=3).contains(&args.len()) => report(&args[1], args.get(2)),
+        _ => println!("{}", Message::Usage.text()),
+    })
+}
+
+fn import(paths: &[String]) {
+    for path in paths {
+        let json = match std::fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(why) => {
+                eprintln!("{}: {}", path, why);
+                continue;
+            }
+        };
+        match importer::import(&json) {
+            Ok((format, sheet)) => println!("{}（{}）: {}", path, format, sheet::to_value(&sheet)),
+            Err(err) => eprintln!("{}: {}", path, err),
+        }
+    }
+}
+
+fn replay(path: &str) {
+    let entries = History::load(path);
+    print!("{}", replay_entries(&entries));
+}
+
+// 振り直した経過と、記録と一致した件数
+fn replay_entries(entries: &[Entry]) -> String {
+    let mut res = String::new();
+    let mut mismatches = 0;
+    for entry in entries {
+        res += format!(
+            "{} {}{}\n",
+            entry.local_time(),
+            Message::Heading(&entry.user_name).text(),
+            entry.input
+        )
+        .as_str();
+        match entry.replay() {
+            Ok(rolled) => {
+                // 記録された結果と比べるので、記録したときの言語で表示する
+                let value = locale::using(entry.language, || format!("{}", rolled.value));
+                for a_line in &rolled.log {
+                    res += format!(" -> {}\n", render::log_line(a_line)).as_str();
+                }
+                if value == entry.value {
+                    res += format!(" -> {}\n", value).as_str();
+                } else {
+                    mismatches += 1;
+                    res += format!(
+                        " -> {}{}\n",
+                        value,
+                        Message::RecordedResult(&entry.value).text()
+                    )
+                    .as_str();
+                }
+            }
+            Err(err) => {
+                mismatches += 1;
+                res += format!(" -> {}\n", err).as_str();
+            }
+        }
+    }
+    res += Message::Replayed {
+        total: entries.len(),
+        matched: entries.len() - mismatches,
+    }
+    .text()
+    .as_str();
+    res += "\n";
+    res
+}
+
+fn prob(content: &str, png: Option<&String>) {
+    match analysis::analyze(&parser::Syntax::Native, None, &[], None, content) {
+        Ok(analysis) => {
+            println!("{}", analysis.text());
+            if let Some(path) = png {
+                write(Path::new(path), &analysis.chart().png());
+            }
+        }
+        Err(err) => eprintln!("{}", err),
+    }
+}
+
+fn report(path: &str, dir: Option<&String>) {
+    let entries = History::load(path);
+    if entries.is_empty() {
+        eprintln!("{}: {}", path, Message::NothingToSummarize.text());
+        return;
+    }
+    let report = stats::Report::new(Message::StatsTitle(Some(path)).text(), &entries);
+    println!("{}", report.text());
+    if let Some(dir) = dir {
+        if let Err(why) = std::fs::create_dir_all(dir) {
+            eprintln!("{}: {}", dir, why);
+            return;
+        }
+        for (sides, chart) in report.charts() {
+            write(
+                &Path::new(dir).join(format!("d{}.png", sides)),
+                &chart.png(),
+            );
+        }
+    }
+}
+
+fn write(path: &Path, content: &[u8]) {
+    match std::fs::write(path, content) {
+        Ok(()) => println!("{}", Message::Written(&path.display().to_string()).text()),
+        Err(why) => eprintln!("{}: {}", path.display(), why),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::locale::{self, Language};
+    use crate::roll;
+    use crate::sheet::{Field, Sheet};
+
+    // 記録したときと同じ設定で振った結果を記録にする
+    fn entry(input: &str, system: Option<&str>, me: Option<Sheet>, language: Language) -> Entry {
+        let macros = vec![String::from("atk := 2d6 + 4")];
+        let (rolled, value) = locale::using(language, || {
+            let rolled = roll::evaluate(
+                &parser::Syntax::Native,
+                system.and_then(crate::game_system::find),
+                &macros,
+                me.as_ref(),
+                42,
+                input,
+            )
+            .unwrap();
+            let value = format!("{}", rolled.value);
+            (rolled, value)
+        });
+        Entry {
+            timestamp: chrono::Utc::now(),
+            guild_id: None,
+            channel_id: 1,
+            user_id: 1,
+            user_name: String::from("アリス"),
+            input: String::from(input),
+            syntax: String::from("Native"),
+            system: system.map(String::from),
+            macros,
+            me,
+            log: rolled.log.clone(),
+            value,
+            outcome: rolled.outcome(),
+            seed: 42,
+            language,
+            secret: false,
+        }
+    }
+
+    #[test]
+    fn replay_recorded_rolls() {
+        let mut me = Sheet::new();
+        me.insert(String::from("skill_spot"), Field::Integer(60));
+        let entries = [
+            entry("atk + 1d6", None, None, Language::Ja),
+            entry(
+                "CC<=me.skill_spot",
+                Some("Cthulhu7th"),
+                Some(me),
+                Language::Ja,
+            ),
+            entry("2d6 >= 7", None, None, Language::En),
+        ];
+        let text = replay_entries(&entries);
+        assert!(text.ends_with("3件中3件が記録と一致しました\n"), "{}", text);
+        assert!(text.contains(&format!(" -> {}\n", entries[0].value)));
+        for a_line in &entries[1].log {
+            assert!(text.contains(&render::log_line(a_line)));
+        }
+
+        // 結果が書き換えられた記録や、振り直せない記録は一致しない
+        let tampered = Entry {
+            value: String::from("999"),
+            ..entries[0].clone()
+        };
+        let unknown = Entry {
+            system: Some(String::from("Unknown")),
+            ..entries[0].clone()
+        };
+        let text = replay_entries(&[tampered, unknown]);
+        assert!(text.contains("（記録された結果：999）"));
+        assert!(text.contains(" -> Unknownというシステムはありません\n"));
+        assert!(text.ends_with("2件中0件が記録と一致しました\n"));
+
+        let text = locale::using(Language::En, || replay_entries(&entries));
+        assert!(text.ends_with("3 of 3 rolls matched the log\n"), "{}", text);
+    }
+}
@@ -0,0 +1,113 @@
+use crate::interaction::Interaction;
+
+pub enum Command<'a> {
+    Help(Option<&'a str>),
+    System(Option<&'a str>),
+    Syntax(Option<&'a str>),
+    Prefix(Option<&'a str>),
+    AutoRoll(Option<&'a str>),
+    SafeMode(Option<&'a str>),
+    GmRole(Option<&'a str>),
+    Reveal(Option<&'a str>),
+    Output(Option<&'a str>),
+    Language(Option<&'a str>),
+    SaveMacro(Option<&'a str>),
+    ListMacros,
+    DeleteMacro(Option<&'a str>),
+    ExportMacros,
+    Sheet(Option<&'a str>),
+    Initiative(Option<&'a str>),
+    History(Option<&'a str>),
+    Export(Option<&'a str>),
+    Stats(Option<&'a str>),
+    Prob(Option<&'a str>),
+    Explain(Option<&'a str>),
+    Trace(Option<&'a str>),
+    Roll(&'a str),
+    SecretRoll(&'a str),
+}
+
+impl<'a> Command<'a> {
+    pub fn parse(content: &'a str) -> Self {
+        if let Some(content) = secret_roll(content.trim()) {
+            return Self::SecretRoll(content);
+        }
+
+        let (name, args) = split_first_word(content.trim());
+        let args = Some(args).filter(|args| !args.is_empty());
+        match name {
+            "help" => Self::Help(args),
+            "system" => Self::System(args),
+            "syntax" => Self::Syntax(args),
+            "prefix" => Self::Prefix(args),
+            "autoroll" => Self::AutoRoll(args),
+            "safemode" => Self::SafeMode(args),
+            "gmrole" => Self::GmRole(args),
+            "reveal" => Self::Reveal(args),
+            "output" => Self::Output(args),
+            "language" | "lang" => Self::Language(args),
+            "save" => Self::SaveMacro(args),
+            "sheet" => Self::Sheet(args),
+            "init" => Self::Initiative(args),
+            "history" => Self::History(args),
+            "export" => Self::Export(args),
+            "stats" => Self::Stats(args),
+            "prob" => Self::Prob(args),
+            "explain" => Self::Explain(args),
+            "trace" => Self::Trace(args),
+            "macro" => {
+                let (action, args) = split_first_word(args.unwrap_or_default());
+                Self::macro_command(action, Some(args).filter(|args| !args.is_empty()))
+            }
+            _ => Self::Roll(content),
+        }
+    }
+
+    pub fn from_interaction(interaction: &'a Interaction) -> Option<Self> {
+        match interaction.name.as_str() {
+            "roll" => interaction.option_str("expr").map(|expr| {
+                if interaction.option_bool("hidden").unwrap_or(false) {
+                    Self::SecretRoll(expr)
+                } else {
+                    Self::Roll(expr)
+                }
+            }),
+            "help" => Some(Self::Help(interaction.option_str("name"))),
+            "system" => Some(Self::System(interaction.option_str("name"))),
+            "syntax" => Some(Self::Syntax(interaction.option_str("name"))),
+            "macro" => Some(Self::macro_command(
+                interaction.option_str("action").unwrap_or_default(),
+                interaction.option_str("args"),
+            )),
+            _ => None,
+        }
+    }
+
+    fn macro_command(action: &'a str, args: Option<&'a str>) -> Self {
+        match action {
+            "save" => Self::SaveMacro(args),
+            "delete" => Self::DeleteMacro(args),
+            "export" => Self::ExportMacros,
+            _ => Self::ListMacros,
+        }
+    }
+}
+
+// S2d6 のように、ダイス式の前にSを付けるとシークレットダイスになる
+fn secret_roll(content: &str) -> Option<&str> {
+    let content = content.strip_prefix('S')?;
+    let is_roll = content.starts_with(|c: char| c.is_ascii_digit())
+        || content.starts_with("CC")
+        || content.starts_with('K')
+        || content.starts_with('D')
+        || content.starts_with('d');
+    Some(content).filter(|_| is_roll)
+}
+
+pub fn split_first_word(text: &str) -> (&str, &str) {
+    if let Some(idx) = text.find(char::is_whitespace) {
+        (&text[..idx], text[idx..].trim())
+    } else {
+        (text, "")
+    }
+}
@@ -0,0 +1,46 @@
+use crate::runtime::Environment;
+
+mod call_of_cthulhu;
+mod double_cross;
+mod sword_world;
+
+#[serenity::async_trait]
+pub trait GameSystem: Send + Sync {
+    // システムの識別名（BCDiceのゲームシステムIDに合わせる）
+    fn name(&self) -> &'static str;
+
+    // システムの正式名称。現在の言語で返す
+    fn title(&self) -> String;
+
+    fn help(&self) -> String;
+
+    async fn set_default(&self, env: &mut Environment);
+}
+
+const SYSTEMS: &[&dyn GameSystem] = &[
+    &call_of_cthulhu::CallOfCthulhu,
+    &sword_world::SwordWorld,
+    &double_cross::DoubleCross,
+];
+
+pub fn systems() -> &'static [&'static dyn GameSystem] {
+    SYSTEMS
+}
+
+pub fn find(name: &str) -> Option<&'static dyn GameSystem> {
+    SYSTEMS
+        .iter()
+        .find(|system| system.name().eq_ignore_ascii_case(name))
+        .copied()
+}
+
+// システムが選択されていない場合は、全てのシステムのコマンドを使えるようにする
+pub async fn set_default(env: &mut Environment, system: Option<&dyn GameSystem>) {
+    if let Some(system) = system {
+        system.set_default(env).await;
+    } else {
+        for system in SYSTEMS {
+            system.set_default(env).await;
+        }
+    }
+}
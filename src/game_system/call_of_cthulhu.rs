@@ -0,0 +1,262 @@
+use super::GameSystem;
+use crate::locale::Message;
+use crate::parser::ast;
+use crate::runtime::built_in_function::def_func;
+use crate::runtime::{Environment, Level, LogEntry, RollEvent, Value};
+use async_std::sync::Arc;
+use rand::Rng;
+use std::collections::HashMap;
+
+// ボーナス・ペナルティダイスの個数の上限
+const BONUS_DICE_LIMIT: i64 = 2;
+
+// 第7版の成功度
+fn judge(roll: i64, skill: i64) -> Level {
+    if roll == 1 {
+        Level::Critical
+    } else if roll == 100 || (skill < 50 && roll >= 96) {
+        Level::Fumble
+    } else if roll <= skill / 5 {
+        Level::Extreme
+    } else if roll <= skill / 2 {
+        Level::Hard
+    } else if roll <= skill {
+        Level::Regular
+    } else {
+        Level::Failure
+    }
+}
+
+pub struct CallOfCthulhu;
+
+#[serenity::async_trait]
+impl GameSystem for CallOfCthulhu {
+    fn name(&self) -> &'static str {
+        "Cthulhu7th"
+    }
+
+    fn title(&self) -> String {
+        Message::CthulhuTitle.text()
+    }
+
+    fn help(&self) -> String {
+        Message::CthulhuHelp.text()
+    }
+
+    async fn set_default(&self, env: &mut Environment) {
+        // cc
+        let help = Arc::new(String::from(
+            "\n\
+            cc : Integer -> Integer -> Record\n\
+            CC(ボーナス・ペナルティダイス)<=技能値\n\
+            \n\
+            ［説明］\n\
+            クトゥルフ神話TRPG 第7版の技能ロールを行います。\n\
+            第1引数が正の数ならボーナスダイス、負の数ならペナルティダイスの個数として扱います（最大2個）。\n\
+            結果は roll, target, level, success, dice を持つレコードになります。\n\
+            \n\
+            ［使用例］\n\
+            CC<=60 //技能値60で判定\n\
+            CC(1)<=60 //ボーナスダイス1個で判定\n\
+            CC(-2)<=60 //ペナルティダイス2個で判定",
+        ));
+        def_func!("cc" help in env with rng, log; n -> a -> {
+            if let (Some(n), Some(a)) = (n.as_integer(), a.as_integer()) {
+                if n.abs() > BONUS_DICE_LIMIT {
+                    return Arc::new(Value::Err(Message::BonusDiceLimit(BONUS_DICE_LIMIT).text()));
+                }
+                return skill_check(n, a, rng, log);
+            }
+            Arc::new(Value::None)
+        });
+
+        // ccb
+        let help = Arc::new(String::from(
+            "\n\
+            ccb : Integer -> Record\n\
+            CCB<=技能値\n\
+            \n\
+            ［説明］\n\
+            第6版形式の技能ロールを行います（BCDice互換モード用）。\n\
+            出目が1～5かつ成功なら決定的成功、96～100かつ失敗なら致命的失敗になります。\n\
+            結果は roll, target, level, success, dice を持つレコードになります。\n\
+            \n\
+            ［使用例］\n\
+            ccb 70 //技能値70で判定",
+        ));
+        def_func!("ccb" help in env with rng, log; a -> {
+            if let Some(a) = a.as_integer() {
+                return classic_skill_check(a, rng, log);
+            }
+            Arc::new(Value::None)
+        });
+    }
+}
+
+fn classic_skill_check(
+    skill: i64,
+    rng: &mut dyn rand::RngCore,
+    log: &mut Vec<LogEntry>,
+) -> Arc<Value> {
+    let roll: i64 = rng.gen_range(1, 101);
+    let level = match (roll <= skill, roll) {
+        (true, 1..=5) => Level::ClassicCritical,
+        (true, _) => Level::ClassicSuccess,
+        (false, 96..=100) => Level::ClassicFumble,
+        (false, _) => Level::Failure,
+    };
+
+    log_check(log, "ccb", vec![roll], roll, level);
+    check_result(roll, skill, level, vec![roll])
+}
+
+fn skill_check(
+    bonus: i64,
+    skill: i64,
+    rng: &mut dyn rand::RngCore,
+    log: &mut Vec<LogEntry>,
+) -> Arc<Value> {
+    let units: i64 = rng.gen_range(0, 10);
+    let dice: Vec<i64> = (0..=bonus.abs())
+        .map(|_| {
+            let tens: i64 = rng.gen_range(0, 10);
+            if tens == 0 && units == 0 {
+                100
+            } else {
+                tens * 10 + units
+            }
+        })
+        .collect();
+
+    let roll = if bonus >= 0 {
+        dice.iter().min()
+    } else {
+        dice.iter().max()
+    }
+    .copied()
+    .unwrap_or(100);
+
+    let level = judge(roll, skill);
+    log_check(log, "cc", dice.clone(), roll, level);
+    check_result(roll, skill, level, dice)
+}
+
+// 振ったダイスと、ボーナス・ペナルティダイスから選んだ出目、成功度を経過に残す
+fn log_check(log: &mut Vec<LogEntry>, operator: &str, dice: Vec<i64>, roll: i64, level: Level) {
+    let mut dropped = dice.clone();
+    if let Some(idx) = dropped.iter().position(|d| *d == roll) {
+        dropped.remove(idx);
+    }
+    let mut event = RollEvent::keep(operator, dice, vec![roll], dropped);
+    event.sides = Some(100);
+    event.level = Some(level);
+    log.push(LogEntry::Roll(event));
+}
+
+fn check_result(roll: i64, skill: i64, level: Level, dice: Vec<i64>) -> Arc<Value> {
+    let mut res = HashMap::new();
+    res.insert(
+        Arc::new(String::from("roll")),
+        Arc::new(Value::Integer(roll)),
+    );
+    res.insert(
+        Arc::new(String::from("target")),
+        Arc::new(Value::Integer(skill)),
+    );
+    res.insert(
+        Arc::new(String::from("level")),
+        Arc::new(Value::String(Arc::new(Message::Level(level).text()))),
+    );
+    res.insert(
+        Arc::new(String::from("success")),
+        Arc::new(Value::Boolean(level.is_success())),
+    );
+    res.insert(
+        Arc::new(String::from("dice")),
+        Arc::new(Value::Array(
+            dice.into_iter()
+                .map(|d| Arc::new(Value::Integer(d)))
+                .collect(),
+        )),
+    );
+
+    Arc::new(Value::Record(res))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn judge_boundaries() {
+        use Level::*;
+        let cases = [
+            (1, 0, Critical),
+            (1, 99, Critical),
+            (2, 10, Extreme),
+            (3, 10, Hard),
+            (12, 60, Extreme),
+            (13, 60, Hard),
+            (30, 60, Hard),
+            (31, 60, Regular),
+            (60, 60, Regular),
+            (61, 60, Failure),
+            (95, 49, Failure),
+            (96, 49, Fumble),
+            (96, 50, Failure),
+            (96, 99, Regular),
+            (99, 99, Regular),
+            (100, 99, Fumble),
+            (100, 200, Fumble),
+        ];
+        for (roll, skill, level) in cases {
+            assert_eq!(judge(roll, skill), level, "{} <= {}", roll, skill);
+        }
+    }
+
+    fn field<'a>(record: &'a Value, key: &str) -> &'a Value {
+        match record {
+            Value::Record(record) => record[&String::from(key)].as_ref(),
+            _ => panic!("{:?}", record),
+        }
+    }
+
+    fn integer(val: &Value) -> i64 {
+        match val {
+            Value::Integer(n) => *n,
+            _ => panic!("{:?}", val),
+        }
+    }
+
+    #[test]
+    fn bonus_and_penalty_dice() {
+        for bonus in -2..=2 {
+            for seed in 0..100 {
+                let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+                let res = skill_check(bonus, 50, &mut rng, &mut vec![]);
+                let roll = integer(field(&res, "roll"));
+                let dice: Vec<_> = match field(&res, "dice") {
+                    Value::Array(dice) => dice.iter().map(|d| integer(d)).collect(),
+                    val => panic!("{:?}", val),
+                };
+
+                // 一の位のダイスは全ての十の位のダイスで共有される
+                assert_eq!(dice.len() as i64, bonus.abs() + 1);
+                assert!(dice.iter().all(|d| (1..=100).contains(d)));
+                assert!(dice.iter().all(|d| d % 10 == dice[0] % 10), "{:?}", dice);
+
+                let expected = if bonus >= 0 {
+                    dice.iter().min()
+                } else {
+                    dice.iter().max()
+                };
+                assert_eq!(Some(&roll), expected, "{} {:?}", bonus, dice);
+                assert_eq!(
+                    format!("{:?}", field(&res, "level")),
+                    Message::Level(judge(roll, 50)).text()
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use super::GameSystem;
+use crate::locale::Message;
+use crate::parser::ast;
+use crate::runtime::built_in_function::def_func;
+use crate::runtime::{Environment, Level, LogEntry, RollEvent, Value};
+use async_std::sync::Arc;
+use rand::Rng;
+use std::collections::HashMap;
+
+const ROUND_LIMIT: usize = 100;
+const DICE_LIMIT: i64 = 200;
+
+struct Pool {
+    count: i64,
+    critical: i64,
+    modifier: i64,
+}
+
+impl Pool {
+    fn from_record(record: &HashMap<Arc<String>, Arc<Value>>) -> Option<Self> {
+        let get = |key: &str, default: i64| match record.get(&String::from(key)) {
+            Some(val) => val.as_integer(),
+            None => Some(default),
+        };
+        Some(Self {
+            count: get("count", 1)?,
+            critical: get("critical", 10)?,
+            modifier: get("modifier", 0)?,
+        })
+    }
+
+    fn roll(&self, rng: &mut dyn rand::RngCore, log: &mut Vec<LogEntry>) -> Arc<Value> {
+        if self.count < 1 || self.count > DICE_LIMIT {
+            return Arc::new(Value::Err(Message::DiceCountRange(DICE_LIMIT).text()));
+        }
+        if self.critical < 2 {
+            return Arc::new(Value::Err(Message::CriticalValueMin(2).text()));
+        }
+
+        let mut count = self.count;
+        let mut rounds: Vec<Vec<i64>> = vec![];
+
+        while count > 0 && rounds.len() < ROUND_LIMIT {
+            let round: Vec<i64> = (0..count).map(|_| rng.gen_range(1, 11)).collect();
+            count = round.iter().filter(|d| **d >= self.critical).count() as i64;
+            rounds.push(round);
+        }
+
+        let fumble = rounds[0].iter().all(|d| *d == 1);
+        let critical = rounds.len() as i64 - 1;
+        let total = if fumble {
+            0
+        } else {
+            let last = rounds[rounds.len() - 1].iter().max().copied().unwrap_or(0);
+            critical * 10 + last + self.modifier
+        };
+
+        // 回転ごとに振ったダイスを記録する
+        for round in &rounds {
+            let mut event = RollEvent::new("dx", 10, round.clone(), None);
+            if fumble {
+                event.level = Some(Level::Fumble);
+            }
+            log.push(LogEntry::Roll(event));
+        }
+
+        let mut res = HashMap::new();
+        res.insert(
+            Arc::new(String::from("total")),
+            Arc::new(Value::Integer(total)),
+        );
+        res.insert(
+            Arc::new(String::from("critical")),
+            Arc::new(Value::Integer(critical)),
+        );
+        res.insert(
+            Arc::new(String::from("fumble")),
+            Arc::new(Value::Boolean(fumble)),
+        );
+        res.insert(
+            Arc::new(String::from("dice")),
+            Arc::new(Value::Array(
+                rounds
+                    .into_iter()
+                    .map(|r| {
+                        Arc::new(Value::Array(
+                            r.into_iter().map(|d| Arc::new(Value::Integer(d))).collect(),
+                        ))
+                    })
+                    .collect(),
+            )),
+        );
+
+        Arc::new(Value::Record(res))
+    }
+}
+
+pub struct DoubleCross;
+
+#[serenity::async_trait]
+impl GameSystem for DoubleCross {
+    fn name(&self) -> &'static str {
+        "DoubleCross"
+    }
+
+    fn title(&self) -> String {
+        Message::DoubleCrossTitle.text()
+    }
+
+    fn help(&self) -> String {
+        Message::DoubleCrossHelp.text()
+    }
+
+    async fn set_default(&self, env: &mut Environment) {
+        // dx
+        let help = Arc::new(String::from(
+            "\n\
+            dx : Record -> Record\n\
+            個数DX[+修正値][@クリティカル値]\n\
+            \n\
+            ［説明］\n\
+            ダブルクロス The 3rd Edition の判定を行います。\n\
+            クリティカル値（省略時10）以上の出目の数だけダイスを振り直し、回転ごとに10を加えて最後の最大の出目を足します。\n\
+            最初の出目がすべて1ならファンブルです。\n\
+            引数のレコードには count, critical, modifier を指定します。\n\
+            \n\
+            ［使用例］\n\
+            10DX@8 //10個のダイスをクリティカル値8で振る\n\
+            5DX+3@7 //修正値+3、クリティカル値7",
+        ));
+        def_func!("dx" help in env with rng, log; a -> {
+            if let Some(pool) = a.as_record().and_then(Pool::from_record) {
+                return pool.roll(rng, log);
+            }
+            Arc::new(Value::None)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    struct Rolled {
+        total: i64,
+        critical: i64,
+        fumble: bool,
+        rounds: Vec<Vec<i64>>,
+        events: Vec<RollEvent>,
+    }
+
+    fn roll(count: i64, critical: i64, modifier: i64, seed: u64) -> Rolled {
+        let pool = Pool {
+            count,
+            critical,
+            modifier,
+        };
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        let mut log = vec![];
+        let res = pool.roll(&mut rng, &mut log);
+        let events = log
+            .into_iter()
+            .map(|entry| match entry {
+                LogEntry::Roll(event) => event,
+                entry => panic!("{:?}", entry),
+            })
+            .collect();
+        let record = match res.as_ref() {
+            Value::Record(record) => record,
+            val => panic!("{:?}", val),
+        };
+        let rounds = match record[&String::from("dice")].as_ref() {
+            Value::Array(rounds) => rounds
+                .iter()
+                .map(|round| match round.as_ref() {
+                    Value::Array(dice) => dice.iter().filter_map(|d| d.as_integer()).collect(),
+                    val => panic!("{:?}", val),
+                })
+                .collect(),
+            val => panic!("{:?}", val),
+        };
+        Rolled {
+            total: record[&String::from("total")].as_integer().unwrap(),
+            critical: record[&String::from("critical")].as_integer().unwrap(),
+            fumble: matches!(
+                record[&String::from("fumble")].as_ref(),
+                Value::Boolean(true)
+            ),
+            rounds,
+            events,
+        }
+    }
+
+    #[test]
+    fn reroll_critical_dice() {
+        let (mut fumbles, mut criticals) = (0, 0);
+        for seed in 0..1000 {
+            let rolled = roll(2, 8, 3, seed);
+
+            // クリティカル値以上の出目の数だけ次の回転で振る
+            assert_eq!(rolled.rounds[0].len(), 2);
+            for round in rolled.rounds.windows(2) {
+                let count = round[0].iter().filter(|d| **d >= 8).count();
+                assert_eq!(round[1].len(), count);
+            }
+            let last = rolled.rounds.last().unwrap();
+            assert!(last.iter().all(|d| *d < 8));
+            assert_eq!(rolled.critical as usize, rolled.rounds.len() - 1);
+
+            // 回転ごとに振ったダイスが経過に残る
+            let faces: Vec<_> = rolled.events.iter().map(|event| &event.faces).collect();
+            assert_eq!(faces, rolled.rounds.iter().collect::<Vec<_>>());
+            assert!(rolled.events.iter().all(|event| event.sides == Some(10)
+                && event.level == Some(Level::Fumble).filter(|_| rolled.fumble)));
+
+            if rolled.rounds[0].iter().all(|d| *d == 1) {
+                assert!(rolled.fumble);
+                assert_eq!(rolled.total, 0);
+                fumbles += 1;
+            } else {
+                assert!(!rolled.fumble);
+                let expected = rolled.critical * 10 + last.iter().max().unwrap() + 3;
+                assert_eq!(rolled.total, expected);
+            }
+            if rolled.critical >= 2 {
+                criticals += 1;
+            }
+        }
+        assert!(fumbles > 0 && criticals > 0);
+    }
+
+    #[test]
+    fn critical_value_limit() {
+        for seed in 0..100 {
+            let rolled = roll(3, 2, 0, seed);
+            assert!(rolled.rounds.iter().flatten().all(|d| (1..=10).contains(d)));
+            assert!(rolled.rounds.last().unwrap().iter().all(|d| *d == 1));
+        }
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let pools = [(3, 1), (0, 10), (DICE_LIMIT + 1, 10)];
+        for (count, critical) in &pools {
+            let pool = Pool {
+                count: *count,
+                critical: *critical,
+                modifier: 0,
+            };
+            assert!(pool.roll(&mut rng, &mut vec![]).is_err());
+        }
+    }
+}
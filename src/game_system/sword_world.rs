@@ -0,0 +1,409 @@
+use super::GameSystem;
+use crate::locale::Message;
+use crate::parser::ast;
+use crate::runtime::built_in_function::def_func;
+use crate::runtime::{Environment, Level, LogEntry, RollEvent, Value};
+use async_std::sync::Arc;
+use rand::Rng;
+use std::collections::HashMap;
+
+const ROLL_LIMIT: usize = 100;
+
+// 威力表。各行は出目3～12に対応する（出目2は自動失敗）
+const RATING_TABLE: [[i64; 10]; 101] = [
+    [0, 0, 0, 1, 2, 2, 3, 3, 4, 4],
+    [0, 0, 0, 1, 2, 3, 3, 3, 4, 4],
+    [0, 0, 0, 1, 2, 3, 4, 4, 4, 4],
+    [0, 0, 1, 1, 2, 3, 4, 4, 4, 5],
+    [0, 0, 1, 2, 2, 3, 4, 4, 5, 5],
+    [0, 1, 1, 2, 2, 3, 4, 5, 5, 5],
+    [0, 1, 1, 2, 3, 3, 4, 5, 5, 5],
+    [0, 1, 1, 2, 3, 4, 4, 5, 5, 6],
+    [0, 1, 2, 2, 3, 4, 4, 5, 6, 6],
+    [0, 1, 2, 3, 3, 4, 4, 5, 6, 7],
+    [1, 1, 2, 3, 3, 4, 5, 5, 6, 7],
+    [1, 2, 2, 3, 3, 4, 5, 6, 6, 7],
+    [1, 2, 2, 3, 4, 4, 5, 6, 6, 7],
+    [1, 2, 3, 3, 4, 4, 5, 6, 7, 7],
+    [1, 2, 3, 4, 4, 4, 5, 6, 7, 8],
+    [1, 2, 3, 4, 4, 5, 5, 6, 7, 8],
+    [1, 2, 3, 4, 4, 5, 6, 7, 7, 8],
+    [1, 2, 3, 4, 5, 5, 6, 7, 7, 8],
+    [1, 2, 3, 4, 5, 6, 6, 7, 7, 8],
+    [1, 2, 3, 4, 5, 6, 7, 7, 8, 9],
+    [1, 2, 3, 4, 5, 6, 7, 8, 9, 10],
+    [1, 2, 3, 4, 6, 6, 7, 8, 9, 10],
+    [1, 2, 3, 5, 6, 6, 7, 8, 9, 10],
+    [2, 2, 3, 5, 6, 7, 7, 8, 9, 10],
+    [2, 3, 4, 5, 6, 7, 7, 8, 9, 10],
+    [2, 3, 4, 5, 6, 7, 8, 8, 9, 10],
+    [2, 3, 4, 5, 6, 8, 8, 9, 9, 10],
+    [2, 3, 4, 6, 6, 8, 8, 9, 9, 10],
+    [2, 3, 4, 6, 6, 8, 9, 9, 10, 10],
+    [2, 3, 4, 6, 7, 8, 9, 9, 10, 10],
+    [2, 4, 4, 6, 7, 8, 9, 10, 10, 10],
+    [2, 4, 5, 6, 7, 8, 9, 10, 10, 11],
+    [3, 4, 5, 6, 7, 8, 10, 10, 10, 11],
+    [3, 4, 5, 6, 8, 8, 10, 10, 10, 11],
+    [3, 4, 5, 6, 8, 9, 10, 10, 11, 11],
+    [3, 4, 5, 7, 8, 9, 10, 10, 11, 12],
+    [3, 5, 5, 7, 8, 9, 10, 11, 11, 12],
+    [3, 5, 6, 7, 8, 9, 10, 11, 12, 12],
+    [3, 5, 6, 7, 8, 10, 10, 11, 12, 13],
+    [4, 5, 6, 7, 8, 10, 11, 11, 12, 13],
+    [4, 5, 6, 7, 9, 10, 11, 11, 12, 13],
+    [4, 6, 6, 7, 9, 10, 11, 12, 12, 13],
+    [4, 6, 7, 7, 9, 10, 11, 12, 13, 13],
+    [4, 6, 7, 8, 9, 10, 11, 12, 13, 14],
+    [4, 6, 7, 8, 10, 10, 11, 12, 13, 14],
+    [4, 6, 7, 9, 10, 10, 11, 12, 13, 14],
+    [4, 6, 7, 9, 10, 10, 12, 13, 13, 14],
+    [4, 6, 7, 9, 10, 11, 12, 13, 13, 15],
+    [4, 6, 7, 9, 10, 12, 12, 13, 13, 15],
+    [4, 6, 7, 10, 10, 12, 12, 13, 14, 15],
+    [4, 6, 8, 10, 10, 12, 12, 13, 15, 15],
+    [5, 7, 8, 10, 10, 12, 12, 13, 15, 15],
+    [5, 7, 8, 10, 11, 12, 12, 13, 15, 15],
+    [5, 7, 9, 10, 11, 12, 12, 14, 15, 15],
+    [5, 7, 9, 10, 11, 12, 13, 14, 15, 16],
+    [5, 7, 10, 10, 11, 12, 13, 14, 16, 16],
+    [5, 8, 10, 10, 11, 12, 13, 15, 16, 16],
+    [5, 8, 10, 11, 11, 12, 13, 15, 16, 17],
+    [5, 8, 10, 11, 12, 12, 13, 15, 16, 17],
+    [5, 9, 10, 11, 12, 12, 14, 15, 16, 17],
+    [5, 9, 10, 11, 12, 13, 14, 15, 16, 18],
+    [5, 9, 10, 11, 12, 13, 14, 16, 17, 18],
+    [5, 9, 10, 11, 13, 13, 14, 16, 17, 18],
+    [5, 9, 10, 11, 13, 13, 15, 17, 17, 18],
+    [5, 9, 10, 11, 13, 14, 15, 17, 17, 18],
+    [5, 9, 10, 12, 13, 14, 15, 17, 18, 18],
+    [5, 9, 10, 12, 13, 15, 15, 17, 18, 19],
+    [5, 9, 10, 12, 13, 15, 16, 17, 19, 19],
+    [5, 9, 10, 12, 14, 15, 16, 17, 19, 19],
+    [5, 9, 10, 12, 14, 16, 16, 17, 19, 19],
+    [5, 9, 10, 12, 14, 16, 17, 18, 19, 19],
+    [5, 9, 10, 13, 14, 16, 17, 18, 19, 20],
+    [5, 9, 10, 13, 15, 16, 17, 18, 19, 20],
+    [5, 9, 10, 13, 15, 16, 17, 19, 20, 21],
+    [6, 9, 10, 13, 15, 16, 18, 19, 20, 21],
+    [6, 9, 10, 13, 16, 16, 18, 19, 20, 21],
+    [6, 9, 10, 13, 16, 17, 18, 19, 20, 21],
+    [6, 9, 10, 13, 16, 17, 18, 20, 21, 22],
+    [6, 9, 10, 13, 16, 17, 19, 20, 22, 23],
+    [6, 9, 10, 13, 16, 18, 19, 20, 22, 23],
+    [6, 9, 10, 13, 16, 18, 20, 21, 22, 23],
+    [6, 9, 10, 13, 17, 18, 20, 21, 22, 23],
+    [6, 9, 10, 14, 17, 18, 20, 21, 22, 24],
+    [6, 9, 11, 14, 17, 18, 20, 21, 23, 24],
+    [6, 9, 11, 14, 17, 19, 20, 21, 23, 24],
+    [6, 9, 11, 14, 17, 19, 21, 22, 23, 24],
+    [7, 10, 11, 14, 17, 19, 21, 22, 23, 25],
+    [7, 10, 12, 14, 17, 19, 21, 22, 24, 25],
+    [7, 10, 12, 14, 18, 19, 21, 22, 24, 25],
+    [7, 10, 12, 15, 18, 19, 21, 22, 24, 26],
+    [7, 10, 12, 15, 18, 19, 21, 23, 25, 26],
+    [7, 11, 13, 15, 18, 19, 21, 23, 25, 26],
+    [7, 11, 13, 15, 18, 20, 21, 23, 25, 27],
+    [8, 11, 13, 15, 18, 20, 22, 23, 25, 27],
+    [8, 11, 13, 16, 18, 20, 22, 23, 25, 28],
+    [8, 11, 14, 16, 18, 20, 22, 23, 26, 28],
+    [8, 11, 14, 16, 19, 20, 22, 23, 26, 28],
+    [8, 12, 14, 16, 19, 20, 22, 24, 26, 28],
+    [8, 12, 15, 16, 19, 20, 22, 24, 27, 28],
+    [8, 12, 15, 17, 19, 20, 22, 24, 27, 29],
+    [8, 12, 15, 18, 19, 20, 22, 24, 27, 30],
+];
+
+struct Rating {
+    rate: i64,
+    critical: i64,
+    modifier: i64,
+    rate_up: i64,
+    crit_ray: i64,
+}
+
+impl Rating {
+    fn from_record(record: &HashMap<Arc<String>, Arc<Value>>) -> Option<Self> {
+        let get = |key: &str, default: i64| match record.get(&String::from(key)) {
+            Some(val) => val.as_integer(),
+            None => Some(default),
+        };
+        Some(Self {
+            rate: get("rate", 0)?,
+            critical: get("critical", 10)?,
+            modifier: get("modifier", 0)?,
+            rate_up: get("rate_up", 0)?,
+            crit_ray: get("crit_ray", 0)?,
+        })
+    }
+
+    fn roll(&self, rng: &mut dyn rand::RngCore, log: &mut Vec<LogEntry>) -> Arc<Value> {
+        if self.rate < 0 || self.rate > 100 {
+            return Arc::new(Value::Err(Message::PowerRange(100).text()));
+        }
+        if self.critical < 3 {
+            return Arc::new(Value::Err(Message::CriticalValueMin(3).text()));
+        }
+
+        let mut rate = self.rate;
+        let mut dice = vec![];
+        let mut values = vec![];
+        let mut success = true;
+        let mut critical = 0;
+
+        while dice.len() < ROLL_LIMIT {
+            let d: [i64; 2] = [rng.gen_range(1, 7), rng.gen_range(1, 7)];
+            let mut total = d[0] + d[1];
+            let mut event = RollEvent::new("k", 6, d.to_vec(), Some(total));
+
+            if total == 2 {
+                if dice.is_empty() {
+                    event.level = Some(Level::AutomaticFailure);
+                    success = false;
+                }
+                log.push(LogEntry::Roll(event));
+                dice.push(Arc::new(Value::Integer(total)));
+                break;
+            }
+            log.push(LogEntry::Roll(event));
+
+            if dice.is_empty() && self.crit_ray != 0 {
+                total = (total + self.crit_ray).clamp(3, 12);
+            }
+
+            let value = RATING_TABLE[rate as usize][(total - 3) as usize];
+            dice.push(Arc::new(Value::Integer(total)));
+            values.push(Arc::new(Value::Integer(value)));
+
+            if total < self.critical {
+                break;
+            }
+            critical += 1;
+            rate = (rate + self.rate_up).min(100);
+        }
+
+        let damage = if success {
+            values.iter().filter_map(|v| v.as_integer()).sum::<i64>() + self.modifier
+        } else {
+            0
+        };
+
+        let mut res = HashMap::new();
+        res.insert(
+            Arc::new(String::from("damage")),
+            Arc::new(Value::Integer(damage)),
+        );
+        res.insert(
+            Arc::new(String::from("critical")),
+            Arc::new(Value::Integer(critical)),
+        );
+        res.insert(
+            Arc::new(String::from("success")),
+            Arc::new(Value::Boolean(success)),
+        );
+        res.insert(Arc::new(String::from("dice")), Arc::new(Value::Array(dice)));
+        res.insert(
+            Arc::new(String::from("values")),
+            Arc::new(Value::Array(values)),
+        );
+
+        Arc::new(Value::Record(res))
+    }
+}
+
+pub struct SwordWorld;
+
+#[serenity::async_trait]
+impl GameSystem for SwordWorld {
+    fn name(&self) -> &'static str {
+        "SwordWorld2"
+    }
+
+    fn title(&self) -> String {
+        Message::SwordWorldTitle.text()
+    }
+
+    fn help(&self) -> String {
+        Message::SwordWorldHelp.text()
+    }
+
+    async fn set_default(&self, env: &mut Environment) {
+        // k
+        let help = Arc::new(String::from(
+            "\n\
+            k : Record -> Record\n\
+            K威力[+修正値][@クリティカル値][r首切り上昇値][$+クリティカルレイ]\n\
+            \n\
+            ［説明］\n\
+            ソード・ワールド2.x の威力表を振ります。\n\
+            クリティカル値（省略時10）以上の出目が出る限り振り足し、出目2（1ゾロ）は自動失敗になります。\n\
+            r は首切り刀のように回転ごとに威力を上昇させ、$ はクリティカルレイとして最初の出目を修正します。\n\
+            引数のレコードには rate, critical, modifier, rate_up, crit_ray を指定します。\n\
+            \n\
+            ［使用例］\n\
+            K20+5@10 //威力20、追加ダメージ5、クリティカル値10\n\
+            K30+8@9$+1 //クリティカルレイ+1\n\
+            K20+5r5 //首切り刀（回転ごとに威力+5）",
+        ));
+        def_func!("k" help in env with rng, log; a -> {
+            if let Some(rating) = a.as_record().and_then(Rating::from_record) {
+                return rating.roll(rng, log);
+            }
+            Arc::new(Value::None)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    struct Rolled {
+        damage: i64,
+        critical: i64,
+        success: bool,
+        dice: Vec<i64>,
+        values: Vec<i64>,
+        events: Vec<RollEvent>,
+    }
+
+    fn roll(rating: &Rating, seed: u64) -> Rolled {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        let mut log = vec![];
+        let res = rating.roll(&mut rng, &mut log);
+        let events = log
+            .into_iter()
+            .map(|entry| match entry {
+                LogEntry::Roll(event) => event,
+                entry => panic!("{:?}", entry),
+            })
+            .collect();
+        let record = match res.as_ref() {
+            Value::Record(record) => record,
+            val => panic!("{:?}", val),
+        };
+        let integers = |key: &str| match record[&String::from(key)].as_ref() {
+            Value::Array(vals) => vals.iter().filter_map(|v| v.as_integer()).collect(),
+            val => panic!("{:?}", val),
+        };
+        Rolled {
+            damage: record[&String::from("damage")].as_integer().unwrap(),
+            critical: record[&String::from("critical")].as_integer().unwrap(),
+            success: matches!(
+                record[&String::from("success")].as_ref(),
+                Value::Boolean(true)
+            ),
+            dice: integers("dice"),
+            values: integers("values"),
+            events,
+        }
+    }
+
+    fn rating(rate: i64, critical: i64, modifier: i64, rate_up: i64, crit_ray: i64) -> Rating {
+        Rating {
+            rate,
+            critical,
+            modifier,
+            rate_up,
+            crit_ray,
+        }
+    }
+
+    #[test]
+    fn follow_rating_table() {
+        let (mut fumbles, mut chains, mut stopped) = (0, 0, 0);
+        for seed in 0..1000 {
+            let rating = rating(20, 10, 5, 5, 0);
+            let rolled = roll(&rating, seed);
+
+            // 最初の1ゾロは自動失敗
+            // 振った2d6ごとに経過が残る
+            let totals: Vec<_> = rolled.events.iter().map(|event| event.total).collect();
+            let dice: Vec<_> = rolled.dice.iter().map(|total| Some(*total)).collect();
+            assert_eq!(totals, dice);
+            assert!(rolled.events.iter().all(|event| event.sides == Some(6)));
+
+            if rolled.dice[0] == 2 {
+                assert!(!rolled.success);
+                assert_eq!(rolled.events[0].level, Some(Level::AutomaticFailure));
+                assert_eq!((rolled.damage, rolled.critical), (0, 0));
+                assert!(rolled.values.is_empty());
+                fumbles += 1;
+                continue;
+            }
+
+            // クリティカルした回数だけ振り足し、回転ごとに威力が上がる
+            assert!(rolled.success);
+            assert_eq!(rolled.critical as usize, rolled.dice.len() - 1);
+            for (i, total) in rolled.dice.iter().enumerate() {
+                if i + 1 < rolled.dice.len() {
+                    assert!(*total >= rating.critical);
+                } else if *total == 2 {
+                    stopped += 1;
+                    continue;
+                } else {
+                    assert!(*total < rating.critical);
+                }
+                let rate = (rating.rate + rating.rate_up * i as i64).min(100);
+                assert_eq!(
+                    rolled.values[i],
+                    RATING_TABLE[rate as usize][(*total - 3) as usize]
+                );
+            }
+            if rolled.critical >= 2 {
+                chains += 1;
+            }
+            assert_eq!(
+                rolled.damage,
+                rolled.values.iter().sum::<i64>() + rating.modifier
+            );
+        }
+        assert!(fumbles > 0 && chains > 0 && stopped > 0);
+    }
+
+    #[test]
+    fn crit_ray_moves_first_roll() {
+        for seed in 0..200 {
+            let first = roll(&rating(10, 13, 0, 0, 0), seed).dice[0];
+            for crit_ray in &[-3, 1, 5] {
+                let rolled = roll(&rating(10, 13, 0, 0, *crit_ray), seed);
+                if first == 2 {
+                    assert_eq!(rolled.dice[0], 2);
+                    assert!(!rolled.success);
+                } else {
+                    assert_eq!(rolled.dice[0], (first + crit_ray).clamp(3, 12));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rating_table_edges() {
+        for seed in 0..200 {
+            let rolled = roll(&rating(0, 13, 0, 0, 0), seed);
+            if rolled.success {
+                assert_eq!(
+                    rolled.values,
+                    [RATING_TABLE[0][(rolled.dice[0] - 3) as usize]]
+                );
+            }
+            // 威力は100を超えて上がらない
+            let rolled = roll(&rating(100, 3, 0, 50, 0), seed);
+            for (value, total) in rolled.values.iter().zip(&rolled.dice) {
+                assert_eq!(*value, RATING_TABLE[100][(total - 3) as usize]);
+            }
+        }
+        assert_eq!(RATING_TABLE[0][0], 0);
+        assert_eq!(RATING_TABLE[100][9], 30);
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        for rating in &[
+            rating(-1, 10, 0, 0, 0),
+            rating(101, 10, 0, 0, 0),
+            rating(20, 2, 0, 0, 0),
+        ] {
+            assert!(rating.roll(&mut rng, &mut vec![]).is_err());
+        }
+    }
+}
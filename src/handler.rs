@@ -0,0 +1,1832 @@
+use crate::analysis;
+use crate::command::{self, Command};
+use crate::game_system::{self, GameSystem};
+use crate::help;
+use crate::history::{self, History};
+use crate::importer;
+use crate::initiative::Encounter;
+use crate::interaction::{self, Interaction};
+use crate::locale::{self, Language, Message, Setting};
+use crate::parser::{self, ast};
+use crate::render::{self, Rolled};
+use crate::roll;
+use crate::runtime;
+use crate::sheet::{self, Sheet};
+use crate::stats;
+use crate::store::{self, Store};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+// Discordのメッセージの文字数制限
+const MESSAGE_LIMIT: usize = 2000;
+// これより長い結果は分割せず、テキストファイルとして添付する
+const SPLIT_LIMIT: usize = MESSAGE_LIMIT * 3;
+// traceで表示する評価の深さと行数の上限
+const TRACE_DEPTH: usize = 8;
+const TRACE_LINES: usize = 200;
+// historyで表示する件数
+const HISTORY_DEFAULT: usize = 10;
+const HISTORY_LIMIT: usize = 50;
+// 1人が保存できるマクロの数
+const MACRO_LIMIT: usize = 100;
+// 読み込むキャラクターシートのファイルの大きさの上限
+const IMPORT_SIZE_LIMIT: u64 = 1024 * 1024;
+
+const USAGE: &str = "\
+使い方：
+@ボット <式> または /roll expr:<式> : ダイスを振ります（例：2d6+3, 10B6>=5, CC<=60）
+@ボット help [関数名|演算子] または /help name:<関数名> : 関数の一覧、関数や演算子の説明を表示します（help commands でこの一覧）
+@ボット system <システム名|reset> または /system : このチャンネルのゲームシステムを設定します
+@ボット system default <システム名|reset> : システムを設定していないチャンネルで使う、このサーバーの既定のゲームシステムを設定します
+@ボット syntax <Native|BCDice> または /syntax : このチャンネルの構文を設定します
+@ボット prefix <接頭辞|reset> : このサーバーでメンションの代わりに使う接頭辞を設定します
+@ボット autoroll <on|off> : ダイス式で始まる発言を自動的に振るかどうかを設定します
+@ボット safemode <on|off> : 正しく解釈できない発言を無視するかどうかを設定します
+@ボット S<式> または /roll hidden:True : シークレットダイスを振り、結果を自分とGMにDMで送ります
+@ボット reveal [番号] : シークレットダイスの結果を公開します
+@ボット gmrole <@ロール|reset> : シークレットダイスの結果を受け取るGMのロールを設定します
+@ボット output <text|embed> : このサーバーでのダイスの結果の表示形式を設定します
+@ボット language <ja|en> : このサーバーでのダイスの結果や返答の言語を設定します
+@ボット save <名前> := <式> : 自分用のマクロを保存します（例：save atk := 2d6+4）
+@ボット macro [list|delete <名前>|export] または /macro : 保存したマクロを表示・削除・書き出しします
+@ボット sheet new <レコード> : このチャンネルで使うキャラクターシートを登録します（例：sheet new {str: 14, skill_spot: 60}）
+@ボット sheet [set|add|sub] <項目> <式> : キャラクターシートの項目を変更します（例：sheet sub hp 1d6）
+@ボット sheet import : 添付したキャラエノ、いあきゃら（ココフォリア形式）、ゆとシートⅡのJSONをキャラクターシートとして登録します
+@ボット sheet [delete] : キャラクターシートを表示・削除します。ダイスでは me.skill_spot のように参照できます
+@ボット init [start|end] : このチャンネルのイニシアチブ表を表示・開始・終了します
+@ボット init add <名前> <式> : イニシアチブを振って参加者を追加します（例：init add ラルフ 2d6+3）
+@ボット init [next|remove <名前>] : 手番を次に回す・参加者を外します
+@ボット history [件数] : このチャンネルで最近振ったダイスを表示します
+@ボット export [md|csv|html] [開始日] [終了日] : このチャンネルのダイスの記録をファイルで書き出します（例：export md 2021-01-01）
+@ボット stats [@ユーザー|channel] [png] [開始日] [終了日] : このチャンネルのダイスの出目の統計と偏りの検定結果を表示します。pngを付けると出目の分布を画像でも表示します
+@ボット prob [png] <式> : ダイス式の結果の確率分布、平均、標準偏差を表示します（例：prob 3d6+2>=12）。pngを付けると分布を画像でも表示します
+@ボット explain <式> : 式がどのように解釈されるかを、全ての演算を括弧で囲んで表示します（例：explain 2d6.s）
+@ボット trace <式> : ダイスを振り、変数の参照や関数の呼び出し、@の振り直しを含む評価の過程を木で表示します（例：trace 1d10 @>= 10）";
+
+const USAGE_EN: &str = "\
+Usage:
+@bot <expr> or /roll expr:<expr> : Rolls dice (e.g. 2d6+3, 10B6>=5, CC<=60)
+@bot help [function|operator] or /help name:<function> : Shows the list of functions or the description of a function or an operator (help commands shows this list)
+@bot system <system|reset> or /system : Sets the game system of this channel
+@bot system default <system|reset> : Sets the default game system of this server, used in channels without a system
+@bot syntax <Native|BCDice> or /syntax : Sets the syntax of this channel
+@bot prefix <prefix|reset> : Sets the prefix used instead of a mention in this server
+@bot autoroll <on|off> : Sets whether to roll messages starting with dice expressions
+@bot safemode <on|off> : Sets whether to ignore messages that cannot be parsed
+@bot S<expr> or /roll hidden:True : Rolls secret dice and sends the result to you and the GM by DM
+@bot reveal [number] : Reveals the result of secret dice
+@bot gmrole <@role|reset> : Sets the GM role that receives the results of secret dice
+@bot output <text|embed> : Sets how dice results are shown in this server
+@bot language <ja|en> : Sets the language of replies in this server
+@bot save <name> := <expr> : Saves a macro for yourself (e.g. save atk := 2d6+4)
+@bot macro [list|delete <name>|export] or /macro : Lists, deletes or exports your macros
+@bot sheet new <record> : Registers your character sheet in this channel (e.g. sheet new {str: 14, skill_spot: 60})
+@bot sheet [set|add|sub] <field> <expr> : Changes a field of your character sheet (e.g. sheet sub hp 1d6)
+@bot sheet import : Registers the attached Charaeno, Iachara (CCFOLIA format) or Yutsheet2 JSON as your character sheet
+@bot sheet [delete] : Shows or deletes your character sheet. Fields can be used in dice as me.skill_spot
+@bot init [start|end] : Shows, starts or ends the initiative table of this channel
+@bot init add <name> <expr> : Rolls initiative and adds a combatant (e.g. init add Ralph 2d6+3)
+@bot init [next|remove <name>] : Passes the turn or removes a combatant
+@bot history [count] : Shows recent rolls in this channel
+@bot export [md|csv|html] [since] [until] : Exports the dice log of this channel as a file (e.g. export md 2021-01-01)
+@bot stats [@user|channel] [png] [since] [until] : Shows the stats of dice faces in this channel and a test of bias. png also attaches the distribution as images
+@bot prob [png] <expr> : Shows the distribution, mean and standard deviation of an expression (e.g. prob 3d6+2>=12). png also attaches the distribution as an image
+@bot explain <expr> : Shows how an expression is parsed, with every operation in parentheses (e.g. explain 2d6.s)
+@bot trace <expr> : Rolls dice and shows the evaluation as a tree, including variables, function calls and rerolls by @ (e.g. trace 1d10 @>= 10)";
+
+fn usage() -> &'static str {
+    match locale::current() {
+        Language::Ja => USAGE,
+        Language::En => USAGE_EN,
+    }
+}
+
+// コマンドの呼び出し元
+pub struct Caller {
+    pub guild_id: Option<GuildId>,
+    pub channel_id: ChannelId,
+    pub user_id: UserId,
+    pub user_name: String,
+    pub roles: Vec<RoleId>,
+    // BOT以外にメンションされたユーザー
+    pub mentions: Vec<UserId>,
+    // 添付ファイルのURL
+    pub attachments: Vec<String>,
+}
+
+pub struct Reply {
+    content: String,
+    // 設定されていれば、contentの代わりに埋め込みで表示する
+    embed: Option<serde_json::Value>,
+    // 呼び出し元とGMにだけDMで送る内容
+    secret: Option<String>,
+    // 添付するファイルの名前と内容
+    files: Vec<(String, Vec<u8>)>,
+}
+
+impl From<String> for Reply {
+    fn from(content: String) -> Self {
+        Self {
+            content,
+            embed: None,
+            secret: None,
+            files: vec![],
+        }
+    }
+}
+
+// サーバーID → ユーザーID → マクロ名 → 定義。DMでのマクロはサーバーIDを0として保存する
+type Macros = HashMap<u64, HashMap<u64, BTreeMap<String, String>>>;
+// チャンネルID → ユーザーID → キャラクターシート
+type Sheets = HashMap<u64, HashMap<u64, Sheet>>;
+// チャンネルID → 戦闘の状態
+type Encounters = HashMap<u64, Encounter>;
+// チャンネルID → チャンネルの設定
+type Channels = HashMap<u64, ChannelSettings>;
+// サーバーID → サーバーの設定
+type Guilds = HashMap<u64, GuildSettings>;
+
+struct Secret {
+    user_id: UserId,
+    content: String,
+    revealed: bool,
+    // 記録に残したロールのシード。評価に失敗した場合は記録がない
+    seed: Option<u64>,
+}
+
+// システムは名前で保存し、未設定ならサーバーの既定のシステムを使う
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct ChannelSettings {
+    system: Option<String>,
+    syntax: parser::Syntax,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct GuildSettings {
+    system: Option<String>,
+    prefix: Option<String>,
+    auto_roll: bool,
+    safe_mode: bool,
+    gm_role: Option<RoleId>,
+    style: render::Style,
+    language: Language,
+}
+
+impl Default for GuildSettings {
+    fn default() -> Self {
+        Self {
+            system: None,
+            prefix: None,
+            auto_roll: false,
+            safe_mode: true,
+            gm_role: None,
+            style: render::Style::Text,
+            language: Language::Ja,
+        }
+    }
+}
+
+pub struct Handler {
+    mention_pattern: Regex,
+    comment_pattern: Regex,
+    dice_pattern: Regex,
+    // 接頭辞を設定していないサーバーやDMで使う接頭辞
+    default_prefix: Option<String>,
+    secrets: RwLock<HashMap<ChannelId, Vec<Secret>>>,
+    channels: Store<Channels>,
+    guilds: Store<Guilds>,
+    macros: Store<Macros>,
+    sheets: Store<Sheets>,
+    encounters: Store<Encounters>,
+    history: History,
+    http_client: reqwest::Client,
+}
+
+impl Default for Handler {
+    fn default() -> Self {
+        Self::open(store::data_dir())
+    }
+}
+
+impl Handler {
+    // 設定や記録をdirに保存するハンドラー
+    fn open(dir: impl AsRef<Path>) -> Self {
+        let dir = dir.as_ref();
+        Self {
+            mention_pattern: Regex::new(r"<@!?\d+>").unwrap(),
+            comment_pattern: Regex::new(r"//.*$").unwrap(),
+            dice_pattern: Regex::new(
+                r"^\s*S?(\d*[dD]\d+|\d+[bB]\d+|CCB?(\(-?\d+\))?<=|K\d+|\d+DX)",
+            )
+            .unwrap(),
+            default_prefix: std::env::var("DICEBOT_PREFIX")
+                .ok()
+                .filter(|prefix| !prefix.is_empty()),
+            secrets: RwLock::new(HashMap::new()),
+            channels: Store::open(dir.join("channels.json")),
+            guilds: Store::open(dir.join("guilds.json")),
+            macros: Store::open(dir.join("macros.json")),
+            sheets: Store::open(dir.join("sheets.json")),
+            encounters: Store::open(dir.join("encounters.json")),
+            history: History::open(dir.join("history.jsonl")),
+            http_client: reqwest::Client::new(),
+        }
+    }
+
+    // 返答はサーバーで設定した言語で書く
+    async fn execute(&self, caller: &Caller, command: Command<'_>) -> Reply {
+        let language = self.language(caller).await;
+        locale::scope(language, self.dispatch(caller, command)).await
+    }
+
+    async fn dispatch(&self, caller: &Caller, command: Command<'_>) -> Reply {
+        match command {
+            Command::Help(name) => self.help(caller, name).await.into(),
+            Command::System(name) => self.system(caller, name).await.into(),
+            Command::Syntax(name) => self.syntax(caller, name).await.into(),
+            Command::Prefix(prefix) => self.prefix(caller, prefix).await.into(),
+            Command::AutoRoll(flag) => self.auto_roll(caller, flag).await.into(),
+            Command::SafeMode(flag) => self.safe_mode(caller, flag).await.into(),
+            Command::GmRole(role) => self.gm_role(caller, role).await.into(),
+            Command::Reveal(id) => self.reveal(caller, id).await.into(),
+            Command::Output(style) => self.output(caller, style).await.into(),
+            Command::Language(name) => self.select_language(caller, name).await.into(),
+            Command::SaveMacro(definition) => self.save_macro(caller, definition).await.into(),
+            Command::ListMacros => self.list_macros(caller).await.into(),
+            Command::DeleteMacro(name) => self.delete_macro(caller, name).await.into(),
+            Command::ExportMacros => self.export_macros(caller).await.into(),
+            Command::Sheet(args) => self.sheet(caller, args).await.into(),
+            Command::Initiative(args) => self.initiative(caller, args).await.into(),
+            Command::History(count) => self.recent_history(caller, count).await.into(),
+            Command::Export(args) => self.export(caller, args).await,
+            Command::Stats(args) => self.stats(caller, args).await,
+            Command::Prob(content) => self.prob(caller, content).await,
+            Command::Explain(content) => self.explain(caller, content).await.into(),
+            Command::Trace(content) => self.trace(caller, content).await,
+            Command::Roll(content) => self.roll(caller, content).await,
+            Command::SecretRoll(content) => self.secret_roll(caller, content).await,
+        }
+    }
+
+    // チャンネルのシステムが未設定なら、サーバーの既定のシステムを使う
+    async fn settings(&self, caller: &Caller) -> (Option<&'static dyn GameSystem>, parser::Syntax) {
+        let (system, syntax) = self
+            .channels
+            .read()
+            .await
+            .get(&caller.channel_id.0)
+            .map(|settings| (settings.system.clone(), settings.syntax))
+            .unwrap_or_default();
+        let system = match system {
+            Some(system) => Some(system),
+            None => self.default_system(caller).await,
+        };
+        (system.as_deref().and_then(game_system::find), syntax)
+    }
+
+    async fn default_system(&self, caller: &Caller) -> Option<String> {
+        self.guilds
+            .read()
+            .await
+            .get(&caller.guild_id?.0)
+            .and_then(|settings| settings.system.clone())
+    }
+
+    async fn roll(&self, caller: &Caller, content: &str) -> Reply {
+        let style = self.style(caller).await;
+
+        match self.evaluate(caller, content, false, false).await {
+            Ok(rolled) => Reply {
+                content: rolled.text(),
+                embed: Some(rolled.embed()).filter(|_| style == render::Style::Embed),
+                secret: None,
+                files: vec![],
+            },
+            Err(err) => err.into(),
+        }
+    }
+
+    // 呼び出し元の設定、マクロ、キャラクターシートを使ってダイス式を評価し、記録に残す
+    async fn evaluate(
+        &self,
+        caller: &Caller,
+        content: &str,
+        secret: bool,
+        traced: bool,
+    ) -> Result<Rolled, String> {
+        let (system, syntax) = self.settings(caller).await;
+        let macros = self.user_macros(caller).await;
+        let me = self.user_sheet(caller).await;
+        let language = self.language(caller).await;
+        let seed = rand::random();
+
+        let rolled = locale::using(language, || {
+            if traced {
+                roll::trace(&syntax, system, &macros, me.as_ref(), seed, content)
+            } else {
+                roll::evaluate(&syntax, system, &macros, me.as_ref(), seed, content)
+            }
+        })?;
+        self.history
+            .append(history::Entry {
+                timestamp: chrono::Utc::now(),
+                guild_id: caller.guild_id.map(|guild_id| guild_id.0),
+                channel_id: caller.channel_id.0,
+                user_id: caller.user_id.0,
+                user_name: caller.user_name.clone(),
+                input: String::from(content),
+                syntax: String::from(syntax.name()),
+                system: system.map(|system| String::from(system.name())),
+                macros,
+                me,
+                log: rolled.log.clone(),
+                value: format!("{}", rolled.value),
+                outcome: rolled.outcome(),
+                seed: rolled.seed,
+                language,
+                secret,
+            })
+            .await;
+        Ok(rolled)
+    }
+
+    async fn recent_history(&self, caller: &Caller, count: Option<&str>) -> String {
+        let count = match count.map(str::parse::<usize>) {
+            Some(Ok(count)) => count.min(HISTORY_LIMIT),
+            Some(Err(_)) => return Message::CountNotNumber.text(),
+            None => HISTORY_DEFAULT,
+        };
+        let entries = self.history.recent(caller.channel_id.0, count);
+        if entries.is_empty() {
+            return Message::NoHistory.text();
+        }
+
+        let mut res = Message::RecentRolls.text();
+        for entry in &entries {
+            res += format!(
+                "\n{} {}{} → {}",
+                entry.local_time(),
+                Message::Heading(&entry.user_name).text(),
+                entry.shown_input(),
+                entry.result()
+            )
+            .as_str();
+        }
+        res
+    }
+
+    async fn stats(&self, caller: &Caller, args: Option<&str>) -> Reply {
+        // メンションは呼び出し元でcallerに移されているので、残りは対象の指定と期間
+        let mut user_id = Some(caller.mentions.first().unwrap_or(&caller.user_id).0);
+        let mut png = false;
+        let mut dates = vec![];
+        for arg in args.unwrap_or_default().split_whitespace() {
+            match arg {
+                "channel" => user_id = None,
+                "png" => png = true,
+                date => dates.push(date),
+            }
+        }
+        if dates.len() > 2 {
+            return Message::StatsPeriod.text().into();
+        }
+        let range = match history::Range::parse(dates.first().copied(), dates.get(1).copied()) {
+            Ok(range) => range,
+            Err(err) => return err.into(),
+        };
+
+        let entries = self.history.entries(caller.channel_id.0, |entry| {
+            !entry.secret
+                && user_id.is_none_or(|user_id| entry.user_id == user_id)
+                && range.contains(entry)
+        });
+        let last = match entries.last() {
+            Some(last) => last,
+            None => return Message::NothingToSummarize.text().into(),
+        };
+
+        let mut title = Message::StatsTitle(user_id.map(|_| last.user_name.as_str())).text();
+        if !dates.is_empty() {
+            title += &Message::Period(dates[0], dates.get(1).unwrap_or(&"")).text();
+        }
+        let report = stats::Report::new(title, &entries);
+        let style = self.style(caller).await;
+
+        let files = if png {
+            report
+                .charts()
+                .into_iter()
+                .map(|(sides, chart)| (format!("d{}.png", sides), chart.png()))
+                .collect()
+        } else {
+            vec![]
+        };
+
+        Reply {
+            content: report.text(),
+            embed: Some(report.embed()).filter(|_| style == render::Style::Embed),
+            secret: None,
+            files,
+        }
+    }
+
+    // ダイス式の結果の分布を求める。振ったダイスとしては記録しない
+    async fn prob(&self, caller: &Caller, content: Option<&str>) -> Reply {
+        let (png, content) = match content.map(command::split_first_word) {
+            Some(("png", content)) => (true, content),
+            Some(_) => (false, content.unwrap_or_default()),
+            None => (false, ""),
+        };
+        if content.is_empty() {
+            return Message::ExpressionRequired("prob 3d6+2>=12").text().into();
+        }
+        let (system, syntax) = self.settings(caller).await;
+        let macros = self.user_macros(caller).await;
+        let me = self.user_sheet(caller).await;
+
+        let language = self.language(caller).await;
+
+        match locale::using(language, || {
+            analysis::analyze(&syntax, system, &macros, me.as_ref(), content)
+        }) {
+            Ok(analysis) => {
+                let style = self.style(caller).await;
+                Reply {
+                    content: analysis.text(),
+                    embed: Some(analysis.embed()).filter(|_| style == render::Style::Embed),
+                    secret: None,
+                    files: if png {
+                        vec![(String::from("distribution.png"), analysis.chart().png())]
+                    } else {
+                        vec![]
+                    },
+                }
+            }
+            Err(err) => err.into(),
+        }
+    }
+
+    // 式がどのように解釈されたかを、全ての演算を括弧で囲んで表示する
+    async fn explain(&self, caller: &Caller, content: Option<&str>) -> String {
+        let content = match content {
+            Some(content) => content,
+            None => return Message::ExpressionRequired("explain 2d6.s").text(),
+        };
+        let (_, syntax) = self.settings(caller).await;
+        match parser::parse(&syntax, content) {
+            Ok(proc) => format!(
+                "{}\n{}",
+                content,
+                Message::Explained(&proc.to_string()).text()
+            ),
+            Err(err) => format!("{:?}", err),
+        }
+    }
+
+    // ダイスを振り、部分式ごとの評価の過程を木にして結果に添える
+    async fn trace(&self, caller: &Caller, content: Option<&str>) -> Reply {
+        let content = match content {
+            Some(content) => content,
+            None => {
+                return Message::ExpressionRequired("trace 1d10 @>= 10")
+                    .text()
+                    .into()
+            }
+        };
+        let rolled = match self.evaluate(caller, content, false, true).await {
+            Ok(rolled) => rolled,
+            Err(err) => return err.into(),
+        };
+        let tree = rolled
+            .trace
+            .as_ref()
+            .map(|trace| trace.render(TRACE_DEPTH, TRACE_LINES))
+            .unwrap_or_default();
+        let content = format!("{}\n```\n{}\n```", rolled.text(), tree);
+        // 1通に収まらない場合は、評価の過程をファイルで添付する
+        if content.chars().count() > MESSAGE_LIMIT {
+            Reply {
+                content: rolled.text(),
+                embed: None,
+                secret: None,
+                files: vec![(String::from("trace.txt"), tree.into_bytes())],
+            }
+        } else {
+            content.into()
+        }
+    }
+
+    async fn export(&self, caller: &Caller, args: Option<&str>) -> Reply {
+        let mut args = args.unwrap_or_default().split_whitespace();
+        let format = match args.next() {
+            Some(name) => match history::Format::find(name) {
+                Some(format) => format,
+                None => return Message::UnknownFormat(name).text().into(),
+            },
+            None => history::Format::Markdown,
+        };
+        let range = match history::Range::parse(args.next(), args.next()) {
+            Ok(range) => range,
+            Err(err) => return err.into(),
+        };
+
+        let entries = self
+            .history
+            .entries(caller.channel_id.0, |entry| range.contains(entry));
+        if entries.is_empty() {
+            return Message::NothingToExport.text().into();
+        }
+
+        Reply {
+            content: Message::Exported(entries.len()).text(),
+            embed: None,
+            secret: None,
+            files: vec![(
+                format!("dice_log.{}", format.extension()),
+                format.export(&entries).into_bytes(),
+            )],
+        }
+    }
+
+    fn macro_key(caller: &Caller) -> (u64, u64) {
+        (
+            caller.guild_id.map_or(0, |guild_id| guild_id.0),
+            caller.user_id.0,
+        )
+    }
+
+    async fn user_macros(&self, caller: &Caller) -> Vec<String> {
+        let (guild_id, user_id) = Self::macro_key(caller);
+        self.macros
+            .read()
+            .await
+            .get(&guild_id)
+            .and_then(|users| users.get(&user_id))
+            .map(|macros| macros.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    async fn save_macro(&self, caller: &Caller, definition: Option<&str>) -> String {
+        let definition = match definition {
+            Some(definition) => definition.trim(),
+            None => return Message::MacroRequired.text(),
+        };
+        let name = match parser::parse(&parser::Syntax::Native, definition) {
+            Ok(proc) => match proc.as_slice() {
+                [expr] => match expr.as_ref() {
+                    ast::Expr0::Def { ident, .. } => ident.name(),
+                    _ => return Message::MacroForm.text(),
+                },
+                _ => return Message::OneMacro.text(),
+            },
+            Err(err) => return format!("{:?}", err),
+        };
+
+        let (guild_id, user_id) = Self::macro_key(caller);
+        self.macros
+            .update(|macros| {
+                let macros = macros
+                    .entry(guild_id)
+                    .or_default()
+                    .entry(user_id)
+                    .or_default();
+                if macros.len() >= MACRO_LIMIT && !macros.contains_key(name.as_str()) {
+                    return Message::MacroLimit(MACRO_LIMIT).text();
+                }
+                macros.insert(name.to_string(), String::from(definition));
+                Message::MacroSaved(&name).text()
+            })
+            .await
+    }
+
+    async fn list_macros(&self, caller: &Caller) -> String {
+        let macros = self.user_macros(caller).await;
+        if macros.is_empty() {
+            return Message::NoMacros.text();
+        }
+        let mut res = Message::MacroList.text();
+        for definition in macros {
+            res += format!("\n`{}`", definition).as_str();
+        }
+        res
+    }
+
+    async fn delete_macro(&self, caller: &Caller, name: Option<&str>) -> String {
+        let name = match name {
+            Some(name) => name,
+            None => return Message::MacroNameRequired.text(),
+        };
+        let (guild_id, user_id) = Self::macro_key(caller);
+        self.macros
+            .update(|macros| {
+                let removed = macros
+                    .get_mut(&guild_id)
+                    .and_then(|users| users.get_mut(&user_id))
+                    .and_then(|macros| macros.remove(name));
+                match removed {
+                    Some(_) => Message::MacroDeleted(name).text(),
+                    None => Message::UnknownMacro(name).text(),
+                }
+            })
+            .await
+    }
+
+    // 他のサーバーでもsaveし直せるよう、定義をそのまま書き出す
+    async fn export_macros(&self, caller: &Caller) -> String {
+        let macros = self.user_macros(caller).await;
+        if macros.is_empty() {
+            return Message::NoMacros.text();
+        }
+        format!("```\n{}\n```", macros.join("\n"))
+    }
+
+    async fn user_sheet(&self, caller: &Caller) -> Option<Sheet> {
+        self.sheets
+            .read()
+            .await
+            .get(&caller.channel_id.0)
+            .and_then(|users| users.get(&caller.user_id.0))
+            .cloned()
+    }
+
+    async fn sheet(&self, caller: &Caller, args: Option<&str>) -> String {
+        let (action, args) = command::split_first_word(args.unwrap_or_default());
+        let me = self.user_sheet(caller).await;
+
+        match action {
+            "" => match me {
+                Some(me) => Message::Sheet(&sheet::to_value(&me).to_string()).text(),
+                None => Message::NoSheet.text(),
+            },
+            "new" => {
+                let rolled = match self.evaluate(caller, args, false, false).await {
+                    Ok(rolled) => rolled,
+                    Err(err) => return err,
+                };
+                match sheet::from_value(&rolled.value) {
+                    Some(sheet) => {
+                        let res = Message::SheetRegistered(&rolled.value.to_string()).text();
+                        self.update_sheet(caller, |me| *me = Some(sheet)).await;
+                        res
+                    }
+                    None => Message::SheetForm.text(),
+                }
+            }
+            "set" | "add" | "sub" => {
+                let mut me = match me {
+                    Some(me) => me,
+                    None => return Message::SheetRequired.text(),
+                };
+                let (field, args) = command::split_first_word(args);
+                if !sheet::is_field_name(field) {
+                    return Message::InvalidFieldName(field).text();
+                }
+
+                let rolled = match self.evaluate(caller, args, false, false).await {
+                    Ok(rolled) => rolled,
+                    Err(err) => return err,
+                };
+
+                let old = me.get(field).cloned();
+                let new = match (action, &old, rolled.value.as_ref()) {
+                    ("set", _, runtime::Value::Integer(n)) => sheet::Field::Integer(*n),
+                    ("set", _, runtime::Value::String(s)) => sheet::Field::String(s.to_string()),
+                    ("add", Some(sheet::Field::Integer(old)), runtime::Value::Integer(n)) => {
+                        sheet::Field::Integer(old + n)
+                    }
+                    ("sub", Some(sheet::Field::Integer(old)), runtime::Value::Integer(n)) => {
+                        sheet::Field::Integer(old - n)
+                    }
+                    ("set", _, _) => {
+                        return format!("{}\n{}", rolled.text(), Message::FieldType.text())
+                    }
+                    (_, Some(sheet::Field::Integer(_)), _) => {
+                        return format!("{}\n{}", rolled.text(), Message::NotInteger.text())
+                    }
+                    (_, Some(_), _) => return Message::NotIntegerField(field).text(),
+                    (_, None, _) => return Message::UnknownField(field).text(),
+                };
+                me.insert(String::from(field), new.clone());
+
+                let fmt_field = |field: &sheet::Field| match field {
+                    sheet::Field::Integer(n) => n.to_string(),
+                    sheet::Field::String(s) => s.clone(),
+                };
+                let field = Message::Heading(field).text();
+                let res = match old {
+                    Some(old) => format!(
+                        "{}\n{}{} → {}",
+                        rolled.text(),
+                        field,
+                        fmt_field(&old),
+                        fmt_field(&new)
+                    ),
+                    None => format!("{}\n{}{}", rolled.text(), field, fmt_field(&new)),
+                };
+                self.update_sheet(caller, |sheet| *sheet = Some(me)).await;
+                res
+            }
+            "import" => {
+                let url = match caller.attachments.first() {
+                    Some(url) => url,
+                    None => return Message::AttachmentRequired.text(),
+                };
+                let json = match self.download(url).await {
+                    Ok(json) => json,
+                    Err(err) => return err,
+                };
+                match importer::import(&json) {
+                    Ok((format, sheet)) => {
+                        let res = Message::SheetImported {
+                            format,
+                            sheet: &sheet::to_value(&sheet).to_string(),
+                        }
+                        .text();
+                        self.update_sheet(caller, |me| *me = Some(sheet)).await;
+                        res
+                    }
+                    Err(err) => err,
+                }
+            }
+            "delete" => {
+                self.update_sheet(caller, |me| *me = None).await;
+                Message::SheetDeleted.text()
+            }
+            action => Message::UnknownCommand("sheet", action).text(),
+        }
+    }
+
+    async fn initiative(&self, caller: &Caller, args: Option<&str>) -> String {
+        let (action, args) = command::split_first_word(args.unwrap_or_default());
+        let channel_id = caller.channel_id.0;
+
+        match action {
+            "" => match self.encounters.read().await.get(&channel_id) {
+                Some(encounter) => format!("{}", encounter),
+                None => Message::NotStarted.text(),
+            },
+            "start" => {
+                let encounter = Encounter::default();
+                let res = format!("{}\n{}", Message::EncounterStarted.text(), encounter);
+                self.encounters
+                    .update(|encounters| encounters.insert(channel_id, encounter))
+                    .await;
+                res
+            }
+            "end" => match self
+                .encounters
+                .update(|encounters| encounters.remove(&channel_id))
+                .await
+            {
+                Some(_) => Message::EncounterEnded.text(),
+                None => Message::NotStarted.text(),
+            },
+            "add" => {
+                let (name, expr) = command::split_first_word(args);
+                if name.is_empty() || expr.is_empty() {
+                    return Message::InitiativeArgs.text();
+                }
+                if !self.encounters.read().await.contains_key(&channel_id) {
+                    return Message::NotStarted.text();
+                }
+
+                let rolled = match self.evaluate(caller, expr, false, false).await {
+                    Ok(rolled) => rolled,
+                    Err(err) => return err,
+                };
+                let initiative = match rolled.value.as_ref() {
+                    runtime::Value::Integer(n) => *n,
+                    _ => {
+                        return format!(
+                            "{}\n{}",
+                            rolled.text(),
+                            Message::InitiativeNotInteger.text()
+                        )
+                    }
+                };
+
+                let encounter = self
+                    .encounters
+                    .update(|encounters| {
+                        encounters.get_mut(&channel_id).map(|encounter| {
+                            encounter.add(name, initiative);
+                            format!("{}", encounter)
+                        })
+                    })
+                    .await;
+                match encounter {
+                    Some(encounter) => format!(
+                        "{}\n{}\n{}",
+                        rolled.text(),
+                        Message::Initiative { name, initiative }.text(),
+                        encounter
+                    ),
+                    None => Message::NotStarted.text(),
+                }
+            }
+            "next" => self
+                .encounters
+                .update(|encounters| {
+                    let encounter = encounters.get_mut(&channel_id)?;
+                    let current = encounter.next().map(|current| current.name.clone());
+                    Some(match current {
+                        Some(current) => {
+                            format!("{}\n{}", Message::Turn(&current).text(), encounter)
+                        }
+                        None => Message::NoTurn.text(),
+                    })
+                })
+                .await
+                .unwrap_or_else(|| Message::NotStarted.text()),
+            "remove" => self
+                .encounters
+                .update(|encounters| {
+                    let encounter = encounters.get_mut(&channel_id)?;
+                    Some(match encounter.remove(args) {
+                        Some(removed) => format!(
+                            "{}\n{}",
+                            Message::CombatantRemoved(&removed.name).text(),
+                            encounter
+                        ),
+                        None => Message::UnknownCombatant(args).text(),
+                    })
+                })
+                .await
+                .unwrap_or_else(|| Message::NotStarted.text()),
+            action => Message::UnknownCommand("init", action).text(),
+        }
+    }
+
+    async fn download(&self, url: &str) -> Result<String, String> {
+        let response = self
+            .http_client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|why| {
+                println!("Failed to download an attachment: {:?}", why);
+                Message::DownloadFailed.text()
+            })?;
+        if response.content_length().unwrap_or(0) > IMPORT_SIZE_LIMIT {
+            return Err(Message::AttachmentTooLarge.text());
+        }
+        response.text().await.map_err(|why| {
+            println!("Failed to download an attachment: {:?}", why);
+            Message::DownloadFailed.text()
+        })
+    }
+
+    async fn update_sheet(&self, caller: &Caller, f: impl FnOnce(&mut Option<Sheet>)) {
+        let (channel_id, user_id) = (caller.channel_id.0, caller.user_id.0);
+        self.sheets
+            .update(|sheets| {
+                let users = sheets.entry(channel_id).or_default();
+                let mut sheet = users.remove(&user_id);
+                f(&mut sheet);
+                if let Some(sheet) = sheet {
+                    users.insert(user_id, sheet);
+                }
+                if users.is_empty() {
+                    sheets.remove(&channel_id);
+                }
+            })
+            .await
+    }
+
+    async fn style(&self, caller: &Caller) -> render::Style {
+        match caller.guild_id {
+            Some(guild_id) => self
+                .guilds
+                .read()
+                .await
+                .get(&guild_id.0)
+                .map_or(render::Style::Text, |settings| settings.style),
+            None => render::Style::Text,
+        }
+    }
+
+    async fn language(&self, caller: &Caller) -> Language {
+        match caller.guild_id {
+            Some(guild_id) => self
+                .guilds
+                .read()
+                .await
+                .get(&guild_id.0)
+                .map_or(Language::Ja, |settings| settings.language),
+            None => Language::Ja,
+        }
+    }
+
+    // 言語を設定する前でも読めるよう、設定の返答は両方の言語で書く
+    async fn select_language(&self, caller: &Caller, name: Option<&str>) -> String {
+        let guild_id = match caller.guild_id {
+            Some(guild_id) => guild_id,
+            None => return Message::ServerOnly(Setting::Language).both(),
+        };
+        self.guilds
+            .update(|guilds| {
+                let settings = guilds.entry(guild_id.0).or_default();
+
+                match name.map(|name| (name, Language::find(name))) {
+                    Some((_, Some(language))) => {
+                        settings.language = language;
+                        Message::LanguageSet(language).both()
+                    }
+                    Some((name, None)) => Message::UnknownLanguage(name).both(),
+                    None => Message::Current(Setting::Language, settings.language.name()).both(),
+                }
+            })
+            .await
+    }
+
+    async fn output(&self, caller: &Caller, style: Option<&str>) -> String {
+        let guild_id = match caller.guild_id {
+            Some(guild_id) => guild_id,
+            None => return Message::ServerOnly(Setting::Style).text(),
+        };
+        self.guilds
+            .update(|guilds| {
+                let settings = guilds.entry(guild_id.0).or_default();
+
+                let style = match style.map(str::to_ascii_lowercase).as_deref() {
+                    Some("text") => render::Style::Text,
+                    Some("embed") => render::Style::Embed,
+                    Some(style) => return Message::UnknownStyle(style).text(),
+                    None => {
+                        let name = match settings.style {
+                            render::Style::Text => "text",
+                            render::Style::Embed => "embed",
+                        };
+                        return Message::Current(Setting::Style, name).text();
+                    }
+                };
+                settings.style = style;
+                Message::StyleSet(style).text()
+            })
+            .await
+    }
+
+    async fn secret_roll(&self, caller: &Caller, content: &str) -> Reply {
+        let (result, seed) = match self.evaluate(caller, content, true, false).await {
+            Ok(rolled) => (rolled.text(), Some(rolled.seed)),
+            Err(err) => (err, None),
+        };
+        let mut secrets = self.secrets.write().await;
+        let secrets = secrets.entry(caller.channel_id).or_default();
+        secrets.push(Secret {
+            user_id: caller.user_id,
+            content: result.clone(),
+            revealed: false,
+            seed,
+        });
+        let id = secrets.len();
+
+        Reply {
+            embed: None,
+            files: vec![],
+            content: Message::SecretRolled {
+                user_id: caller.user_id.0,
+                id,
+            }
+            .text(),
+            secret: Some(format!(
+                "{}\n{}",
+                Message::SecretResult {
+                    id,
+                    channel_id: caller.channel_id.0
+                }
+                .text(),
+                result
+            )),
+        }
+    }
+
+    async fn reveal(&self, caller: &Caller, id: Option<&str>) -> String {
+        let is_gm = self.is_gm(caller).await;
+        let mut secrets = self.secrets.write().await;
+        let secrets = match secrets.get_mut(&caller.channel_id) {
+            Some(secrets) => secrets,
+            None => return Message::NoSecrets.text(),
+        };
+
+        let idx = if let Some(id) = id {
+            match id.trim_start_matches('#').parse::<usize>() {
+                Ok(n) if n >= 1 && n <= secrets.len() => n - 1,
+                _ => return Message::UnknownSecret(id).text(),
+            }
+        } else {
+            match secrets
+                .iter()
+                .rposition(|secret| secret.user_id == caller.user_id && !secret.revealed)
+            {
+                Some(idx) => idx,
+                None => return Message::NoSecrets.text(),
+            }
+        };
+
+        let secret = &mut secrets[idx];
+        if secret.user_id != caller.user_id && !is_gm {
+            return Message::OthersSecret.text();
+        }
+        secret.revealed = true;
+        if let Some(seed) = secret.seed {
+            self.history.reveal(caller.channel_id.0, seed).await;
+        }
+
+        format!(
+            "{}\n{}",
+            Message::SecretRevealed {
+                user_id: secret.user_id.0,
+                id: idx + 1
+            }
+            .text(),
+            secret.content
+        )
+    }
+
+    async fn is_gm(&self, caller: &Caller) -> bool {
+        if let Some(guild_id) = caller.guild_id {
+            if let Some(gm_role) = self
+                .guilds
+                .read()
+                .await
+                .get(&guild_id.0)
+                .and_then(|settings| settings.gm_role)
+            {
+                return caller.roles.contains(&gm_role);
+            }
+        }
+        false
+    }
+
+    async fn gm_role(&self, caller: &Caller, role: Option<&str>) -> String {
+        let guild_id = match caller.guild_id {
+            Some(guild_id) => guild_id,
+            None => return Message::ServerOnly(Setting::GmRole).text(),
+        };
+        self.guilds
+            .update(|guilds| {
+                let settings = guilds.entry(guild_id.0).or_default();
+
+                match role {
+                    Some("reset") => {
+                        settings.gm_role = None;
+                        Message::Reset(Setting::GmRole).text()
+                    }
+                    Some(role) => {
+                        let id = role.trim_start_matches("<@&").trim_end_matches('>');
+                        match id.parse() {
+                            Ok(id) => {
+                                settings.gm_role = Some(RoleId(id));
+                                Message::GmRoleSet(id).text()
+                            }
+                            Err(_) => Message::NotRole(role).text(),
+                        }
+                    }
+                    None => match settings.gm_role {
+                        Some(role) => {
+                            Message::Current(Setting::GmRole, &format!("<@&{}>", role)).text()
+                        }
+                        None => Message::Current(Setting::GmRole, &Message::NotSet.text()).text(),
+                    },
+                }
+            })
+            .await
+    }
+
+    // シークレットダイスの結果を、振った本人とGMのロールを持つメンバーにDMで送る
+    // メンションへの返答と同様に、長い返答は分割するか添付し、添付ファイルは続けて送る
+    async fn respond(
+        &self,
+        interaction: &Interaction,
+        reply: &Reply,
+        ephemeral: bool,
+    ) -> reqwest::Result<()> {
+        let client = &self.http_client;
+        let (chunks, files) = match (&reply.embed, reply.files.is_empty()) {
+            // 添付ファイルがあるときは埋め込みを使わない
+            (_, false) => (
+                vec![render::summarize(&reply.content, MESSAGE_LIMIT)],
+                reply.files.clone(),
+            ),
+            (Some(embed), true) => {
+                return interaction
+                    .respond(client, &reply.content, Some(embed), ephemeral)
+                    .await
+            }
+            (None, true) => match delivery(&reply.content) {
+                Delivery::Messages(chunks) => (chunks, vec![]),
+                Delivery::Attachment(summary) => (
+                    vec![summary],
+                    vec![(
+                        String::from("result.txt"),
+                        reply.content.clone().into_bytes(),
+                    )],
+                ),
+            },
+        };
+        let mut chunks = chunks.into_iter();
+        let content = chunks.next().unwrap_or_default();
+        interaction
+            .respond(client, &content, None, ephemeral)
+            .await?;
+        for chunk in chunks {
+            interaction
+                .follow_up(client, &chunk, &[], ephemeral)
+                .await?;
+        }
+        if !files.is_empty() {
+            interaction.follow_up(client, "", &files, ephemeral).await?;
+        }
+        Ok(())
+    }
+
+    async fn send_secret(&self, context: &Context, caller: &Caller, secret: &str) {
+        let mut users = vec![caller.user_id];
+
+        if let Some(guild_id) = caller.guild_id {
+            let gm_role = self
+                .guilds
+                .read()
+                .await
+                .get(&guild_id.0)
+                .and_then(|settings| settings.gm_role);
+            if let Some(gm_role) = gm_role {
+                match guild_id.members(&context.http, Some(1000), None).await {
+                    Ok(members) => users.extend(
+                        members
+                            .into_iter()
+                            .filter(|member| member.roles.contains(&gm_role))
+                            .map(|member| member.user.id)
+                            .filter(|user_id| *user_id != caller.user_id),
+                    ),
+                    Err(why) => println!("Failed to get guild members: {:?}", why),
+                }
+            }
+        }
+
+        for user_id in users {
+            let sent = match user_id.create_dm_channel(&context.http).await {
+                Ok(channel) => send_text(&context.http, channel.id, secret, None).await,
+                Err(why) => Err(why),
+            };
+            if let Err(why) = sent {
+                println!("Failed to send a direct message: {:?}", why);
+            }
+        }
+    }
+
+    async fn help(&self, caller: &Caller, name: Option<&str>) -> String {
+        let (system, _) = self.settings(caller).await;
+        let name = match name {
+            Some("commands") => return String::from(usage()),
+            Some(name) => name,
+            None => return help::listing(system),
+        };
+        if let Some(help) = help::syntax(name) {
+            return format!("{}\n{}", name, help);
+        }
+        match roll::evaluate(
+            &parser::Syntax::Native,
+            system,
+            &[],
+            None,
+            rand::random(),
+            &format!("help {}", name),
+        ) {
+            Ok(rolled) if matches!(rolled.value.as_ref(), runtime::Value::String(_)) => {
+                rolled.text()
+            }
+            _ => Message::UnknownFunction(name).text(),
+        }
+    }
+
+    // 「system default <システム名>」でサーバーの既定のシステムを設定する
+    async fn system(&self, caller: &Caller, name: Option<&str>) -> String {
+        if let ("default", name) = command::split_first_word(name.unwrap_or_default()) {
+            return self
+                .guild_system(caller, Some(name).filter(|name| !name.is_empty()))
+                .await;
+        }
+
+        let channel_id = caller.channel_id.0;
+        let system = name.map(|name| (name, game_system::find(name)));
+        let mut res = match system {
+            Some(("reset", _)) => {
+                self.channels
+                    .update(|channels| channels.entry(channel_id).or_default().system = None)
+                    .await;
+                Message::ChannelSystemReset.text() + "\n"
+            }
+            Some((_, Some(system))) => {
+                self.channels
+                    .update(|channels| {
+                        channels.entry(channel_id).or_default().system =
+                            Some(String::from(system.name()))
+                    })
+                    .await;
+                return Message::SystemSet {
+                    title: &system.title(),
+                    name: system.name(),
+                    help: &system.help(),
+                }
+                .text();
+            }
+            Some((name, None)) => return unknown_system(name),
+            None => String::new(),
+        };
+
+        let (system, _) = self.settings(caller).await;
+        let inherited = self
+            .channels
+            .read()
+            .await
+            .get(&channel_id)
+            .is_none_or(|settings| settings.system.is_none());
+        res += &match system {
+            Some(system) => Message::CurrentSystem {
+                title: &system.title(),
+                name: system.name(),
+                inherited,
+            }
+            .text(),
+            None => Message::NoSystem.text(),
+        };
+        res + "\n" + &available_systems()
+    }
+
+    async fn guild_system(&self, caller: &Caller, name: Option<&str>) -> String {
+        let guild_id = match caller.guild_id {
+            Some(guild_id) => guild_id.0,
+            None => return Message::ServerOnly(Setting::DefaultSystem).text(),
+        };
+
+        match name.map(|name| (name, game_system::find(name))) {
+            Some(("reset", _)) => {
+                self.guilds
+                    .update(|guilds| guilds.entry(guild_id).or_default().system = None)
+                    .await;
+                Message::Reset(Setting::DefaultSystem).text()
+            }
+            Some((_, Some(system))) => {
+                self.guilds
+                    .update(|guilds| {
+                        guilds.entry(guild_id).or_default().system =
+                            Some(String::from(system.name()))
+                    })
+                    .await;
+                Message::DefaultSystemSet {
+                    title: &system.title(),
+                    name: system.name(),
+                }
+                .text()
+            }
+            Some((name, None)) => unknown_system(name),
+            None => {
+                let system = self.default_system(caller).await;
+                let system = match system.as_deref().and_then(game_system::find) {
+                    Some(system) => format!("{}（{}）", system.title(), system.name()),
+                    None => Message::NotSet.text(),
+                };
+                Message::Current(Setting::DefaultSystem, &system).text()
+                    + "\n"
+                    + &available_systems()
+            }
+        }
+    }
+
+    async fn syntax(&self, caller: &Caller, name: Option<&str>) -> String {
+        let channel_id = caller.channel_id.0;
+        let syntax = match name.map(str::to_ascii_lowercase).as_deref() {
+            Some("native") => parser::Syntax::Native,
+            Some("bcdice") => parser::Syntax::BCDice,
+            Some(name) => return Message::UnknownSyntax(name).text(),
+            None => {
+                let (_, syntax) = self.settings(caller).await;
+                return Message::CurrentSyntax(syntax).text();
+            }
+        };
+
+        self.channels
+            .update(|channels| channels.entry(channel_id).or_default().syntax = syntax)
+            .await;
+        Message::SyntaxSet(syntax).text()
+    }
+
+    async fn prefix(&self, caller: &Caller, prefix: Option<&str>) -> String {
+        let guild_id = match caller.guild_id {
+            Some(guild_id) => guild_id,
+            None => return Message::ServerOnly(Setting::Prefix).text(),
+        };
+        self.guilds
+            .update(|guilds| {
+                let settings = guilds.entry(guild_id.0).or_default();
+
+                match prefix {
+                    Some("reset") => {
+                        settings.prefix = None;
+                        Message::Reset(Setting::Prefix).text()
+                    }
+                    Some(prefix) => {
+                        settings.prefix = Some(String::from(prefix));
+                        Message::PrefixSet(prefix).text()
+                    }
+                    None => match settings.prefix.as_ref().or(self.default_prefix.as_ref()) {
+                        Some(prefix) => Message::Current(Setting::Prefix, prefix).text(),
+                        None => Message::Current(Setting::Prefix, &Message::NotSet.text()).text(),
+                    },
+                }
+            })
+            .await
+    }
+
+    async fn auto_roll(&self, caller: &Caller, flag: Option<&str>) -> String {
+        let guild_id = match caller.guild_id {
+            Some(guild_id) => guild_id,
+            None => return Message::ServerOnly(Setting::AutoRoll).text(),
+        };
+        self.guilds
+            .update(|guilds| {
+                let settings = guilds.entry(guild_id.0).or_default();
+
+                if let Some(flag) = flag.and_then(parse_flag) {
+                    settings.auto_roll = flag;
+                }
+                Message::Flag(Setting::AutoRoll, settings.auto_roll).text()
+            })
+            .await
+    }
+
+    async fn safe_mode(&self, caller: &Caller, flag: Option<&str>) -> String {
+        let guild_id = match caller.guild_id {
+            Some(guild_id) => guild_id,
+            None => return Message::ServerOnly(Setting::SafeMode).text(),
+        };
+        self.guilds
+            .update(|guilds| {
+                let settings = guilds.entry(guild_id.0).or_default();
+
+                if let Some(flag) = flag.and_then(parse_flag) {
+                    settings.safe_mode = flag;
+                }
+                Message::Flag(Setting::SafeMode, settings.safe_mode).text()
+            })
+            .await
+    }
+
+    // メンション以外の発言からコマンドを取り出す
+    async fn unmentioned_command<'a>(
+        &self,
+        caller: &Caller,
+        content: &'a str,
+    ) -> Option<Command<'a>> {
+        let (prefix, auto_roll, safe_mode) = match caller.guild_id {
+            Some(guild_id) => self
+                .guilds
+                .read()
+                .await
+                .get(&guild_id.0)
+                .map(|settings| {
+                    (
+                        settings.prefix.clone(),
+                        settings.auto_roll,
+                        settings.safe_mode,
+                    )
+                })
+                .unwrap_or((None, false, true)),
+            None => (None, false, true),
+        };
+        let prefix = prefix.or_else(|| self.default_prefix.clone());
+
+        let (command, safe_mode) = if let Some(content) = prefix
+            .as_ref()
+            .and_then(|prefix| content.strip_prefix(prefix.as_str()))
+        {
+            (Command::parse(content), safe_mode)
+        } else if auto_roll && self.dice_pattern.is_match(content) {
+            // 接頭辞なしで振る場合は発言全体を式として扱い、
+            // 普段の会話に反応しないよう、全体を解釈できないときは振らない
+            (Command::parse(content.trim()), true)
+        } else {
+            return None;
+        };
+
+        if let Command::Roll(content) | Command::SecretRoll(content) = &command {
+            if safe_mode {
+                let (_, syntax) = self.settings(caller).await;
+                if parser::parse(&syntax, content).is_err() {
+                    return None;
+                }
+            }
+        }
+
+        Some(command)
+    }
+}
+
+// 送り方。文字数制限を超える場合は分割し、それでも長すぎる場合は全文をファイルとして添付する
+enum Delivery {
+    Messages(Vec<String>),
+    // 本文に載せる要約
+    Attachment(String),
+}
+
+fn delivery(content: &str) -> Delivery {
+    if content.chars().count() > SPLIT_LIMIT {
+        Delivery::Attachment(render::summarize(content, MESSAGE_LIMIT))
+    } else {
+        Delivery::Messages(render::split(content, MESSAGE_LIMIT))
+    }
+}
+
+async fn send_text(
+    http: &serenity::http::Http,
+    channel_id: ChannelId,
+    content: &str,
+    reference: Option<MessageId>,
+) -> serenity::Result<()> {
+    let chunks = match delivery(content) {
+        Delivery::Messages(chunks) => chunks,
+        Delivery::Attachment(summary) => {
+            let mut map = serde_json::Map::new();
+            map.insert(String::from("content"), summary.into());
+            http.send_files(channel_id.0, vec![(content.as_bytes(), "result.txt")], map)
+                .await?;
+            return Ok(());
+        }
+    };
+
+    for (idx, chunk) in chunks.into_iter().enumerate() {
+        let mut map = json!({ "content": chunk });
+        if let (0, Some(reference)) = (idx, reference) {
+            map["message_reference"] = json!({ "message_id": *reference.as_u64() });
+        }
+        http.send_message(channel_id.0, &map).await?;
+    }
+    Ok(())
+}
+
+fn unknown_system(name: &str) -> String {
+    Message::UnknownSystem(name).text() + "\n" + &available_systems()
+}
+
+fn available_systems() -> String {
+    let mut res = Message::AvailableSystems.text();
+    for system in game_system::systems() {
+        res += format!("\n{} : {}", system.name(), system.title()).as_str();
+    }
+    res
+}
+
+fn parse_flag(flag: &str) -> Option<bool> {
+    match flag.to_ascii_lowercase().as_str() {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    }
+}
+
+#[serenity::async_trait]
+impl EventHandler for Handler {
+    async fn message(&self, context: Context, msg: serenity::model::channel::Message) {
+        let current_user_id = context.cache.current_user_id().await;
+        let caller = Caller {
+            guild_id: msg.guild_id,
+            channel_id: msg.channel_id,
+            user_id: msg.author.id,
+            user_name: msg.author.name.clone(),
+            roles: msg
+                .member
+                .as_ref()
+                .map(|member| member.roles.clone())
+                .unwrap_or_default(),
+            mentions: msg
+                .mentions
+                .iter()
+                .map(|user| user.id)
+                .filter(|user_id| *user_id != current_user_id)
+                .collect(),
+            attachments: msg
+                .attachments
+                .iter()
+                .map(|attachment| attachment.url.clone())
+                .collect(),
+        };
+
+        let content = self.comment_pattern.replace_all(&msg.content, "");
+        let reply = if msg.mentions_me(&context).await.unwrap_or(false) {
+            // メンションを削除
+            let content = self.mention_pattern.replace_all(&content, "");
+            self.execute(&caller, Command::parse(&content)).await
+        } else if msg.author.bot {
+            return;
+        } else if let Some(command) = self.unmentioned_command(&caller, &content).await {
+            self.execute(&caller, command).await
+        } else {
+            return;
+        };
+
+        // 省略や分割の表示も返答と同じ言語で書く
+        let language = self.language(&caller).await;
+        let sent = locale::scope(language, async {
+            match (&reply.embed, reply.files.is_empty()) {
+                // 添付ファイルがあるときは埋め込みを使わない
+                (_, false) => {
+                    let mut map = serde_json::Map::new();
+                    map.insert(
+                        String::from("content"),
+                        render::summarize(&reply.content, MESSAGE_LIMIT).into(),
+                    );
+                    let files: Vec<(&[u8], &str)> = reply
+                        .files
+                        .iter()
+                        .map(|(name, file)| (file.as_slice(), name.as_str()))
+                        .collect();
+                    context
+                        .http
+                        .send_files(msg.channel_id.0, files, map)
+                        .await
+                        .map(|_| ())
+                }
+                (Some(embed), true) => {
+                    let map = json!({
+                        "embed": embed,
+                        "message_reference": {
+                            "message_id": *msg.id.as_u64()
+                        }
+                    });
+                    context
+                        .http
+                        .send_message(msg.channel_id.0, &map)
+                        .await
+                        .map(|_| ())
+                }
+                (None, true) => {
+                    send_text(&context.http, msg.channel_id, &reply.content, Some(msg.id)).await
+                }
+            }
+        })
+        .await;
+        if let Err(why) = sent {
+            println!("Failed to send a message: {:?}", why);
+        }
+
+        if let Some(secret) = &reply.secret {
+            self.send_secret(&context, &caller, secret).await;
+        }
+    }
+
+    async fn ready(&self, context: Context, _: Ready) {
+        let application_id = match context.http.get_current_application_info().await {
+            Ok(info) => *info.id.as_u64(),
+            Err(why) => {
+                println!("Failed to get application info: {:?}", why);
+                return;
+            }
+        };
+        if let Err(why) =
+            interaction::register(&self.http_client, &context.http.token, application_id).await
+        {
+            println!("Failed to register slash commands: {:?}", why);
+        }
+    }
+
+    async fn unknown(&self, context: Context, name: String, raw: serde_json::Value) {
+        if name != "INTERACTION_CREATE" {
+            return;
+        }
+        if let Some(interaction) = Interaction::from_value(&raw) {
+            if let Some(command) = Command::from_interaction(&interaction) {
+                let caller = Caller {
+                    guild_id: interaction.guild_id.map(GuildId),
+                    channel_id: ChannelId(interaction.channel_id),
+                    user_id: UserId(interaction.user_id),
+                    user_name: interaction.user_name.clone(),
+                    roles: interaction.roles.iter().copied().map(RoleId).collect(),
+                    mentions: vec![],
+                    attachments: vec![],
+                };
+                let ephemeral = interaction
+                    .option_bool("ephemeral")
+                    .unwrap_or(matches!(command, Command::Help(_)));
+                let reply = self.execute(&caller, command).await;
+                let language = self.language(&caller).await;
+                let sent =
+                    locale::scope(language, self.respond(&interaction, &reply, ephemeral)).await;
+                if let Err(why) = sent {
+                    println!("Failed to respond to interaction: {:?}", why);
+                }
+                if let Some(secret) = &reply.secret {
+                    self.send_secret(&context, &caller, secret).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // テストごとの保存先。同じディレクトリで開き直すと、保存した内容を読み込む
+    fn data_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dicebot-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn caller(user_id: u64) -> Caller {
+        Caller {
+            guild_id: Some(GuildId(1)),
+            channel_id: ChannelId(2),
+            user_id: UserId(user_id),
+            user_name: String::from("アリス"),
+            roles: vec![],
+            mentions: vec![],
+            attachments: vec![],
+        }
+    }
+
+    fn run(handler: &Handler, content: &str) -> String {
+        run_as(handler, 3, content)
+    }
+
+    fn run_as(handler: &Handler, user_id: u64, content: &str) -> String {
+        // ファイルへの書き込みにtokioのランタイムを使う
+        let reply = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(handler.execute(&caller(user_id), Command::parse(content)));
+        reply.content
+    }
+
+    #[test]
+    fn save_and_load_macros() {
+        let dir = data_dir("macros");
+        let handler = Handler::open(&dir);
+        assert_eq!(
+            run(&handler, "save atk := 2d6+4"),
+            "マクロatkを保存しました"
+        );
+        run(&handler, "save dmg := 1d6");
+        // 同じ名前で保存すると上書きする
+        run(&handler, "save atk := 10");
+        assert_eq!(
+            run(&handler, "macro list"),
+            "保存しているマクロ：\n`atk := 10`\n`dmg := 1d6`"
+        );
+        assert!(run(&handler, "atk + 1").contains(" -> 11"));
+        // マクロはユーザーごとに保存される
+        assert_eq!(
+            run_as(&handler, 4, "macro list"),
+            "保存しているマクロはありません"
+        );
+
+        assert_eq!(run(&handler, "macro delete dmg"), "マクロdmgを削除しました");
+        assert_eq!(
+            run(&handler, "macro delete dmg"),
+            "dmgというマクロはありません"
+        );
+        assert_eq!(
+            run(&handler, "save 2d6"),
+            "マクロは「名前 := 式」の形で指定してください"
+        );
+        assert_eq!(
+            run(&handler, "save a := 1; b := 2"),
+            "マクロは1つずつ保存してください"
+        );
+
+        let handler = Handler::open(&dir);
+        assert_eq!(
+            run(&handler, "macro list"),
+            "保存しているマクロ：\n`atk := 10`"
+        );
+        assert_eq!(run(&handler, "macro export"), "```\natk := 10\n```");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn update_sheet_fields() {
+        let dir = data_dir("sheets");
+        let handler = Handler::open(&dir);
+        assert_eq!(
+            run(&handler, "sheet add hp 1"),
+            "先に sheet new でキャラクターシートを登録してください"
+        );
+        assert!(run(&handler, r#"sheet new {hp: 10, name: "アリス"}"#)
+            .starts_with("キャラクターシートを登録しました"));
+
+        assert!(run(&handler, "sheet sub hp 3").ends_with("hp：10 → 7"));
+        assert!(run(&handler, "sheet add hp 1 + 1").ends_with("hp：7 → 9"));
+        assert!(run(&handler, "sheet set hp 12").ends_with("hp：9 → 12"));
+        assert!(run(&handler, r#"sheet set name "ボブ""#).ends_with("name：アリス → ボブ"));
+        assert!(run(&handler, "sheet set mp 5").ends_with("mp：5"));
+        assert!(run(&handler, "me.hp - me.mp").contains(" -> 7"));
+
+        assert_eq!(
+            run(&handler, "sheet add name 1"),
+            "nameは整数の項目ではありません"
+        );
+        assert_eq!(
+            run(&handler, "sheet sub san 1"),
+            "sanという項目はありません"
+        );
+        assert_eq!(
+            run(&handler, "sheet set 1st 1"),
+            "1stは項目名として使えません"
+        );
+        assert!(run(&handler, "sheet add hp [1]").ends_with("結果が整数ではありません"));
+
+        // シートはチャンネルとユーザーごとに保存される
+        assert_eq!(
+            run_as(&handler, 4, "sheet"),
+            "このチャンネルではキャラクターシートが登録されていません"
+        );
+        let handler = Handler::open(&dir);
+        assert_eq!(
+            run(&handler, "sheet"),
+            r#"キャラクターシート：{hp: 12, mp: 5, name: ボブ}"#
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reply_in_guild_language() {
+        let handler = Handler::open(data_dir("language"));
+        assert_eq!(
+            run(&handler, "macro list"),
+            "保存しているマクロはありません"
+        );
+
+        let res = run(&handler, "language en");
+        assert!(res.contains("英語") && res.contains("English"), "{}", res);
+        assert_eq!(run(&handler, "macro list"), "You have no saved macros");
+        assert_eq!(run(&handler, "safemode"), "safe mode: on");
+        assert!(run(&handler, "init").starts_with("No encounter has started"));
+        assert!(run(&handler, "help commands").starts_with("Usage:"));
+        assert!(run(&handler, "init start").starts_with("Started the encounter\nRound 1"));
+        let res = run(&handler, "system Cthulhu7th");
+        assert!(res.starts_with("Set the system to"), "{}", res);
+        assert!(res.contains("Call of Cthulhu 7th Edition") && res.contains("Skill roll"));
+        // 判定の経過と結果のレコードの両方に英語の成功度が入る
+        let res = run(&handler, "CC<=0");
+        let level = ["Critical", "Failure", "Fumble"]
+            .iter()
+            .find(|level| res.contains(&format!("level: {},", level)));
+        assert!(
+            level.is_some_and(|level| res.contains(&format!("→ {} ->", level))),
+            "{}",
+            res
+        );
+    }
+
+    #[test]
+    fn hide_secret_in_history() {
+        let dir = data_dir("secret");
+        let handler = Handler::open(&dir);
+        run(&handler, "S2d6+3");
+        run(&handler, "2d6+4");
+        let res = run(&handler, "history");
+        assert!(res.contains("（シークレットダイス）"), "{}", res);
+        assert!(!res.contains("2d6+3") && res.contains("2d6+4"), "{}", res);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn split_or_attach_long_replies() {
+        match delivery("2d6 → 7") {
+            Delivery::Messages(chunks) => assert_eq!(chunks, ["2d6 → 7"]),
+            Delivery::Attachment(_) => panic!(),
+        }
+
+        let lines: Vec<_> = (0..300)
+            .map(|n| format!("出目{}：{}", n, n % 6 + 1))
+            .collect();
+        let content = lines.join("\n");
+        match delivery(&content) {
+            Delivery::Messages(chunks) => {
+                assert!(chunks.len() > 1);
+                assert!(chunks.iter().all(|c| c.chars().count() <= MESSAGE_LIMIT));
+                assert_eq!(chunks.join("\n"), content);
+            }
+            Delivery::Attachment(_) => panic!(),
+        }
+
+        let content = "1d6 → 3\n".repeat(SPLIT_LIMIT);
+        match delivery(&content) {
+            Delivery::Messages(_) => panic!(),
+            Delivery::Attachment(summary) => {
+                assert!(summary.chars().count() <= MESSAGE_LIMIT);
+                assert!(summary.starts_with("1d6 → 3"));
+            }
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use crate::game_system::{self, GameSystem};
+use crate::runtime::{self, built_in_function};
+
+// 演算子と構文の説明。名前はhelpの引数として書くもの
+const SYNTAX: &[(&str, &str)] = &[
+    (
+        "d",
+        "x d y : Integer -> Integer -> Integer\n\
+        \n\
+        ［説明］\n\
+        y面のダイスをx個振り、出目の合計を返します。Dと書くこともできます。\n\
+        \n\
+        ［使用例］\n\
+        2d6 //6面のダイスを2個振った合計\n\
+        1D100 //100面のダイスを1個振る",
+    ),
+    (
+        "b",
+        "x b y : Integer -> Integer -> Array\n\
+        \n\
+        ［説明］\n\
+        y面のダイスをx個振り、出目の配列を返します。Bと書くこともできます。\n\
+        配列を比較すると、条件を満たす出目の数を数えます。\n\
+        \n\
+        ［使用例］\n\
+        10B6 //6面のダイスを10個振る\n\
+        10B6>=5 //出目が5以上のダイスを数える",
+    ),
+    (
+        "+",
+        "x + y, x - y, x * y, x / y : Integer -> Integer -> Integer\n\
+        \n\
+        ［説明］\n\
+        四則演算を行います。割り算の余りは切り捨てます。\n\
+        掛け算と割り算は足し算と引き算より先に計算します。\n\
+        \n\
+        ［使用例］\n\
+        2d6+3 //2d6に3を足す\n\
+        (1d6+1)*2 //括弧の中を先に計算する",
+    ),
+    (
+        "==",
+        "x == y, x != y, x < y, x > y, x <= y, x >= y : Integer -> Integer -> Boolean\n\
+        \n\
+        ［説明］\n\
+        2つの値を比較し、成功か失敗を返します。\n\
+        左辺が配列やレコードの場合は、要素ごとに比較します。\n\
+        \n\
+        ［使用例］\n\
+        1d100<=60 //60以下なら成功\n\
+        10B6>=5 //出目が5以上のダイスを数える",
+    ),
+    (
+        "#",
+        "x # n : Any -> Integer -> Array\n\
+        \n\
+        ［説明］\n\
+        式xをn回評価し、結果の配列を返します。\n\
+        \n\
+        ［使用例］\n\
+        3d6 # 6 //3d6を6回振る",
+    ),
+    (
+        "@",
+        "x @ f : Any -> Fn -> Array\n\
+        x @>= y, x @<= y, x @> y, x @< y, x @== y, x @!= y : Any -> Any -> Array\n\
+        \n\
+        ［説明］\n\
+        条件を満たす間、式xを評価し直します。評価した全ての結果を配列で返します。\n\
+        @の場合は、結果を関数fに渡して成功が返る間、振り直します。\n\
+        \n\
+        ［使用例］\n\
+        1d10 @>= 10 //10が出る限り振り足す\n\
+        1d6 @ \\x -> x == 6 //6が出る限り振り足す",
+    ),
+    (
+        ".",
+        "x . f : Any -> Fn -> Any\n\
+        \n\
+        ［説明］\n\
+        xを関数fに渡します（f x と同じです）。\n\
+        xがレコードの場合は、me.hp のようにフィールドの値を取り出します。\n\
+        \n\
+        ［使用例］\n\
+        10B6.s //10B6を並び替える\n\
+        1d100 <= me.skill_spot //キャラクターシートの値で判定する",
+    ),
+    (
+        ":=",
+        "x := 式, x? := 式\n\
+        \n\
+        ［説明］\n\
+        式に名前を付けます。x は定義したときの値を、x? は使うたびに式を評価し直した値を表します。\n\
+        \n\
+        ［使用例］\n\
+        atk := 2d6+4; atk //一度振った値を使う\n\
+        atk? := 2d6+4; atk? # 3 //3回振り直す",
+    ),
+    (
+        "\\",
+        "\\x -> 式, \\x y -> 式\n\
+        \n\
+        ［説明］\n\
+        関数を作ります。関数は f x y のように空白で区切って引数を渡して呼び出します。\n\
+        \n\
+        ［使用例］\n\
+        double := \\x -> x * 2; double 1d6 //1d6を2倍する",
+    ),
+    (
+        ";",
+        "式; 式\n\
+        \n\
+        ［説明］\n\
+        式を順番に評価し、最後の式の値を返します。\n\
+        { } で囲むと、中で定義した名前はその外では使えなくなります。\n\
+        \n\
+        ［使用例］\n\
+        x := 1d6; x * x //1d6を振って2乗する",
+    ),
+    (
+        "[]",
+        "[x, y, ...], {key: x, ...}\n\
+        \n\
+        ［説明］\n\
+        [ ] は配列を、{ } にキーと値を書くとレコードを作ります。\n\
+        \n\
+        ［使用例］\n\
+        choice [\"アリス\", \"ボブ\"] //配列から1つ選ぶ\n\
+        {hp: 2d6+10, mp: 1d6} //レコードを作る",
+    ),
+];
+
+// 同じ説明を使う別名
+const ALIASES: &[(&str, &str)] = &[
+    ("D", "d"),
+    ("B", "b"),
+    ("-", "+"),
+    ("*", "+"),
+    ("/", "+"),
+    ("!=", "=="),
+    ("<", "=="),
+    (">", "=="),
+    ("<=", "=="),
+    (">=", "=="),
+    ("@==", "@"),
+    ("@!=", "@"),
+    ("@<", "@"),
+    ("@>", "@"),
+    ("@<=", "@"),
+    ("@>=", "@"),
+    ("?", ":="),
+    ("->", "\\"),
+    ("{}", "[]"),
+];
+
+// 演算子や構文の説明
+pub fn syntax(name: &str) -> Option<&'static str> {
+    let name = ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map_or(name, |(_, name)| *name);
+    SYNTAX
+        .iter()
+        .find(|(topic, _)| *topic == name)
+        .map(|(_, help)| *help)
+}
+
+// ゲームシステムごとの組み込み関数の名前
+fn system_functions(system: &dyn GameSystem) -> Vec<String> {
+    let mut env = runtime::Environment::new();
+    async_std::task::block_on(system.set_default(&mut env));
+    async_std::task::block_on(env.built_in_functions())
+        .into_iter()
+        .map(|(name, _)| name.to_string())
+        .filter(|name| name != "help")
+        .collect()
+}
+
+// 組み込み関数と演算子の一覧。システムが選択されていない場合は全てのシステムの関数を並べる
+pub fn listing(system: Option<&dyn GameSystem>) -> String {
+    let mut res = String::from("関数（help <名前> で説明を表示します）：");
+    for (category, names) in built_in_function::CATEGORIES {
+        res += format!("\n{}：{}", category, names.join(", ")).as_str();
+    }
+    let systems = match system {
+        Some(system) => vec![system],
+        None => game_system::systems().to_vec(),
+    };
+    for system in systems {
+        res += format!(
+            "\n{}：{}",
+            system.title(),
+            system_functions(system).join(", ")
+        )
+        .as_str();
+    }
+    // 同じ説明の演算子はまとめて表示する
+    let topics: Vec<_> = SYNTAX
+        .iter()
+        .map(|(topic, _)| {
+            let mut names = vec![*topic];
+            names.extend(
+                ALIASES
+                    .iter()
+                    .filter(|(alias, name)| name == topic && !alias.eq_ignore_ascii_case(topic))
+                    .map(|(alias, _)| *alias),
+            );
+            names.join(" ")
+        })
+        .collect();
+    res += format!("\n演算子と構文：{}", topics.join(", ")).as_str();
+    res += "\nボットのコマンドの一覧は help commands で表示します";
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn built_in_functions() -> Vec<(String, String)> {
+        let mut env = runtime::Environment::new();
+        async_std::task::block_on(built_in_function::set_default(&mut env));
+        async_std::task::block_on(game_system::set_default(&mut env, None));
+        async_std::task::block_on(env.built_in_functions())
+            .into_iter()
+            .map(|(name, help)| (name.to_string(), help.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn every_function_has_help() {
+        for (name, help) in built_in_functions() {
+            assert!(
+                help.contains("［説明］") && help.contains("［使用例］"),
+                "{}の説明がありません",
+                name
+            );
+            assert!(
+                help.trim_start()
+                    .starts_with(format!("{} :", name).as_str()),
+                "{}の説明が型から始まっていません",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn every_function_is_listed() {
+        let categorized: Vec<_> = built_in_function::CATEGORIES
+            .iter()
+            .flat_map(|(_, names)| names.iter())
+            .collect();
+        let system_functions: Vec<_> = game_system::systems()
+            .iter()
+            .flat_map(|system| system_functions(*system))
+            .collect();
+        let functions = built_in_functions();
+        for (name, _) in &functions {
+            assert!(
+                categorized.contains(&&name.as_str()) || system_functions.contains(name),
+                "{}が一覧にありません",
+                name
+            );
+        }
+        for name in categorized {
+            assert!(
+                functions.iter().any(|(defined, _)| defined == name),
+                "{}は定義されていません",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn syntax_examples_parse() {
+        for (topic, help) in SYNTAX {
+            let examples = help.split("［使用例］\n").nth(1).unwrap_or_default();
+            assert!(!examples.is_empty(), "{}の使用例がありません", topic);
+            for example in examples.lines() {
+                let example = example.split("//").next().unwrap_or_default().trim();
+                assert!(
+                    parser::parse(&parser::Syntax::Native, example).is_ok(),
+                    "{}の使用例 {} を解釈できません",
+                    topic,
+                    example
+                );
+            }
+        }
+        for (alias, name) in ALIASES {
+            assert!(
+                syntax(alias).is_some(),
+                "{}の参照先{}がありません",
+                alias,
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,553 @@
+use crate::game_system;
+use crate::locale::{self, Language, Message};
+use crate::parser;
+use crate::render::{self, Outcome, Rolled};
+use crate::roll;
+use crate::runtime::LogEntry;
+use crate::sheet::Sheet;
+use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+// 1回分のダイスの記録
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub timestamp: DateTime<Utc>,
+    pub guild_id: Option<u64>,
+    pub channel_id: u64,
+    pub user_id: u64,
+    pub user_name: String,
+    pub input: String,
+    pub syntax: String,
+    pub system: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub macros: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub me: Option<Sheet>,
+    // 以前の記録の経過は文字列のまま読み込む
+    pub log: Vec<LogEntry>,
+    pub value: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<Outcome>,
+    pub seed: u64,
+    #[serde(default)]
+    pub language: Language,
+    // シークレットダイスは公開されるまで内容を表示しない
+    #[serde(default)]
+    pub secret: bool,
+}
+
+impl Entry {
+    pub fn result(&self) -> String {
+        if self.secret {
+            Message::SecretDice.text()
+        } else {
+            self.value.clone()
+        }
+    }
+
+    // 入力とシードがあれば振り直して結果がわかるので、公開前のシークレットダイスでは隠す
+    pub fn shown_input(&self) -> &str {
+        if self.secret {
+            ""
+        } else {
+            &self.input
+        }
+    }
+
+    pub fn shown_seed(&self) -> String {
+        if self.secret {
+            String::new()
+        } else {
+            self.seed.to_string()
+        }
+    }
+
+    pub fn local_time(&self) -> String {
+        self.timestamp
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string()
+    }
+
+    // 記録された設定、シード、言語で振り直す
+    pub fn replay(&self) -> Result<Rolled, String> {
+        let syntax = parser::Syntax::find(&self.syntax).unwrap_or_default();
+        let system = match &self.system {
+            Some(name) => {
+                Some(game_system::find(name).ok_or_else(|| Message::UnknownSystem(name).text())?)
+            }
+            None => None,
+        };
+        locale::using(self.language, || {
+            roll::evaluate(
+                &syntax,
+                system,
+                &self.macros,
+                self.me.as_ref(),
+                self.seed,
+                &self.input,
+            )
+        })
+    }
+}
+
+// JSON Linesのファイルに追記していく記録
+pub struct History {
+    path: PathBuf,
+    // 開いたときにファイルから読み込んだチャンネルごとの記録。以降はファイルと一緒に更新する
+    cache: Mutex<HashMap<u64, Vec<Entry>>>,
+    // 追記と書き直しが混ざらないよう、ファイルには1つずつ書き込む
+    writing: tokio::sync::Mutex<()>,
+}
+
+impl History {
+    pub fn open(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let mut cache: HashMap<u64, Vec<Entry>> = HashMap::new();
+        for entry in Self::load(&path) {
+            cache.entry(entry.channel_id).or_default().push(entry);
+        }
+        Self {
+            path,
+            cache: Mutex::new(cache),
+            writing: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    // ファイルの全ての記録を古い順に読み込む
+    pub fn load(path: impl AsRef<Path>) -> Vec<Entry> {
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(_) => return vec![],
+        };
+        std::io::BufReader::new(file)
+            .lines()
+            .filter_map(|line| serde_json::from_str(&line.ok()?).ok())
+            .collect()
+    }
+
+    fn cache(&self) -> MutexGuard<'_, HashMap<u64, Vec<Entry>>> {
+        self.cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // ファイルへの書き込みは、ハンドラーを止めないよう別のスレッドで行う
+    async fn write(&self, f: impl FnOnce(&Path) -> std::io::Result<()> + Send + 'static) {
+        let _writing = self.writing.lock().await;
+        let path = self.path.clone();
+        let res = tokio::task::spawn_blocking(move || f(&path))
+            .await
+            .unwrap_or_else(|why| Err(std::io::Error::other(why)));
+        if let Err(why) = res {
+            println!("Failed to write {}: {:?}", self.path.display(), why);
+        }
+    }
+
+    pub async fn append(&self, entry: Entry) {
+        let line = match serde_json::to_string(&entry) {
+            Ok(line) => line,
+            Err(why) => {
+                println!("Failed to write {}: {:?}", self.path.display(), why);
+                return;
+            }
+        };
+        self.cache()
+            .entry(entry.channel_id)
+            .or_default()
+            .push(entry);
+        self.write(move |path| {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?;
+            writeln!(file, "{}", line)
+        })
+        .await;
+    }
+
+    // シークレットダイスの記録を公開済みにする。記録は追記していくので、ファイル全体を書き直す
+    pub async fn reveal(&self, channel_id: u64, seed: u64) {
+        for entry in self.cache().get_mut(&channel_id).into_iter().flatten() {
+            if entry.seed == seed {
+                entry.secret = false;
+            }
+        }
+        self.write(move |path| {
+            let lines = std::fs::read_to_string(path)?;
+            let mut res = String::new();
+            for line in lines.lines() {
+                match serde_json::from_str::<Entry>(line) {
+                    Ok(mut entry)
+                        if entry.secret && entry.channel_id == channel_id && entry.seed == seed =>
+                    {
+                        entry.secret = false;
+                        res += serde_json::to_string(&entry)?.as_str();
+                    }
+                    _ => res += line,
+                }
+                res += "\n";
+            }
+            // 書き込み途中で落ちても記録が壊れないよう、一時ファイルに書いてから置き換える
+            let tmp = path.with_extension("tmp");
+            std::fs::write(&tmp, res)?;
+            std::fs::rename(&tmp, path)
+        })
+        .await;
+    }
+
+    // チャンネルの記録のうち、条件に合うものを古い順に返す
+    pub fn entries(&self, channel_id: u64, filter: impl Fn(&Entry) -> bool) -> Vec<Entry> {
+        self.cache()
+            .get(&channel_id)
+            .into_iter()
+            .flatten()
+            .filter(|entry| filter(entry))
+            .cloned()
+            .collect()
+    }
+
+    // チャンネルの最近の記録をcount件、古い順に返す
+    pub fn recent(&self, channel_id: u64, count: usize) -> Vec<Entry> {
+        self.cache()
+            .get(&channel_id)
+            .map(|entries| entries[entries.len().saturating_sub(count)..].to_vec())
+            .unwrap_or_default()
+    }
+}
+
+// 期間の指定。YYYY-MM-DDの形で、開始日と終了日を含む
+#[derive(Default)]
+pub struct Range {
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+}
+
+impl Range {
+    pub fn parse(since: Option<&str>, until: Option<&str>) -> Result<Self, String> {
+        let date = |date: &str, days: i64| {
+            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| Local.from_local_date(&date).single())
+                .map(|date| {
+                    (date.and_hms(0, 0, 0) + chrono::Duration::days(days)).with_timezone(&Utc)
+                })
+                .ok_or_else(|| Message::NotDate(date).text())
+        };
+        Ok(Self {
+            since: since.map(|since| date(since, 0)).transpose()?,
+            until: until.map(|until| date(until, 1)).transpose()?,
+        })
+    }
+
+    pub fn contains(&self, entry: &Entry) -> bool {
+        self.since.is_none_or(|since| since <= entry.timestamp)
+            && self.until.is_none_or(|until| entry.timestamp < until)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Markdown,
+    Csv,
+    Html,
+}
+
+impl Format {
+    pub fn find(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "md" | "markdown" => Some(Self::Markdown),
+            "csv" => Some(Self::Csv),
+            "html" => Some(Self::Html),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Markdown => "md",
+            Self::Csv => "csv",
+            Self::Html => "html",
+        }
+    }
+
+    pub fn export(self, entries: &[Entry]) -> String {
+        match self {
+            Self::Markdown => markdown(entries),
+            Self::Csv => csv(entries),
+            Self::Html => html(entries),
+        }
+    }
+}
+
+fn markdown(entries: &[Entry]) -> String {
+    let mut res = format!("# {}\n", Message::DiceLog.text());
+    for entry in entries {
+        res += format!("\n## {} {}\n\n", entry.local_time(), entry.user_name).as_str();
+        if !entry.secret {
+            res += format!("`{}`\n\n", entry.input).as_str();
+            for a_line in &entry.log {
+                res += format!("- {}\n", render::log_line(a_line)).as_str();
+            }
+        }
+        res += format!("- **{}**\n", entry.result()).as_str();
+    }
+    res
+}
+
+fn csv(entries: &[Entry]) -> String {
+    let quote = |field: &str| format!("\"{}\"", field.replace('"', "\"\""));
+    let mut res = String::from("timestamp,user_id,user_name,input,log,value,seed\n");
+    for entry in entries {
+        let log = if entry.secret {
+            String::new()
+        } else {
+            entry
+                .log
+                .iter()
+                .map(render::log_line)
+                .collect::<Vec<_>>()
+                .join(" -> ")
+        };
+        res += format!(
+            "{},{},{},{},{},{},{}\n",
+            entry.timestamp.to_rfc3339(),
+            entry.user_id,
+            quote(&entry.user_name),
+            quote(entry.shown_input()),
+            quote(&log),
+            quote(&entry.result()),
+            entry.shown_seed()
+        )
+        .as_str();
+    }
+    res
+}
+
+fn html(entries: &[Entry]) -> String {
+    let escape = |text: &str| {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    };
+    let mut res = format!(
+        "<!DOCTYPE html>\n<html lang=\"{}\">\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n<table>\n<tr><th>{}</th><th>{}</th><th>{}</th><th>{}</th><th>{}</th></tr>\n",
+        locale::current().name(),
+        Message::DiceLog.text(),
+        Message::TimeColumn.text(),
+        Message::UserColumn.text(),
+        Message::InputColumn.text(),
+        Message::LogColumn.text(),
+        Message::ResultField.text(),
+    );
+    for entry in entries {
+        let log = if entry.secret {
+            String::new()
+        } else {
+            entry
+                .log
+                .iter()
+                .map(|a_line| escape(&render::log_line(a_line)))
+                .collect::<Vec<_>>()
+                .join("<br>")
+        };
+        res += format!(
+            "<tr><td>{}</td><td>{}</td><td><code>{}</code></td><td>{}</td><td>{}</td></tr>\n",
+            entry.local_time(),
+            escape(&entry.user_name),
+            escape(entry.shown_input()),
+            log,
+            escape(&entry.result())
+        )
+        .as_str();
+    }
+    res += "</table>\n</body>\n</html>\n";
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(channel_id: u64, seed: u64, input: &str, secret: bool) -> Entry {
+        let rolled = roll::evaluate(&parser::Syntax::Native, None, &[], None, seed, input).unwrap();
+        Entry {
+            timestamp: Utc::now(),
+            guild_id: None,
+            channel_id,
+            user_id: 1,
+            user_name: String::from("アリス"),
+            input: String::from(input),
+            syntax: String::from("Native"),
+            system: None,
+            macros: vec![],
+            me: None,
+            log: rolled.log.clone(),
+            value: format!("{}", rolled.value),
+            outcome: rolled.outcome(),
+            seed,
+            language: Language::Ja,
+            secret,
+        }
+    }
+
+    fn history(name: &str) -> History {
+        let path =
+            std::env::temp_dir().join(format!("dicebot-{}-{}.jsonl", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        History::open(path)
+    }
+
+    // ファイルへの書き込みはtokioのランタイムで行う
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Runtime::new().unwrap().block_on(future)
+    }
+
+    #[test]
+    fn reveal_secret() {
+        let history = history("reveal");
+        block_on(async {
+            history.append(entry(1, 10, "2d6", true)).await;
+            history.append(entry(1, 11, "2d6", true)).await;
+            history.append(entry(2, 10, "2d6", true)).await;
+            history.append(entry(1, 12, "2d6", false)).await;
+            history.reveal(1, 10).await;
+        });
+
+        let secrets = |entries: Vec<Entry>| -> Vec<_> {
+            entries
+                .iter()
+                .map(|entry| (entry.channel_id, entry.seed, entry.secret))
+                .collect()
+        };
+        assert_eq!(
+            secrets(history.entries(1, |_| true)),
+            [(1, 10, false), (1, 11, true), (1, 12, false)]
+        );
+        assert_eq!(secrets(history.entries(2, |_| true)), [(2, 10, true)]);
+        assert_eq!(
+            secrets(History::load(&history.path)),
+            [(1, 10, false), (1, 11, true), (2, 10, true), (1, 12, false)]
+        );
+        let _ = std::fs::remove_file(&history.path);
+    }
+
+    #[test]
+    fn read_file_once() {
+        let history = history("cache");
+        block_on(history.append(entry(1, 10, "2d6", false)));
+        assert_eq!(history.entries(1, |_| true).len(), 1);
+
+        // 開いた後は、このHistoryを通した追記だけが反映される
+        let line = serde_json::to_string(&entry(1, 11, "2d6", false)).unwrap();
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&history.path)
+            .unwrap();
+        writeln!(file, "{}", line).unwrap();
+        assert_eq!(history.entries(1, |_| true).len(), 1);
+
+        block_on(history.append(entry(1, 12, "2d6", false)));
+        let seeds: Vec<_> = history
+            .entries(1, |_| true)
+            .iter()
+            .map(|e| e.seed)
+            .collect();
+        assert_eq!(seeds, [10, 12]);
+        assert_eq!(History::open(&history.path).entries(1, |_| true).len(), 3);
+
+        // 最近の記録は、チャンネルごとに必要な件数だけ返す
+        let seeds: Vec<_> = history.recent(1, 1).iter().map(|e| e.seed).collect();
+        assert_eq!(seeds, [12]);
+        assert_eq!(history.recent(1, 10).len(), 2);
+        assert!(history.recent(2, 10).is_empty());
+        let _ = std::fs::remove_file(&history.path);
+    }
+
+    #[test]
+    fn filter_by_date_range() {
+        let at = |y, m, d, h| Entry {
+            timestamp: Local.ymd(y, m, d).and_hms(h, 0, 0).with_timezone(&Utc),
+            ..entry(1, 0, "1d6", false)
+        };
+        let range = Range::parse(Some("2021-01-01"), Some("2021-01-31")).unwrap();
+        assert!(!range.contains(&at(2020, 12, 31, 23)));
+        assert!(range.contains(&at(2021, 1, 1, 0)));
+        assert!(range.contains(&at(2021, 1, 31, 23)));
+        assert!(!range.contains(&at(2021, 2, 1, 0)));
+
+        let since = Range::parse(Some("2021-01-01"), None).unwrap();
+        assert!(since.contains(&at(2030, 1, 1, 0)));
+        assert!(Range::default().contains(&at(2000, 1, 1, 0)));
+
+        assert_eq!(
+            Range::parse(Some("2021-13-01"), None).err().unwrap(),
+            "2021-13-01は日付ではありません（例：2021-01-31）"
+        );
+        assert!(Range::parse(Some("2021-01-01"), Some("2021/01/31")).is_err());
+    }
+
+    #[test]
+    fn export_formats() {
+        let entry = Entry {
+            user_name: String::from("\"ボブ\" & <アリス>"),
+            ..entry(1, 10, "max_of 1 [1, 2d6]", false)
+        };
+        let log: Vec<_> = entry.log.iter().map(render::log_line).collect();
+        let entries = [entry.clone(), entry.clone()];
+
+        let markdown = Format::Markdown.export(&entries);
+        assert!(markdown.starts_with("# ダイスの記録\n"));
+        assert_eq!(markdown.matches("`max_of 1 [1, 2d6]`").count(), 2);
+        assert!(markdown.contains(&format!("- {}\n", log[0])));
+        assert!(markdown.contains(&format!("- **{}**\n", entry.value)));
+
+        let csv = Format::Csv.export(&entries);
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines[0], "timestamp,user_id,user_name,input,log,value,seed");
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].contains(r#","""ボブ"" & <アリス>","max_of 1 [1, 2d6]","#));
+        assert!(lines[1].contains(&log.join(" -> ")));
+        assert!(lines[1].ends_with(",10"));
+
+        let html = Format::Html.export(&entries);
+        assert!(html.contains("<td>&quot;ボブ&quot; &amp; &lt;アリス&gt;</td>"));
+        assert!(html.contains("<code>max_of 1 [1, 2d6]</code>"));
+        assert_eq!(html.matches("<tr><td>").count(), 2);
+        assert!(html.ends_with("</table>\n</body>\n</html>\n"));
+    }
+
+    #[test]
+    fn hide_secret_in_exports() {
+        let secret = entry(1, 10, "1d100", true);
+        let revealed = Entry {
+            secret: false,
+            ..secret.clone()
+        };
+        for format in &[Format::Markdown, Format::Csv, Format::Html] {
+            let exported = format.export(std::slice::from_ref(&secret));
+            assert!(exported.contains("（シークレットダイス）"));
+            assert!(!exported.contains(&render::log_line(&secret.log[0])));
+            assert!(!exported.contains("1d100"));
+
+            let exported = format.export(std::slice::from_ref(&revealed));
+            assert!(!exported.contains("（シークレットダイス）"));
+            assert!(exported.contains(&render::log_line(&revealed.log[0])));
+            assert!(exported.contains("1d100"));
+        }
+
+        // 振り直せないよう、公開前はシードも書き出さない
+        let csv = Format::Csv.export(&[secret, revealed]);
+        let lines: Vec<_> = csv.lines().collect();
+        assert!(lines[1].ends_with(",\"（シークレットダイス）\","));
+        assert!(lines[2].ends_with(",10"));
+    }
+}
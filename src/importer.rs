@@ -0,0 +1,209 @@
+use crate::locale::Message;
+use crate::sheet::{Field, Sheet};
+use serde_json::Value;
+
+mod ccfolia;
+mod charaeno;
+mod ytsheet;
+
+// 外部のキャラクターシートのJSONを読み込む
+pub trait Importer: Sync {
+    // 形式の名前
+    fn name(&self) -> &'static str;
+
+    // この形式でなければNoneを返す
+    fn import(&self, json: &Value) -> Option<Sheet>;
+}
+
+const IMPORTERS: &[&dyn Importer] = &[&charaeno::Charaeno, &ccfolia::Ccfolia, &ytsheet::Ytsheet];
+
+// 形式を判別して読み込み、形式の名前とキャラクターシートを返す
+pub fn import(json: &str) -> Result<(&'static str, Sheet), String> {
+    let json: Value =
+        serde_json::from_str(json).map_err(|why| Message::NotJson(&why.to_string()).text())?;
+    IMPORTERS
+        .iter()
+        .find_map(|importer| importer.import(&json).map(|sheet| (importer.name(), sheet)))
+        .ok_or_else(|| {
+            let names: Vec<_> = IMPORTERS.iter().map(|importer| importer.name()).collect();
+            Message::UnsupportedSheet(&names.join(", ")).text()
+        })
+}
+
+// 数値と数字の文字列のどちらでも整数として読む
+fn integer(json: &Value) -> Option<i64> {
+    match json {
+        Value::Number(n) => n.as_i64().or_else(|| n.as_f64().map(|n| n as i64)),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+// keysのうち最初に見つかった値をfieldとして設定する
+fn insert_integer(sheet: &mut Sheet, field: &str, json: &Value, keys: &[&str]) {
+    if let Some(n) = keys.iter().find_map(|key| json.get(key).and_then(integer)) {
+        sheet.insert(String::from(field), Field::Integer(n));
+    }
+}
+
+fn insert_string(sheet: &mut Sheet, field: &str, json: &Value, keys: &[&str]) {
+    let s = keys
+        .iter()
+        .find_map(|key| json.get(key).and_then(Value::as_str))
+        .filter(|s| !s.is_empty());
+    if let Some(s) = s {
+        sheet.insert(String::from(field), Field::String(String::from(s)));
+    }
+}
+
+// CoCの技能名をmeの後ろに書ける名前にする。対応表にない技能は元の名前のまま me."技能名" で参照する
+fn coc_skill(name: &str) -> String {
+    let name = name.trim();
+    COC_SKILLS
+        .iter()
+        .find(|(ja, _)| *ja == name)
+        .map_or_else(|| String::from(name), |(_, en)| format!("skill_{}", en))
+}
+
+const COC_SKILLS: &[(&str, &str)] = &[
+    ("威圧", "intimidate"),
+    ("言いくるめ", "fast_talk"),
+    ("医学", "medicine"),
+    ("運転（自動車）", "drive_auto"),
+    ("応急手当", "first_aid"),
+    ("オカルト", "occult"),
+    ("回避", "dodge"),
+    ("鍵開け", "locksmith"),
+    ("鑑定", "appraise"),
+    ("機械修理", "mechanical_repair"),
+    ("聞き耳", "listen"),
+    ("近接戦闘（格闘）", "fighting_brawl"),
+    ("クトゥルフ神話", "cthulhu_mythos"),
+    ("経理", "accounting"),
+    ("考古学", "archaeology"),
+    ("コンピューター", "computer_use"),
+    ("サバイバル", "survival"),
+    ("写真術", "photography"),
+    ("射撃（拳銃）", "firearms_handgun"),
+    ("射撃（ライフル／ショットガン）", "firearms_rifle"),
+    ("重機械操作", "operate_heavy_machinery"),
+    ("乗馬", "ride"),
+    ("信用", "credit_rating"),
+    ("心理学", "psychology"),
+    ("人類学", "anthropology"),
+    ("水泳", "swim"),
+    ("精神分析", "psychoanalysis"),
+    ("説得", "persuade"),
+    ("跳躍", "jump"),
+    ("追跡", "track"),
+    ("手さばき", "sleight_of_hand"),
+    ("電気修理", "electrical_repair"),
+    ("電子工学", "electronics"),
+    ("投擲", "throw"),
+    ("登攀", "climb"),
+    ("図書館", "library_use"),
+    ("隠密", "stealth"),
+    ("ナビゲート", "navigate"),
+    ("博物学", "natural_world"),
+    ("変装", "disguise"),
+    ("法律", "law"),
+    ("魅惑", "charm"),
+    ("目星", "spot"),
+    ("歴史", "history"),
+    // 6版の技能
+    ("キック", "kick"),
+    ("組み付き", "grapple"),
+    ("こぶし（パンチ）", "fist"),
+    ("頭突き", "head_butt"),
+    ("隠れる", "hide"),
+    ("隠す", "conceal"),
+    ("忍び歩き", "sneak"),
+    ("化学", "chemistry"),
+    ("生物学", "biology"),
+    ("地質学", "geology"),
+    ("天文学", "astronomy"),
+    ("物理学", "physics"),
+    ("薬学", "pharmacy"),
+    ("製作", "craft"),
+    ("母国語", "own_language"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(sheet: &Sheet, key: &str) -> String {
+        match sheet.get(key) {
+            Some(Field::Integer(n)) => n.to_string(),
+            Some(Field::String(s)) => s.clone(),
+            None => String::from("None"),
+        }
+    }
+
+    #[test]
+    fn imports_charaeno() {
+        let (name, sheet) = import(include_str!("../tests/fixtures/charaeno.json")).unwrap();
+        assert_eq!(name, "Charaeno");
+        assert_eq!(field(&sheet, "name"), "朝霧 透");
+        assert_eq!(field(&sheet, "str"), "50");
+        assert_eq!(field(&sheet, "edu"), "75");
+        assert_eq!(field(&sheet, "hp"), "11");
+        assert_eq!(field(&sheet, "san"), "60");
+        assert_eq!(field(&sheet, "db"), "0");
+        assert_eq!(field(&sheet, "skill_spot"), "70");
+        assert_eq!(field(&sheet, "skill_library_use"), "65");
+        assert_eq!(field(&sheet, "芸術／製作（写真）"), "40");
+    }
+
+    #[test]
+    fn imports_iachara() {
+        let (name, sheet) = import(include_str!("../tests/fixtures/iachara.json")).unwrap();
+        assert_eq!(name, "いあきゃら");
+        assert_eq!(field(&sheet, "name"), "久遠寺 灯");
+        assert_eq!(field(&sheet, "hp"), "12");
+        assert_eq!(field(&sheet, "san"), "55");
+        assert_eq!(field(&sheet, "dex"), "70");
+        assert_eq!(field(&sheet, "skill_spot"), "80");
+        assert_eq!(field(&sheet, "skill_psychology"), "45");
+        assert_eq!(field(&sheet, "skill_dodge"), "35");
+        // 能力値の判定のコマンドは技能として取り込まない
+        assert_eq!(field(&sheet, "SAN"), "None");
+    }
+
+    #[test]
+    fn imports_ytsheet_sword_world() {
+        let (name, sheet) = import(include_str!("../tests/fixtures/ytsheet_sw2.json")).unwrap();
+        assert_eq!(name, "ゆとシートⅡ");
+        assert_eq!(field(&sheet, "name"), "ラルフ");
+        assert_eq!(field(&sheet, "level"), "5");
+        assert_eq!(field(&sheet, "dex"), "18");
+        assert_eq!(field(&sheet, "dex_bonus"), "3");
+        assert_eq!(field(&sheet, "hp"), "34");
+        assert_eq!(field(&sheet, "fighter"), "5");
+        assert_eq!(field(&sheet, "scout"), "3");
+        assert_eq!(field(&sheet, "vit_resist"), "9");
+    }
+
+    #[test]
+    fn imports_ytsheet_double_cross() {
+        let (name, sheet) = import(include_str!("../tests/fixtures/ytsheet_dx3.json")).unwrap();
+        assert_eq!(name, "ゆとシートⅡ");
+        assert_eq!(field(&sheet, "name"), "黒峰 蓮");
+        assert_eq!(field(&sheet, "body"), "3");
+        assert_eq!(field(&sheet, "sense"), "5");
+        assert_eq!(field(&sheet, "hp"), "29");
+        assert_eq!(field(&sheet, "initiative"), "12");
+        assert_eq!(field(&sheet, "encroach"), "34");
+        assert_eq!(field(&sheet, "ranged"), "4");
+    }
+
+    #[test]
+    fn rejects_unknown_format() {
+        let err = |json| import(json).err().unwrap();
+        assert!(err("{\"foo\": 1}").starts_with("対応していない形式です"));
+        assert!(err("not json").starts_with("JSONとして読み込めません"));
+        // エラーは現在の言語で返す
+        let en = crate::locale::using(crate::locale::Language::En, || err("{}"));
+        assert!(en.starts_with("Unsupported format"), "{}", en);
+    }
+}
@@ -0,0 +1,61 @@
+use super::{coc_skill, insert_string, integer, Importer};
+use crate::sheet::{self, Field, Sheet};
+use regex::Regex;
+use serde_json::Value;
+
+// いあきゃらのココフォリア形式の出力
+pub struct Ccfolia;
+
+impl Importer for Ccfolia {
+    fn name(&self) -> &'static str {
+        "いあきゃら"
+    }
+
+    fn import(&self, json: &Value) -> Option<Sheet> {
+        if json.get("kind")?.as_str()? != "character" {
+            return None;
+        }
+        let data = json.get("data")?;
+        let mut sheet = Sheet::new();
+
+        insert_string(&mut sheet, "name", data, &["name"]);
+
+        // HP、MP、SANなどのステータスと、STRなどの能力値
+        let labeled = ["status", "params"]
+            .iter()
+            .filter_map(|key| data.get(key).and_then(Value::as_array))
+            .flatten();
+        for item in labeled {
+            let label = match item.get("label").and_then(Value::as_str) {
+                Some(label) if sheet::is_field_name(label) => label.to_ascii_lowercase(),
+                _ => continue,
+            };
+            match item.get("value") {
+                Some(value) => match integer(value) {
+                    Some(n) => sheet.insert(label, Field::Integer(n)),
+                    None => match value.as_str() {
+                        Some(s) => sheet.insert(label, Field::String(String::from(s))),
+                        None => continue,
+                    },
+                },
+                None => continue,
+            };
+        }
+
+        // 技能はチャットパレットの「CCB<=70 【目星】」のような行から読む
+        let command = Regex::new(r"^\s*CCB?(?:\(-?\d+\))?<=(\d+)\s*【(.+?)】").unwrap();
+        let commands = data
+            .get("commands")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        for line in commands.lines() {
+            if let Some(caps) = command.captures(line) {
+                if let Ok(n) = caps[1].parse() {
+                    sheet.insert(coc_skill(&caps[2]), Field::Integer(n));
+                }
+            }
+        }
+
+        Some(sheet)
+    }
+}
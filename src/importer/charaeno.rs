@@ -0,0 +1,44 @@
+use super::{coc_skill, insert_integer, insert_string, integer, Importer};
+use crate::sheet::{Field, Sheet};
+use serde_json::Value;
+
+// キャラエノ（CoC7版）のJSON
+pub struct Charaeno;
+
+const CHARACTERISTICS: &[&str] = &["str", "con", "pow", "dex", "app", "siz", "int", "edu"];
+const ATTRIBUTES: &[&str] = &["hp", "mp", "san", "luck", "mov", "build"];
+
+impl Importer for Charaeno {
+    fn name(&self) -> &'static str {
+        "Charaeno"
+    }
+
+    fn import(&self, json: &Value) -> Option<Sheet> {
+        let characteristics = json.get("characteristics")?.as_object()?;
+        let skills = json.get("skills")?.as_array()?;
+        let mut sheet = Sheet::new();
+
+        insert_string(&mut sheet, "name", json, &["name"]);
+        for key in CHARACTERISTICS {
+            if let Some(n) = characteristics.get(*key).and_then(integer) {
+                sheet.insert(String::from(*key), Field::Integer(n));
+            }
+        }
+        if let Some(attribute) = json.get("attribute") {
+            for key in ATTRIBUTES {
+                insert_integer(&mut sheet, key, attribute, &[key]);
+            }
+            // ダメージボーナスは「+1D4」のようなダイス式なので文字列のまま持つ
+            insert_string(&mut sheet, "db", attribute, &["db"]);
+        }
+        for skill in skills {
+            let name = skill.get("name").and_then(Value::as_str);
+            let value = skill.get("value").and_then(integer);
+            if let (Some(name), Some(value)) = (name, value) {
+                sheet.insert(coc_skill(name), Field::Integer(value));
+            }
+        }
+
+        Some(sheet)
+    }
+}
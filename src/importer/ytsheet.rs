@@ -0,0 +1,84 @@
+use super::{insert_integer, insert_string, Importer};
+use crate::sheet::Sheet;
+use serde_json::Value;
+
+// ゆとシートⅡのJSON（ソード・ワールド2.0/2.5とダブルクロス The 3rd Edition）
+pub struct Ytsheet;
+
+const SWORD_WORLD: &[(&str, &[&str])] = &[
+    ("level", &["level"]),
+    ("dex", &["sttDex"]),
+    ("agi", &["sttAgi"]),
+    ("str", &["sttStr"]),
+    ("vit", &["sttVit"]),
+    ("int", &["sttInt"]),
+    ("mnd", &["sttMnd"]),
+    ("dex_bonus", &["bonusDex"]),
+    ("agi_bonus", &["bonusAgi"]),
+    ("str_bonus", &["bonusStr"]),
+    ("vit_bonus", &["bonusVit"]),
+    ("int_bonus", &["bonusInt"]),
+    ("mnd_bonus", &["bonusMnd"]),
+    ("hp", &["hpTotal"]),
+    ("mp", &["mpTotal"]),
+    ("vit_resist", &["vitResistTotal"]),
+    ("mnd_resist", &["mndResistTotal"]),
+    ("fighter", &["lvFig"]),
+    ("grappler", &["lvGra"]),
+    ("fencer", &["lvFen"]),
+    ("shooter", &["lvSho"]),
+    ("sorcerer", &["lvSor"]),
+    ("conjurer", &["lvCon"]),
+    ("priest", &["lvPri"]),
+    ("fairytamer", &["lvFai"]),
+    ("magitech", &["lvMag"]),
+    ("scout", &["lvSco"]),
+    ("ranger", &["lvRan"]),
+    ("sage", &["lvSag"]),
+    ("enhancer", &["lvEnh"]),
+    ("bard", &["lvBar"]),
+    ("rider", &["lvRid"]),
+    ("alchemist", &["lvAlc"]),
+];
+
+const DOUBLE_CROSS: &[(&str, &[&str])] = &[
+    ("body", &["sttTotalBody", "sttBody"]),
+    ("sense", &["sttTotalSense", "sttSense"]),
+    ("mind", &["sttTotalMind", "sttMind"]),
+    ("social", &["sttTotalSocial", "sttSocial"]),
+    ("hp", &["maxHpTotal"]),
+    ("initiative", &["initiativeTotal"]),
+    ("encroach", &["baseEncroach"]),
+    ("melee", &["skillTotalMelee", "skillMelee"]),
+    ("dodge", &["skillTotalDodge", "skillDodge"]),
+    ("ranged", &["skillTotalRanged", "skillRanged"]),
+    ("percept", &["skillTotalPercept", "skillPercept"]),
+    ("rc", &["skillTotalRC", "skillRC"]),
+    ("will", &["skillTotalWill", "skillWill"]),
+    ("negotiate", &["skillTotalNegotiate", "skillNegotiate"]),
+    ("procure", &["skillTotalProcure", "skillProcure"]),
+];
+
+impl Importer for Ytsheet {
+    fn name(&self) -> &'static str {
+        "ゆとシートⅡ"
+    }
+
+    fn import(&self, json: &Value) -> Option<Sheet> {
+        let fields = if json.get("sttBody").is_some() || json.get("sttTotalBody").is_some() {
+            DOUBLE_CROSS
+        } else if json.get("sttDex").is_some() {
+            SWORD_WORLD
+        } else {
+            return None;
+        };
+        let mut sheet = Sheet::new();
+
+        insert_string(&mut sheet, "name", json, &["characterName"]);
+        for (field, keys) in fields {
+            insert_integer(&mut sheet, field, json, keys);
+        }
+
+        Some(sheet)
+    }
+}
@@ -0,0 +1,243 @@
+use crate::locale::Message;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Combatant {
+    pub name: String,
+    pub initiative: i64,
+}
+
+// チャンネルごとの戦闘の状態
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Encounter {
+    round: u32,
+    // 現在の手番の参加者の位置
+    turn: usize,
+    // イニシアチブの高い順に並べる
+    combatants: Vec<Combatant>,
+}
+
+impl Default for Encounter {
+    fn default() -> Self {
+        Self {
+            round: 1,
+            turn: 0,
+            combatants: vec![],
+        }
+    }
+}
+
+impl Encounter {
+    pub fn current(&self) -> Option<&Combatant> {
+        self.combatants.get(self.turn)
+    }
+
+    // 同じイニシアチブの場合は先に追加した方を先にする。同じ名前の参加者は置き換える
+    pub fn add(&mut self, name: &str, initiative: i64) {
+        // 手番が回り始めた後は、手番の参加者が変わらないようにする
+        let started = self.round > 1 || self.turn > 0;
+        let current = match self.current() {
+            // 手番の参加者を置き換える場合は、removeと同様に次の参加者の手番にする
+            Some(combatant) if started && combatant.name == name => {
+                match self.combatants.get(self.turn + 1) {
+                    Some(next) => Some(next.name.clone()),
+                    None => {
+                        self.round += 1;
+                        None
+                    }
+                }
+            }
+            combatant => combatant.map(|combatant| combatant.name.clone()),
+        };
+
+        self.combatants.retain(|combatant| combatant.name != name);
+        let idx = self
+            .combatants
+            .iter()
+            .position(|combatant| combatant.initiative < initiative)
+            .unwrap_or(self.combatants.len());
+        self.combatants.insert(
+            idx,
+            Combatant {
+                name: String::from(name),
+                initiative,
+            },
+        );
+
+        if started {
+            self.turn = current
+                .and_then(|current| {
+                    self.combatants
+                        .iter()
+                        .position(|combatant| combatant.name == current)
+                })
+                .unwrap_or(0);
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Combatant> {
+        let idx = self
+            .combatants
+            .iter()
+            .position(|combatant| combatant.name == name)?;
+        let removed = self.combatants.remove(idx);
+        if idx < self.turn {
+            self.turn -= 1;
+        }
+        // 最後の参加者の手番で外した場合は、nextと同様に次のラウンドになる
+        if self.turn >= self.combatants.len() {
+            self.turn = 0;
+            if !self.combatants.is_empty() {
+                self.round += 1;
+            }
+        }
+        Some(removed)
+    }
+
+    // 手番を次の参加者に回す。最後の参加者の次は次のラウンドになる
+    pub fn next(&mut self) -> Option<&Combatant> {
+        if self.combatants.is_empty() {
+            return None;
+        }
+        self.turn += 1;
+        if self.turn >= self.combatants.len() {
+            self.turn = 0;
+            self.round += 1;
+        }
+        self.current()
+    }
+}
+
+impl std::fmt::Display for Encounter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", Message::Round(self.round).text())?;
+        if self.combatants.is_empty() {
+            write!(f, "\n{}", Message::NoCombatants.text())?;
+        }
+        for (idx, combatant) in self.combatants.iter().enumerate() {
+            let mark = if idx == self.turn { "▶" } else { "　" };
+            write!(
+                f,
+                "\n{} {:>3} {}",
+                mark, combatant.initiative, combatant.name
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(encounter: &Encounter) -> Vec<&str> {
+        encounter
+            .combatants
+            .iter()
+            .map(|combatant| combatant.name.as_str())
+            .collect()
+    }
+
+    fn state(encounter: &Encounter) -> (u32, Option<&str>) {
+        (
+            encounter.round,
+            encounter.current().map(|combatant| combatant.name.as_str()),
+        )
+    }
+
+    fn encounter() -> Encounter {
+        let mut encounter = Encounter::default();
+        encounter.add("ゴブリン", 8);
+        encounter.add("アリス", 12);
+        encounter.add("ボブ", 8);
+        encounter.add("キャロル", 10);
+        encounter
+    }
+
+    #[test]
+    fn sort_by_initiative() {
+        let mut encounter = encounter();
+        // 同じイニシアチブなら先に追加した方が先
+        assert_eq!(
+            order(&encounter),
+            ["アリス", "キャロル", "ゴブリン", "ボブ"]
+        );
+        assert_eq!(state(&encounter), (1, Some("アリス")));
+
+        // 手番が回る前に追加した場合は、最もイニシアチブの高い参加者から始める
+        encounter.add("ドラゴン", 20);
+        assert_eq!(state(&encounter), (1, Some("ドラゴン")));
+
+        // 同じ名前で追加すると置き換える
+        encounter.add("アリス", 5);
+        assert_eq!(
+            order(&encounter),
+            ["ドラゴン", "キャロル", "ゴブリン", "ボブ", "アリス"]
+        );
+    }
+
+    #[test]
+    fn advance_turns() {
+        let mut encounter = encounter();
+        let names: Vec<_> = (0..4)
+            .map(|_| encounter.next().unwrap().name.clone())
+            .collect();
+        assert_eq!(names, ["キャロル", "ゴブリン", "ボブ", "アリス"]);
+        assert_eq!(state(&encounter), (2, Some("アリス")));
+        assert!(Encounter::default().next().is_none());
+    }
+
+    #[test]
+    fn keep_current_turn_when_adding() {
+        let mut encounter = encounter();
+        encounter.next();
+        assert_eq!(state(&encounter), (1, Some("キャロル")));
+        encounter.add("ドラゴン", 20);
+        assert_eq!(state(&encounter), (1, Some("キャロル")));
+        encounter.add("ダン", 9);
+        assert_eq!(state(&encounter), (1, Some("キャロル")));
+        // 手番の参加者を置き換えると、まだ手番の来ていない次の参加者の手番になる
+        encounter.add("キャロル", 1);
+        assert_eq!(state(&encounter), (1, Some("ダン")));
+        let names: Vec<_> = (0..4)
+            .map(|_| encounter.next().unwrap().name.clone())
+            .collect();
+        assert_eq!(names, ["ゴブリン", "ボブ", "キャロル", "ドラゴン"]);
+        assert_eq!(encounter.round, 2);
+
+        // 最後の参加者の手番で置き換えると次のラウンドになる
+        let mut encounter = self::encounter();
+        for _ in 0..3 {
+            encounter.next();
+        }
+        assert_eq!(state(&encounter), (1, Some("ボブ")));
+        encounter.add("ボブ", 11);
+        assert_eq!(state(&encounter), (2, Some("アリス")));
+        assert_eq!(
+            order(&encounter),
+            ["アリス", "ボブ", "キャロル", "ゴブリン"]
+        );
+    }
+
+    #[test]
+    fn remove_combatants() {
+        let mut encounter = encounter();
+        encounter.next();
+        encounter.next();
+        assert_eq!(state(&encounter), (1, Some("ゴブリン")));
+
+        // 手番より前の参加者を外しても手番は変わらない
+        assert_eq!(encounter.remove("アリス").unwrap().name, "アリス");
+        assert_eq!(state(&encounter), (1, Some("ゴブリン")));
+        // 手番の参加者を外すと次の参加者の手番になる
+        encounter.remove("ゴブリン");
+        assert_eq!(state(&encounter), (1, Some("ボブ")));
+        // 最後の参加者の手番で外すと次のラウンドになる
+        encounter.remove("ボブ");
+        assert_eq!(state(&encounter), (2, Some("キャロル")));
+
+        assert!(encounter.remove("ボブ").is_none());
+        encounter.remove("キャロル");
+        assert_eq!(state(&encounter), (2, None));
+    }
+}
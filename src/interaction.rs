@@ -0,0 +1,227 @@
+use crate::game_system;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+const API_BASE: &str = "https://discord.com/api/v8";
+
+const CHANNEL_MESSAGE_WITH_SOURCE: u64 = 4;
+const EPHEMERAL: u64 = 1 << 6;
+
+// スラッシュコマンドのインタラクション
+pub struct Interaction {
+    id: String,
+    application_id: String,
+    token: String,
+    pub guild_id: Option<u64>,
+    pub channel_id: u64,
+    pub user_id: u64,
+    pub user_name: String,
+    pub roles: Vec<u64>,
+    pub name: String,
+    pub options: HashMap<String, Value>,
+}
+
+impl Interaction {
+    pub fn from_value(raw: &Value) -> Option<Self> {
+        let data = raw.get("data")?;
+        let options = data
+            .get("options")
+            .and_then(Value::as_array)
+            .map(|options| {
+                options
+                    .iter()
+                    .filter_map(|option| {
+                        let name = option.get("name")?.as_str()?;
+                        let value = option.get("value")?;
+                        Some((String::from(name), value.clone()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // サーバー内ではmember.user、DMではuserに呼び出したユーザーが入る
+        let member = raw.get("member");
+        let user = member
+            .and_then(|member| member.get("user"))
+            .or_else(|| raw.get("user"))?;
+        let roles = member
+            .and_then(|member| member.get("roles"))
+            .and_then(Value::as_array)
+            .map(|roles| {
+                roles
+                    .iter()
+                    .filter_map(|role| role.as_str()?.parse().ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Some(Self {
+            id: String::from(raw.get("id")?.as_str()?),
+            application_id: String::from(raw.get("application_id")?.as_str()?),
+            token: String::from(raw.get("token")?.as_str()?),
+            guild_id: raw
+                .get("guild_id")
+                .and_then(Value::as_str)
+                .and_then(|id| id.parse().ok()),
+            channel_id: raw.get("channel_id")?.as_str()?.parse().ok()?,
+            user_id: user.get("id")?.as_str()?.parse().ok()?,
+            user_name: String::from(user.get("username")?.as_str()?),
+            roles,
+            name: String::from(data.get("name")?.as_str()?),
+            options,
+        })
+    }
+
+    pub fn option_str(&self, name: &str) -> Option<&str> {
+        self.options.get(name).and_then(Value::as_str)
+    }
+
+    pub fn option_bool(&self, name: &str) -> Option<bool> {
+        self.options.get(name).and_then(Value::as_bool)
+    }
+
+    pub async fn respond(
+        &self,
+        client: &reqwest::Client,
+        content: &str,
+        embed: Option<&Value>,
+        ephemeral: bool,
+    ) -> reqwest::Result<()> {
+        let flags = if ephemeral { EPHEMERAL } else { 0 };
+        let data = match embed {
+            Some(embed) => json!({ "embeds": [embed], "flags": flags }),
+            None => json!({ "content": content, "flags": flags }),
+        };
+        let body = json!({
+            "type": CHANNEL_MESSAGE_WITH_SOURCE,
+            "data": data,
+        });
+        client
+            .post(&format!(
+                "{}/interactions/{}/{}/callback",
+                API_BASE, self.id, self.token
+            ))
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    // 応答した後に、続きのメッセージや添付ファイルを送る
+    pub async fn follow_up(
+        &self,
+        client: &reqwest::Client,
+        content: &str,
+        files: &[(String, Vec<u8>)],
+        ephemeral: bool,
+    ) -> reqwest::Result<()> {
+        let flags = if ephemeral { EPHEMERAL } else { 0 };
+        let payload = json!({ "content": content, "flags": flags });
+        let request = client.post(&format!(
+            "{}/webhooks/{}/{}",
+            API_BASE, self.application_id, self.token
+        ));
+        let request = if files.is_empty() {
+            request.json(&payload)
+        } else {
+            let form = files.iter().enumerate().fold(
+                reqwest::multipart::Form::new().text("payload_json", payload.to_string()),
+                |form, (idx, (name, file))| {
+                    let part =
+                        reqwest::multipart::Part::bytes(file.clone()).file_name(name.clone());
+                    form.part(format!("file{}", idx), part)
+                },
+            );
+            request.multipart(form)
+        };
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+fn commands() -> Value {
+    let systems: Vec<_> = game_system::systems()
+        .iter()
+        .map(|system| json!({ "name": system.title(), "value": system.name() }))
+        .collect();
+
+    json!([
+        {
+            "name": "roll",
+            "description": "ダイスを振ります",
+            "options": [
+                { "type": 3, "name": "expr", "description": "ダイス式（例：2d6+3）", "required": true },
+                { "type": 5, "name": "ephemeral", "description": "結果を自分だけに表示します" },
+                { "type": 5, "name": "hidden", "description": "シークレットダイスとして振り、結果を自分とGMにDMで送ります" }
+            ]
+        },
+        {
+            "name": "help",
+            "description": "使い方や関数の説明を表示します",
+            "options": [
+                { "type": 3, "name": "name", "description": "関数名" }
+            ]
+        },
+        {
+            "name": "system",
+            "description": "このチャンネルのゲームシステムを設定します",
+            "options": [
+                { "type": 3, "name": "name", "description": "ゲームシステム", "choices": systems }
+            ]
+        },
+        {
+            "name": "syntax",
+            "description": "このチャンネルの構文を設定します",
+            "options": [
+                {
+                    "type": 3,
+                    "name": "name",
+                    "description": "構文",
+                    "choices": [
+                        { "name": "標準", "value": "Native" },
+                        { "name": "BCDice互換", "value": "BCDice" }
+                    ]
+                }
+            ]
+        },
+        {
+            "name": "macro",
+            "description": "自分用のマクロを管理します",
+            "options": [
+                {
+                    "type": 3,
+                    "name": "action",
+                    "description": "操作",
+                    "required": true,
+                    "choices": [
+                        { "name": "保存", "value": "save" },
+                        { "name": "一覧", "value": "list" },
+                        { "name": "削除", "value": "delete" },
+                        { "name": "書き出し", "value": "export" }
+                    ]
+                },
+                { "type": 3, "name": "args", "description": "保存する定義（例：atk := 2d6+4）または削除するマクロ名" }
+            ]
+        }
+    ])
+}
+
+// 起動時にスラッシュコマンドを一括で登録する
+pub async fn register(
+    client: &reqwest::Client,
+    token: &str,
+    application_id: u64,
+) -> reqwest::Result<()> {
+    client
+        .put(&format!(
+            "{}/applications/{}/commands",
+            API_BASE, application_id
+        ))
+        .header("Authorization", token)
+        .json(&commands())
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
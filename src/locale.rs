@@ -0,0 +1,951 @@
+use crate::parser::Syntax;
+use crate::render::Style;
+use crate::runtime::Level;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::future::Future;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[default]
+    Ja,
+    En,
+}
+
+impl Language {
+    pub fn find(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "ja" | "japanese" | "日本語" => Some(Self::Ja),
+            "en" | "english" | "英語" => Some(Self::En),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Ja => "ja",
+            Self::En => "en",
+        }
+    }
+}
+
+thread_local! {
+    // ダイス式の評価と結果の表示に使う言語
+    static CURRENT: Cell<Language> = const { Cell::new(Language::Ja) };
+}
+
+pub fn current() -> Language {
+    CURRENT.with(Cell::get)
+}
+
+// fの実行中だけ言語を切り替える
+pub fn using<T>(language: Language, f: impl FnOnce() -> T) -> T {
+    let prev = CURRENT.with(|current| current.replace(language));
+    let res = f();
+    CURRENT.with(|current| current.set(prev));
+    res
+}
+
+// futureの実行中だけ言語を切り替える。pollのたびに切り替えるので、途中でスレッドが変わってもよい
+pub async fn scope<F: Future>(language: Language, future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    std::future::poll_fn(|cx| using(language, || future.as_mut().poll(cx))).await
+}
+
+// サーバー単位の設定の名前
+#[derive(Clone, Copy)]
+pub enum Setting {
+    Language,
+    Style,
+    GmRole,
+    DefaultSystem,
+    Prefix,
+    AutoRoll,
+    SafeMode,
+}
+
+impl Setting {
+    fn ja(self) -> &'static str {
+        match self {
+            Self::Language => "言語",
+            Self::Style => "表示形式",
+            Self::GmRole => "GMのロール",
+            Self::DefaultSystem => "サーバーの既定のシステム",
+            Self::Prefix => "接頭辞",
+            Self::AutoRoll => "自動ロール",
+            Self::SafeMode => "セーフモード",
+        }
+    }
+
+    fn en(self) -> &'static str {
+        match self {
+            Self::Language => "language",
+            Self::Style => "output style",
+            Self::GmRole => "GM role",
+            Self::DefaultSystem => "default system of this server",
+            Self::Prefix => "prefix",
+            Self::AutoRoll => "auto-roll",
+            Self::SafeMode => "safe mode",
+        }
+    }
+}
+
+// 言語によって変わる文言
+pub enum Message<'a> {
+    Success,
+    Failure,
+    Critical,
+    Fumble,
+    // 配列の中の成功の数
+    Successes(usize),
+    Error(&'a str),
+    // エラーになった部分式
+    ErrorSource(&'a str),
+    TimeLimit(u128),
+    // x と x? を同時に使った
+    MixedIdent(&'a str),
+    DiceField,
+    ResultField,
+    OutcomeField,
+    // クリティカルやファンブルを強調する
+    Emphasized(&'a str),
+    // 長すぎる結果の途中を省いた印
+    Omitted,
+    // 一覧の前に付ける見出し
+    Heading(&'a str),
+
+    // ゲームシステムの判定と引数のエラー
+    Level(Level),
+    BonusDiceLimit(i64),
+    DiceCountRange(i64),
+    CriticalValueMin(i64),
+    PowerRange(i64),
+
+    // ゲームシステムの名前と使い方
+    CthulhuTitle,
+    CthulhuHelp,
+    SwordWorldTitle,
+    SwordWorldHelp,
+    DoubleCrossTitle,
+    DoubleCrossHelp,
+
+    // サーバー単位の設定
+    ServerOnly(Setting),
+    Current(Setting, &'a str),
+    Reset(Setting),
+    NotSet,
+    Flag(Setting, bool),
+    LanguageSet(Language),
+    UnknownLanguage(&'a str),
+    StyleSet(Style),
+    UnknownStyle(&'a str),
+    PrefixSet(&'a str),
+    GmRoleSet(u64),
+    NotRole(&'a str),
+
+    // ゲームシステムと構文
+    SystemSet {
+        title: &'a str,
+        name: &'a str,
+        help: &'a str,
+    },
+    ChannelSystemReset,
+    CurrentSystem {
+        title: &'a str,
+        name: &'a str,
+        // サーバーの既定のシステムを使っている
+        inherited: bool,
+    },
+    NoSystem,
+    DefaultSystemSet {
+        title: &'a str,
+        name: &'a str,
+    },
+    UnknownSystem(&'a str),
+    AvailableSystems,
+    SyntaxSet(Syntax),
+    CurrentSyntax(Syntax),
+    UnknownSyntax(&'a str),
+    UnknownFunction(&'a str),
+
+    // 式を受け取るコマンド
+    ExpressionRequired(&'a str),
+    Explained(&'a str),
+
+    // シークレットダイス
+    SecretRolled {
+        user_id: u64,
+        id: usize,
+    },
+    SecretResult {
+        id: usize,
+        channel_id: u64,
+    },
+    SecretRevealed {
+        user_id: u64,
+        id: usize,
+    },
+    NoSecrets,
+    UnknownSecret(&'a str),
+    OthersSecret,
+    SecretDice,
+
+    // マクロ
+    MacroRequired,
+    MacroForm,
+    OneMacro,
+    MacroLimit(usize),
+    MacroSaved(&'a str),
+    MacroDeleted(&'a str),
+    MacroNameRequired,
+    UnknownMacro(&'a str),
+    NoMacros,
+    MacroList,
+
+    // キャラクターシート
+    Sheet(&'a str),
+    NoSheet,
+    SheetRegistered(&'a str),
+    SheetImported {
+        format: &'a str,
+        sheet: &'a str,
+    },
+    SheetForm,
+    NotJson(&'a str),
+    UnsupportedSheet(&'a str),
+    SheetRequired,
+    SheetDeleted,
+    InvalidFieldName(&'a str),
+    FieldType,
+    NotInteger,
+    NotIntegerField(&'a str),
+    UnknownField(&'a str),
+    AttachmentRequired,
+    DownloadFailed,
+    AttachmentTooLarge,
+    UnknownCommand(&'a str, &'a str),
+
+    // イニシアチブ表
+    Round(u32),
+    NoCombatants,
+    NotStarted,
+    EncounterStarted,
+    EncounterEnded,
+    InitiativeArgs,
+    InitiativeNotInteger,
+    Initiative {
+        name: &'a str,
+        initiative: i64,
+    },
+    Turn(&'a str),
+    NoTurn,
+    CombatantRemoved(&'a str),
+    UnknownCombatant(&'a str),
+
+    // ダイスの記録
+    CountNotNumber,
+    NoHistory,
+    RecentRolls,
+    NotDate(&'a str),
+    UnknownFormat(&'a str),
+    NothingToExport,
+    Exported(usize),
+    DiceLog,
+    TimeColumn,
+    UserColumn,
+    InputColumn,
+    LogColumn,
+
+    // 出目の統計
+    StatsPeriod,
+    NothingToSummarize,
+    StatsTitle(Option<&'a str>),
+    Period(&'a str, &'a str),
+    Rolls(usize),
+    DiceCount {
+        sides: i64,
+        count: u64,
+    },
+    Mean {
+        mean: f64,
+        expected: f64,
+    },
+    ChiSquare {
+        chi_square: f64,
+        df: usize,
+        p: f64,
+    },
+    Biased,
+    Unbiased,
+    FewRolls,
+    PlayerTally {
+        name: &'a str,
+        rolls: usize,
+        critical: usize,
+        fumble: usize,
+        success: usize,
+        failure: usize,
+    },
+    CriticalsField,
+    CriticalsFieldContinued,
+
+    // 確率分布
+    Exact,
+    MonteCarlo(usize),
+    SuccessRate(f64),
+    Margin(f64),
+    Probability {
+        input: &'a str,
+        method: &'a str,
+    },
+    Distribution {
+        input: &'a str,
+        method: &'a str,
+    },
+    MeanAndDeviation {
+        mean: f64,
+        deviation: f64,
+    },
+    Percentiles(&'a str),
+    Histogram,
+
+    // コマンドラインの機能
+    Usage,
+    RecordedResult(&'a str),
+    Replayed {
+        total: usize,
+        matched: usize,
+    },
+    Written(&'a str),
+}
+
+impl Message<'_> {
+    // 現在の言語での文言
+    pub fn text(&self) -> String {
+        self.text_in(current())
+    }
+
+    pub fn text_in(&self, language: Language) -> String {
+        match language {
+            Language::Ja => self.ja(),
+            Language::En => self.en(),
+        }
+    }
+
+    // 言語を設定する前でも読めるよう、両方の言語を並べる
+    pub fn both(&self) -> String {
+        format!("{}\n{}", self.ja(), self.en())
+    }
+
+    fn ja(&self) -> String {
+        match self {
+            Self::Success => String::from("成功"),
+            Self::Failure => String::from("失敗"),
+            Self::Critical => String::from("クリティカル"),
+            Self::Fumble => String::from("ファンブル"),
+            Self::Successes(n) => format!("{}成功", n),
+            Self::Error(err) => format!("エラー：{}", err),
+            Self::ErrorSource(source) => format!("（{}で発生）", source),
+            Self::TimeLimit(limit) => format!("TLE (Limit :{} ms)", limit),
+            Self::MixedIdent(name) => format!("{0}と{0}?を併用することはできません", name),
+            Self::DiceField => String::from("ダイス"),
+            Self::ResultField => String::from("結果"),
+            Self::OutcomeField => String::from("判定"),
+            Self::Emphasized(label) => format!("**{}！**", label),
+            Self::Omitted => String::from("（長すぎるため途中を省略しました）"),
+            Self::Heading(heading) => format!("{}：", heading),
+
+            Self::Level(level) => String::from(match level {
+                Level::Critical => "クリティカル",
+                Level::Extreme => "イクストリーム成功",
+                Level::Hard => "ハード成功",
+                Level::Regular => "レギュラー成功",
+                Level::Failure => "失敗",
+                Level::Fumble => "ファンブル",
+                Level::ClassicCritical => "決定的成功",
+                Level::ClassicSuccess => "成功",
+                Level::ClassicFumble => "致命的失敗",
+                Level::AutomaticFailure => "自動失敗",
+            }),
+            Self::BonusDiceLimit(limit) => {
+                format!("ボーナス・ペナルティダイスは{}個までです", limit)
+            }
+            Self::DiceCountRange(limit) => {
+                format!("ダイスの個数は1～{}で指定してください", limit)
+            }
+            Self::CriticalValueMin(min) => {
+                format!("クリティカル値は{}以上で指定してください", min)
+            }
+            Self::PowerRange(max) => format!("威力は0～{}で指定してください", max),
+
+            Self::CthulhuTitle => String::from("クトゥルフ神話TRPG 第7版"),
+            Self::CthulhuHelp => String::from(
+                "CC(ボーナス・ペナルティダイス)<=技能値 : 技能ロール\n\
+                CCB<=技能値 : 第6版形式の技能ロール",
+            ),
+            Self::SwordWorldTitle => String::from("ソード・ワールド2.x"),
+            Self::SwordWorldHelp => String::from(
+                "K威力[+修正値][@クリティカル値][r首切り上昇値][$+クリティカルレイ] : 威力表",
+            ),
+            Self::DoubleCrossTitle => String::from("ダブルクロス The 3rd Edition"),
+            Self::DoubleCrossHelp => String::from("個数DX[+修正値][@クリティカル値] : 判定"),
+
+            Self::ServerOnly(setting) => {
+                format!("{}はサーバー内でのみ設定できます", setting.ja())
+            }
+            Self::Current(setting, value) => format!("現在の{}：{}", setting.ja(), value),
+            Self::Reset(setting) => format!("{}の設定を解除しました", setting.ja()),
+            Self::NotSet => String::from("未設定"),
+            Self::Flag(setting, flag) => {
+                format!("{}：{}", setting.ja(), if *flag { "on" } else { "off" })
+            }
+            Self::LanguageSet(Language::Ja) => String::from("ダイスの結果を日本語で表示します"),
+            Self::LanguageSet(Language::En) => String::from("ダイスの結果を英語で表示します"),
+            Self::UnknownLanguage(name) => {
+                format!("{}という言語はありません\n利用可能な言語：ja, en", name)
+            }
+            Self::StyleSet(Style::Text) => String::from("表示形式をテキストに設定しました"),
+            Self::StyleSet(Style::Embed) => String::from("表示形式を埋め込みに設定しました"),
+            Self::UnknownStyle(style) => format!(
+                "{}という表示形式はありません\n利用可能な表示形式：text, embed",
+                style
+            ),
+            Self::PrefixSet(prefix) => format!("接頭辞を「{}」に設定しました", prefix),
+            Self::GmRoleSet(id) => format!("GMのロールを<@&{}>に設定しました", id),
+            Self::NotRole(role) => format!("{}はロールではありません", role),
+
+            Self::SystemSet { title, name, help } => {
+                format!("システムを{}（{}）に設定しました\n{}", title, name, help)
+            }
+            Self::ChannelSystemReset => {
+                String::from("このチャンネルのシステムの設定を解除しました")
+            }
+            Self::CurrentSystem {
+                title,
+                name,
+                inherited: true,
+            } => format!("現在のシステム：{}（{}、サーバーの既定）", title, name),
+            Self::CurrentSystem { title, name, .. } => {
+                format!("現在のシステム：{}（{}）", title, name)
+            }
+            Self::NoSystem => {
+                String::from("現在のシステム：未設定（全てのシステムのコマンドが使えます）")
+            }
+            Self::DefaultSystemSet { title, name } => format!(
+                "サーバーの既定のシステムを{}（{}）に設定しました\nシステムを設定していないチャンネルで使われます",
+                title, name
+            ),
+            Self::UnknownSystem(name) => format!("{}というシステムはありません", name),
+            Self::AvailableSystems => String::from("利用可能なシステム："),
+            Self::SyntaxSet(Syntax::Native) => String::from("構文を標準モードに設定しました"),
+            Self::SyntaxSet(Syntax::BCDice) => {
+                String::from("構文をBCDice互換モードに設定しました")
+            }
+            Self::CurrentSyntax(Syntax::Native) => {
+                String::from("現在の構文：標準モード（Native）")
+            }
+            Self::CurrentSyntax(Syntax::BCDice) => {
+                String::from("現在の構文：BCDice互換モード（BCDice）")
+            }
+            Self::UnknownSyntax(name) => format!(
+                "{}という構文はありません\n利用可能な構文：Native, BCDice",
+                name
+            ),
+            Self::UnknownFunction(name) => format!(
+                "{}という関数や演算子はありません\n一覧は help で表示します",
+                name
+            ),
+
+            Self::ExpressionRequired(example) => {
+                format!("式を指定してください（例：{}）", example)
+            }
+            Self::Explained(proc) => format!("解釈：{}", proc),
+
+            Self::SecretRolled { user_id, id } => {
+                format!("<@{}> がシークレットダイス #{} を振りました", user_id, id)
+            }
+            Self::SecretResult { id, channel_id } => {
+                format!("シークレットダイス #{}（<#{}>）", id, channel_id)
+            }
+            Self::SecretRevealed { user_id, id } => {
+                format!("<@{}> のシークレットダイス #{} を公開します", user_id, id)
+            }
+            Self::NoSecrets => String::from("公開できるシークレットダイスはありません"),
+            Self::UnknownSecret(id) => format!("シークレットダイス #{} はありません", id),
+            Self::OthersSecret => {
+                String::from("他の人のシークレットダイスはGMしか公開できません")
+            }
+            Self::SecretDice => String::from("（シークレットダイス）"),
+
+            Self::MacroRequired => {
+                String::from("保存する定義を指定してください（例：save atk := 2d6+4）")
+            }
+            Self::MacroForm => String::from("マクロは「名前 := 式」の形で指定してください"),
+            Self::OneMacro => String::from("マクロは1つずつ保存してください"),
+            Self::MacroLimit(limit) => format!("マクロは{}個までしか保存できません", limit),
+            Self::MacroSaved(name) => format!("マクロ{}を保存しました", name),
+            Self::MacroDeleted(name) => format!("マクロ{}を削除しました", name),
+            Self::MacroNameRequired => String::from("削除するマクロの名前を指定してください"),
+            Self::UnknownMacro(name) => format!("{}というマクロはありません", name),
+            Self::NoMacros => String::from("保存しているマクロはありません"),
+            Self::MacroList => String::from("保存しているマクロ："),
+
+            Self::Sheet(sheet) => format!("キャラクターシート：{}", sheet),
+            Self::NoSheet => {
+                String::from("このチャンネルではキャラクターシートが登録されていません")
+            }
+            Self::SheetRegistered(sheet) => {
+                format!("キャラクターシートを登録しました：{}", sheet)
+            }
+            Self::SheetImported { format, sheet } => {
+                format!("{}のキャラクターシートを登録しました：{}", format, sheet)
+            }
+            Self::SheetForm => String::from(
+                "キャラクターシートは整数か文字列を値に持つレコードで指定してください（例：sheet new {str: 14, dex: 12}）",
+            ),
+            Self::NotJson(why) => format!("JSONとして読み込めません：{}", why),
+            Self::UnsupportedSheet(formats) => {
+                format!("対応していない形式です（対応している形式：{}）", formats)
+            }
+            Self::SheetRequired => {
+                String::from("先に sheet new でキャラクターシートを登録してください")
+            }
+            Self::SheetDeleted => String::from("キャラクターシートを削除しました"),
+            Self::InvalidFieldName(field) => format!("{}は項目名として使えません", field),
+            Self::FieldType => String::from("項目には整数か文字列しか設定できません"),
+            Self::NotInteger => String::from("結果が整数ではありません"),
+            Self::NotIntegerField(field) => format!("{}は整数の項目ではありません", field),
+            Self::UnknownField(field) => format!("{}という項目はありません", field),
+            Self::AttachmentRequired => String::from("読み込むJSONファイルを添付してください"),
+            Self::DownloadFailed => String::from("添付ファイルを読み込めませんでした"),
+            Self::AttachmentTooLarge => String::from("添付ファイルが大きすぎます"),
+            Self::UnknownCommand(command, action) => {
+                format!("{} {}というコマンドはありません", command, action)
+            }
+
+            Self::Round(round) => format!("ラウンド {}", round),
+            Self::NoCombatants => String::from("（参加者はいません）"),
+            Self::NotStarted => String::from(
+                "このチャンネルでは戦闘が始まっていません（init start で開始します）",
+            ),
+            Self::EncounterStarted => String::from("戦闘を開始しました"),
+            Self::EncounterEnded => String::from("戦闘を終了しました"),
+            Self::InitiativeArgs => String::from(
+                "参加者の名前とイニシアチブの式を指定してください（例：init add ラルフ 2d6+3）",
+            ),
+            Self::InitiativeNotInteger => String::from("イニシアチブが整数ではありません"),
+            Self::Initiative { name, initiative } => {
+                format!("{}のイニシアチブ：{}", name, initiative)
+            }
+            Self::Turn(name) => format!("{}の手番です", name),
+            Self::NoTurn => String::from("参加者がいません"),
+            Self::CombatantRemoved(name) => format!("{}を外しました", name),
+            Self::UnknownCombatant(name) => format!("{}という参加者はいません", name),
+
+            Self::CountNotNumber => String::from("件数は数字で指定してください"),
+            Self::NoHistory => String::from("このチャンネルにはダイスの記録がありません"),
+            Self::RecentRolls => String::from("最近のダイス："),
+            Self::NotDate(date) => format!("{}は日付ではありません（例：2021-01-31）", date),
+            Self::UnknownFormat(name) => format!(
+                "{}という形式はありません\n利用可能な形式：md, csv, html",
+                name
+            ),
+            Self::NothingToExport => String::from("書き出すダイスの記録がありません"),
+            Self::Exported(n) => format!("ダイスの記録を{}件書き出しました", n),
+            Self::DiceLog => String::from("ダイスの記録"),
+            Self::TimeColumn => String::from("日時"),
+            Self::UserColumn => String::from("ユーザー"),
+            Self::InputColumn => String::from("入力"),
+            Self::LogColumn => String::from("経過"),
+
+            Self::StatsPeriod => String::from(
+                "期間は開始日と終了日で指定してください（例：stats 2021-01-01 2021-01-31）",
+            ),
+            Self::NothingToSummarize => String::from("集計するダイスの記録がありません"),
+            Self::StatsTitle(Some(name)) => format!("{}のダイスの統計", name),
+            Self::StatsTitle(None) => String::from("このチャンネルのダイスの統計"),
+            Self::Period(since, until) => format!("（{}～{}）", since, until),
+            Self::Rolls(n) => format!("振った回数：{}", n),
+            Self::DiceCount { sides, count } => format!("D{}：{}個", sides, count),
+            Self::Mean { mean, expected } => format!("平均{:.2}（期待値{:.2}）", mean, expected),
+            Self::ChiSquare { chi_square, df, p } => {
+                format!("χ²={:.2}（自由度{}） p={:.3}", chi_square, df, p)
+            }
+            Self::Biased => String::from("偏りがある可能性があります"),
+            Self::Unbiased => String::from("偏りは見られません"),
+            Self::FewRolls => String::from("（回数が少ないため参考値）"),
+            Self::PlayerTally {
+                name,
+                rolls,
+                critical,
+                fumble,
+                success,
+                failure,
+            } => format!(
+                "{}：{}回 クリティカル{}回 ファンブル{}回（成功{}回、失敗{}回）",
+                name, rolls, critical, fumble, success, failure
+            ),
+            Self::CriticalsField => String::from("クリティカル／ファンブル"),
+            Self::CriticalsFieldContinued => String::from("クリティカル／ファンブル（続き）"),
+
+            Self::Exact => String::from("厳密計算"),
+            Self::MonteCarlo(trials) => format!("モンテカルロ法 {}回", trials),
+            Self::SuccessRate(p) => format!("成功率：{:.2}%", p),
+            Self::Margin(error) => format!("（±{:.2}%）", error),
+            Self::Probability { input, method } => format!("{} の確率（{}）", input, method),
+            Self::Distribution { input, method } => format!("{} の分布（{}）", input, method),
+            Self::MeanAndDeviation { mean, deviation } => {
+                format!("平均：{:.2} 標準偏差：{:.2}", mean, deviation)
+            }
+            Self::Percentiles(percentiles) => format!("パーセンタイル：{}", percentiles),
+            Self::Histogram => String::from("ヒストグラム"),
+
+            Self::Usage => String::from(
+                "\
+                使い方：\n\
+                uectrpg_dicebot : BOTを起動します\n\
+                uectrpg_dicebot import <ファイル>... : キャラクターシートのJSONを読み込み、BOTで使うレコードとして表示します\n\
+                uectrpg_dicebot replay <ファイル> : ダイスの記録（history.jsonl）を記録されたシードで振り直し、結果が一致するか確かめます\n\
+                uectrpg_dicebot prob <式> [PNGファイル] : ダイス式の結果の確率分布を表示し、指定されていればグラフの画像を書き出します\n\
+                uectrpg_dicebot stats <ファイル> [ディレクトリ] : ダイスの記録の出目の統計を表示し、指定されていれば出目の分布の画像をディレクトリに書き出します",
+            ),
+            Self::RecordedResult(value) => format!("（記録された結果：{}）", value),
+            Self::Replayed { total, matched } => {
+                format!("{}件中{}件が記録と一致しました", total, matched)
+            }
+            Self::Written(path) => format!("{}を書き出しました", path),
+        }
+    }
+
+    fn en(&self) -> String {
+        match self {
+            Self::Success => String::from("Success"),
+            Self::Failure => String::from("Failure"),
+            Self::Critical => String::from("Critical"),
+            Self::Fumble => String::from("Fumble"),
+            Self::Successes(1) => String::from("1 success"),
+            Self::Successes(n) => format!("{} successes", n),
+            Self::Error(err) => format!("Error: {}", err),
+            Self::ErrorSource(source) => format!(" (in {})", source),
+            Self::TimeLimit(limit) => format!("Time limit exceeded ({} ms)", limit),
+            Self::MixedIdent(name) => format!("{0} and {0}? cannot be used together", name),
+            Self::DiceField => String::from("Dice"),
+            Self::ResultField => String::from("Result"),
+            Self::OutcomeField => String::from("Outcome"),
+            Self::Emphasized(label) => format!("**{}!**", label),
+            Self::Omitted => String::from("(Omitted because the result is too long)"),
+            Self::Heading(heading) => format!("{}:", heading),
+
+            Self::Level(level) => String::from(match level {
+                Level::Critical => "Critical",
+                Level::Extreme => "Extreme success",
+                Level::Hard => "Hard success",
+                Level::Regular => "Regular success",
+                Level::Failure => "Failure",
+                Level::Fumble => "Fumble",
+                Level::ClassicCritical => "Critical success",
+                Level::ClassicSuccess => "Success",
+                Level::ClassicFumble => "Fumble",
+                Level::AutomaticFailure => "Automatic failure",
+            }),
+            Self::BonusDiceLimit(limit) => {
+                format!("Up to {} bonus or penalty dice can be used", limit)
+            }
+            Self::DiceCountRange(limit) => {
+                format!("The number of dice must be between 1 and {}", limit)
+            }
+            Self::CriticalValueMin(min) => {
+                format!("The critical value must be {} or more", min)
+            }
+            Self::PowerRange(max) => format!("The power must be between 0 and {}", max),
+
+            Self::CthulhuTitle => String::from("Call of Cthulhu 7th Edition"),
+            Self::CthulhuHelp => String::from(
+                "CC(bonus or penalty dice)<=skill : Skill roll\n\
+                CCB<=skill : Skill roll in the 6th edition style",
+            ),
+            Self::SwordWorldTitle => String::from("Sword World 2.x"),
+            Self::SwordWorldHelp => String::from(
+                "K<power>[+modifier][@critical][r<power increase>][$+<critical ray>] : Rating table",
+            ),
+            Self::DoubleCrossTitle => String::from("Double Cross The 3rd Edition"),
+            Self::DoubleCrossHelp => {
+                String::from("<count>DX[+modifier][@critical] : Check")
+            }
+
+            Self::ServerOnly(setting) => {
+                format!("The {} can only be set in a server", setting.en())
+            }
+            Self::Current(setting, value) => format!("Current {}: {}", setting.en(), value),
+            Self::Reset(setting) => format!("Reset the {}", setting.en()),
+            Self::NotSet => String::from("not set"),
+            Self::Flag(setting, flag) => {
+                format!("{}: {}", setting.en(), if *flag { "on" } else { "off" })
+            }
+            Self::LanguageSet(Language::Ja) => {
+                String::from("Dice results will be shown in Japanese")
+            }
+            Self::LanguageSet(Language::En) => {
+                String::from("Dice results will be shown in English")
+            }
+            Self::UnknownLanguage(name) => {
+                format!("Unknown language: {}\nAvailable languages: ja, en", name)
+            }
+            Self::StyleSet(Style::Text) => String::from("Set the output style to text"),
+            Self::StyleSet(Style::Embed) => String::from("Set the output style to embed"),
+            Self::UnknownStyle(style) => format!(
+                "Unknown output style: {}\nAvailable output styles: text, embed",
+                style
+            ),
+            Self::PrefixSet(prefix) => format!("Set the prefix to \"{}\"", prefix),
+            Self::GmRoleSet(id) => format!("Set the GM role to <@&{}>", id),
+            Self::NotRole(role) => format!("{} is not a role", role),
+
+            Self::SystemSet { title, name, help } => {
+                format!("Set the system to {} ({})\n{}", title, name, help)
+            }
+            Self::ChannelSystemReset => String::from("Reset the system of this channel"),
+            Self::CurrentSystem {
+                title,
+                name,
+                inherited: true,
+            } => format!("Current system: {} ({}, server default)", title, name),
+            Self::CurrentSystem { title, name, .. } => {
+                format!("Current system: {} ({})", title, name)
+            }
+            Self::NoSystem => {
+                String::from("Current system: not set (commands of all systems are available)")
+            }
+            Self::DefaultSystemSet { title, name } => format!(
+                "Set the default system of this server to {} ({})\nIt is used in channels without a system",
+                title, name
+            ),
+            Self::UnknownSystem(name) => format!("Unknown system: {}", name),
+            Self::AvailableSystems => String::from("Available systems:"),
+            Self::SyntaxSet(Syntax::Native) => String::from("Set the syntax to native mode"),
+            Self::SyntaxSet(Syntax::BCDice) => {
+                String::from("Set the syntax to BCDice compatible mode")
+            }
+            Self::CurrentSyntax(Syntax::Native) => {
+                String::from("Current syntax: native mode (Native)")
+            }
+            Self::CurrentSyntax(Syntax::BCDice) => {
+                String::from("Current syntax: BCDice compatible mode (BCDice)")
+            }
+            Self::UnknownSyntax(name) => format!(
+                "Unknown syntax: {}\nAvailable syntaxes: Native, BCDice",
+                name
+            ),
+            Self::UnknownFunction(name) => format!(
+                "Unknown function or operator: {}\nUse help to list them",
+                name
+            ),
+
+            Self::ExpressionRequired(example) => {
+                format!("Specify an expression (e.g. {})", example)
+            }
+            Self::Explained(proc) => format!("Parsed as: {}", proc),
+
+            Self::SecretRolled { user_id, id } => {
+                format!("<@{}> rolled secret dice #{}", user_id, id)
+            }
+            Self::SecretResult { id, channel_id } => {
+                format!("Secret dice #{} (<#{}>)", id, channel_id)
+            }
+            Self::SecretRevealed { user_id, id } => {
+                format!("Revealing secret dice #{} of <@{}>", id, user_id)
+            }
+            Self::NoSecrets => String::from("There are no secret dice to reveal"),
+            Self::UnknownSecret(id) => format!("There is no secret dice #{}", id),
+            Self::OthersSecret => {
+                String::from("Only the GM can reveal secret dice of other players")
+            }
+            Self::SecretDice => String::from("(Secret dice)"),
+
+            Self::MacroRequired => {
+                String::from("Specify a definition to save (e.g. save atk := 2d6+4)")
+            }
+            Self::MacroForm => String::from("Specify a macro as \"name := expression\""),
+            Self::OneMacro => String::from("Save one macro at a time"),
+            Self::MacroLimit(limit) => format!("You can save up to {} macros", limit),
+            Self::MacroSaved(name) => format!("Saved the macro {}", name),
+            Self::MacroDeleted(name) => format!("Deleted the macro {}", name),
+            Self::MacroNameRequired => String::from("Specify the name of the macro to delete"),
+            Self::UnknownMacro(name) => format!("Unknown macro: {}", name),
+            Self::NoMacros => String::from("You have no saved macros"),
+            Self::MacroList => String::from("Saved macros:"),
+
+            Self::Sheet(sheet) => format!("Character sheet: {}", sheet),
+            Self::NoSheet => String::from("No character sheet is registered in this channel"),
+            Self::SheetRegistered(sheet) => format!("Registered the character sheet: {}", sheet),
+            Self::SheetImported { format, sheet } => {
+                format!("Registered the character sheet from {}: {}", format, sheet)
+            }
+            Self::SheetForm => String::from(
+                "Specify a character sheet as a record of integers and strings (e.g. sheet new {str: 14, dex: 12})",
+            ),
+            Self::NotJson(why) => format!("Cannot read the file as JSON: {}", why),
+            Self::UnsupportedSheet(formats) => {
+                format!("Unsupported format (supported formats: {})", formats)
+            }
+            Self::SheetRequired => {
+                String::from("Register a character sheet with sheet new first")
+            }
+            Self::SheetDeleted => String::from("Deleted the character sheet"),
+            Self::InvalidFieldName(field) => format!("{} cannot be used as a field name", field),
+            Self::FieldType => String::from("Fields can only be set to integers or strings"),
+            Self::NotInteger => String::from("The result is not an integer"),
+            Self::NotIntegerField(field) => format!("{} is not an integer field", field),
+            Self::UnknownField(field) => format!("Unknown field: {}", field),
+            Self::AttachmentRequired => String::from("Attach a JSON file to import"),
+            Self::DownloadFailed => String::from("Failed to read the attachment"),
+            Self::AttachmentTooLarge => String::from("The attachment is too large"),
+            Self::UnknownCommand(command, action) => {
+                format!("Unknown command: {} {}", command, action)
+            }
+
+            Self::Round(round) => format!("Round {}", round),
+            Self::NoCombatants => String::from("(No combatants)"),
+            Self::NotStarted => String::from(
+                "No encounter has started in this channel (start one with init start)",
+            ),
+            Self::EncounterStarted => String::from("Started the encounter"),
+            Self::EncounterEnded => String::from("Ended the encounter"),
+            Self::InitiativeArgs => String::from(
+                "Specify the name of a combatant and an initiative expression (e.g. init add Ralph 2d6+3)",
+            ),
+            Self::InitiativeNotInteger => String::from("The initiative is not an integer"),
+            Self::Initiative { name, initiative } => {
+                format!("Initiative of {}: {}", name, initiative)
+            }
+            Self::Turn(name) => format!("It is {}'s turn", name),
+            Self::NoTurn => String::from("There are no combatants"),
+            Self::CombatantRemoved(name) => format!("Removed {}", name),
+            Self::UnknownCombatant(name) => format!("Unknown combatant: {}", name),
+
+            Self::CountNotNumber => String::from("Specify the count as a number"),
+            Self::NoHistory => String::from("No dice have been rolled in this channel"),
+            Self::RecentRolls => String::from("Recent rolls:"),
+            Self::NotDate(date) => format!("{} is not a date (e.g. 2021-01-31)", date),
+            Self::UnknownFormat(name) => format!(
+                "Unknown format: {}\nAvailable formats: md, csv, html",
+                name
+            ),
+            Self::NothingToExport => String::from("There are no rolls to export"),
+            Self::Exported(1) => String::from("Exported 1 roll"),
+            Self::Exported(n) => format!("Exported {} rolls", n),
+            Self::DiceLog => String::from("Dice log"),
+            Self::TimeColumn => String::from("Time"),
+            Self::UserColumn => String::from("User"),
+            Self::InputColumn => String::from("Input"),
+            Self::LogColumn => String::from("Log"),
+
+            Self::StatsPeriod => String::from(
+                "Specify the period by a start date and an end date (e.g. stats 2021-01-01 2021-01-31)",
+            ),
+            Self::NothingToSummarize => String::from("There are no rolls to summarize"),
+            Self::StatsTitle(Some(name)) => format!("Dice stats of {}", name),
+            Self::StatsTitle(None) => String::from("Dice stats of this channel"),
+            Self::Period(since, until) => format!(" ({} - {})", since, until),
+            Self::Rolls(n) => format!("Rolls: {}", n),
+            Self::DiceCount { sides, count } => format!("D{}: {} dice", sides, count),
+            Self::Mean { mean, expected } => {
+                format!("Mean {:.2} (expected {:.2})", mean, expected)
+            }
+            Self::ChiSquare { chi_square, df, p } => {
+                format!("χ²={:.2} (df {}) p={:.3}", chi_square, df, p)
+            }
+            Self::Biased => String::from("The dice may be biased"),
+            Self::Unbiased => String::from("No bias was found"),
+            Self::FewRolls => String::from(" (for reference only, too few rolls)"),
+            Self::PlayerTally {
+                name,
+                rolls,
+                critical,
+                fumble,
+                success,
+                failure,
+            } => format!(
+                "{}: {} rolls, {} criticals, {} fumbles ({} successes, {} failures)",
+                name, rolls, critical, fumble, success, failure
+            ),
+            Self::CriticalsField => String::from("Criticals / Fumbles"),
+            Self::CriticalsFieldContinued => String::from("Criticals / Fumbles (continued)"),
+
+            Self::Exact => String::from("exact"),
+            Self::MonteCarlo(trials) => format!("Monte Carlo, {} trials", trials),
+            Self::SuccessRate(p) => format!("Success rate: {:.2}%", p),
+            Self::Margin(error) => format!(" (±{:.2}%)", error),
+            Self::Probability { input, method } => {
+                format!("Probability of {} ({})", input, method)
+            }
+            Self::Distribution { input, method } => {
+                format!("Distribution of {} ({})", input, method)
+            }
+            Self::MeanAndDeviation { mean, deviation } => {
+                format!("Mean: {:.2} Standard deviation: {:.2}", mean, deviation)
+            }
+            Self::Percentiles(percentiles) => format!("Percentiles: {}", percentiles),
+            Self::Histogram => String::from("Histogram"),
+
+            Self::Usage => String::from(
+                "\
+                Usage:\n\
+                uectrpg_dicebot : Starts the bot\n\
+                uectrpg_dicebot import <file>... : Reads character sheet JSON files and prints them as records for the bot\n\
+                uectrpg_dicebot replay <file> : Rerolls the dice log (history.jsonl) with the recorded seeds and checks that the results match\n\
+                uectrpg_dicebot prob <expr> [PNG file] : Prints the probability distribution of a dice expression and writes a chart image if a file is given\n\
+                uectrpg_dicebot stats <file> [directory] : Prints statistics of the dice log and writes distribution images to the directory if given",
+            ),
+            Self::RecordedResult(value) => format!(" (recorded result: {})", value),
+            Self::Replayed { total, matched } => {
+                format!("{} of {} rolls matched the log", matched, total)
+            }
+            Self::Written(path) => format!("Wrote {}", path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+    use crate::roll;
+
+    fn roll(language: Language, content: &str) -> String {
+        using(language, || {
+            let rolled =
+                roll::evaluate(&parser::Syntax::Native, None, &[], None, 0, content).unwrap();
+            format!("{}", rolled.value)
+        })
+    }
+
+    #[test]
+    fn format_values() {
+        assert_eq!(roll(Language::Ja, "1d6>=1"), "成功");
+        assert_eq!(roll(Language::En, "1d6>=1"), "Success");
+        assert_eq!(roll(Language::En, "1d6>=7"), "Failure");
+        assert_eq!(roll(Language::Ja, "3b6>=1"), "3成功");
+        assert_eq!(roll(Language::En, "3b6>=1"), "3 successes");
+        assert_eq!(roll(Language::En, "1b6>=1"), "1 success");
+    }
+
+    #[test]
+    fn format_errors() {
+        assert_eq!(
+            roll(Language::Ja, "x := 1; x?"),
+            "エラー：xとx?を併用することはできません"
+        );
+        assert_eq!(
+            roll(Language::En, "x := 1; x?"),
+            "Error: x and x? cannot be used together"
+        );
+    }
+
+    #[test]
+    fn restore_language() {
+        using(Language::En, || assert!(current() == Language::En));
+        assert!(current() == Language::Ja);
+    }
+}
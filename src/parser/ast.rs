@@ -3,21 +3,34 @@ use std::collections::HashMap;
 
 pub struct Proc(Vec<Arc<Expr0>>);
 
+// 式が書かれた元の文字列と、その中での位置（バイト単位）
+#[derive(Clone)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub source: Arc<String>,
+}
+
+// 項とリテラルの位置は、それを囲むExpr0::Termの位置と同じ
+#[allow(clippy::enum_variant_names)]
 pub enum Expr0 {
     Expr0 {
         right: Arc<Expr0>,
         left: Arc<Expr0>,
         operator: String,
+        span: Span,
     },
     Fn {
         arg: Arc<String>,
         value: Arc<Expr0>,
+        span: Span,
     },
     Def {
         ident: Arc<Ident>,
         value: Arc<Expr0>,
+        span: Span,
     },
-    Term(Term),
+    Term(Term, Span),
 }
 
 pub enum Term {
@@ -30,6 +43,7 @@ pub enum Term {
 
 pub enum Literal {
     Integer(i64),
+    String(Arc<String>),
     Ident(Ident),
 }
 
@@ -59,6 +73,41 @@ impl std::ops::DerefMut for Proc {
     }
 }
 
+impl Span {
+    pub fn new(source: &Arc<String>, start: usize, end: usize) -> Self {
+        Self {
+            start,
+            end,
+            source: Arc::clone(source),
+        }
+    }
+
+    // 2つの位置を含む範囲
+    pub fn join(&self, other: &Self) -> Self {
+        Self {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+            source: Arc::clone(&self.source),
+        }
+    }
+
+    // 元の文字列のうち、この範囲の部分
+    pub fn text(&self) -> &str {
+        self.source.get(self.start..self.end).unwrap_or_default()
+    }
+}
+
+impl Expr0 {
+    pub fn span(&self) -> &Span {
+        match self {
+            Self::Expr0 { span, .. } => span,
+            Self::Fn { span, .. } => span,
+            Self::Def { span, .. } => span,
+            Self::Term(_, span) => span,
+        }
+    }
+}
+
 impl Ident {
     pub fn name(&self) -> Arc<String> {
         match self {
@@ -68,9 +117,234 @@ impl Ident {
     }
 
     pub fn is_strict(&self) -> bool {
+        matches!(self, Self::Strict(_))
+    }
+}
+
+// 全ての演算を括弧で囲んだ、解釈の結果がわかる形で表示する
+impl std::fmt::Display for Proc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let exprs: Vec<_> = self.iter().map(|expr| expr.to_string()).collect();
+        write!(f, "{}", exprs.join("; "))
+    }
+}
+
+impl std::fmt::Display for Expr0 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Strict(_) => true,
-            _ => false,
+            Self::Expr0 {
+                left,
+                right,
+                operator,
+                ..
+            } => match operator.as_str() {
+                "d" | "b" => write!(
+                    f,
+                    "({}{}{})",
+                    DiceOperand(left),
+                    operator,
+                    DiceOperand(right)
+                ),
+                " " => write!(f, "({} {})", left, right),
+                operator => write!(f, "({} {} {})", left, operator, right),
+            },
+            Self::Fn { arg, value, .. } => write!(f, "(\\{} -> {})", arg, value),
+            Self::Def { ident, value, .. } => write!(f, "({} := {})", ident, value),
+            Self::Term(term, _) => write!(f, "{}", term),
         }
     }
 }
+
+// 2dx のように識別子とダイスの演算子がつながらないよう、識別子は括弧で囲む
+struct DiceOperand<'a>(&'a Expr0);
+
+impl std::fmt::Display for DiceOperand<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut expr = self.0;
+        while let Expr0::Term(Term::Expr0(inner), _) = expr {
+            expr = inner;
+        }
+        match expr {
+            Expr0::Term(Term::Literal(Literal::Ident(_)), _) => write!(f, "({})", expr),
+            _ => write!(f, "{}", expr),
+        }
+    }
+}
+
+impl std::fmt::Display for Term {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            // 元の括弧は、演算を囲む括弧と重なるので省く
+            Self::Expr0(expr) => write!(f, "{}", expr),
+            Self::Proc(proc) => write!(f, "{{{}}}", proc),
+            Self::Array(exprs) => {
+                let exprs: Vec<_> = exprs.iter().map(|expr| expr.to_string()).collect();
+                write!(f, "[{}]", exprs.join(", "))
+            }
+            Self::Record(pairs) => {
+                let mut pairs: Vec<_> = pairs
+                    .iter()
+                    .map(|(key, expr)| format!("{}: {}", key, expr))
+                    .collect();
+                pairs.sort();
+                write!(f, "{{{}}}", pairs.join(", "))
+            }
+            Self::Literal(literal) => write!(f, "{}", literal),
+        }
+    }
+}
+
+impl std::fmt::Display for Literal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            // 負の数のリテラルは書けないので、引き算として表示する
+            Self::Integer(n) if *n < 0 => write!(f, "(0 - {})", n.unsigned_abs()),
+            Self::Integer(n) => write!(f, "{}", n),
+            Self::String(s) => write!(f, "\"{}\"", s),
+            Self::Ident(ident) => write!(f, "{}", ident),
+        }
+    }
+}
+
+impl std::fmt::Display for Ident {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Strict(name) => write!(f, "{}", name),
+            Self::Lazy(name) => write!(f, "{}?", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::{self, Syntax};
+    use crate::{render, roll};
+
+    // 表示した式をもう一度解釈して表示すると、同じ式になる
+    fn round_trip(syntax: Syntax, src: &str) -> String {
+        let printed = parser::parse(&syntax, src)
+            .unwrap_or_else(|err| panic!("{} : {}", src, err))
+            .to_string();
+        let reprinted = parser::parse(&Syntax::Native, &printed)
+            .unwrap_or_else(|err| panic!("{} -> {} : {}", src, printed, err))
+            .to_string();
+        assert_eq!(printed, reprinted, "{}", src);
+
+        // 同じシードで振れば同じ結果になる
+        let evaluate = |syntax, src| {
+            let rolled = roll::evaluate(&syntax, None, &[], None, 0, src).unwrap();
+            let log: Vec<_> = rolled.log.iter().map(render::log_line).collect();
+            (log, format!("{}", rolled.value))
+        };
+        assert_eq!(
+            evaluate(syntax, src),
+            evaluate(Syntax::Native, &printed),
+            "{} -> {}",
+            src,
+            printed
+        );
+        printed
+    }
+
+    #[test]
+    fn fully_parenthesized() {
+        let cases = [
+            ("2d6+3", "((2d6) + 3)"),
+            ("3d6+2>=12", "(((3d6) + 2) >= 12)"),
+            ("2d6.s", "((2d6) . s)"),
+            ("10B6.s>=5", "(((10b6) . s) >= 5)"),
+            ("max_of 3 4b6", "((max_of 3) (4b6))"),
+            ("x := 1d6; x * x", "(x := (1d6)); (x * x)"),
+            ("atk? := 2d6; atk? # 3", "(atk? := (2d6)); (atk? # 3)"),
+            ("n := 2; (n)d6", "(n := 2); ((n)d6)"),
+            ("n d 6", "((n d) 6)"),
+            ("\\x y -> x + y", "(\\x -> (\\y -> (x + y)))"),
+            ("1d10 @>= 10", "((1d10) @>= 10)"),
+            ("{hp: 10, mp: 2d6}.hp", "({hp: 10, mp: (2d6)} . hp)"),
+            (r#"choice ["a", "b"]"#, r#"(choice ["a", "b"])"#),
+            ("{x := 1; x}", "{(x := 1); x}"),
+        ];
+        for (src, expected) in &cases {
+            assert_eq!(round_trip(Syntax::Native, src), *expected);
+        }
+    }
+
+    #[test]
+    fn print_bcdice_as_native() {
+        assert_eq!(
+            round_trip(Syntax::BCDice, "CC(-2)<=60"),
+            "((cc (0 - 2)) 60)"
+        );
+        assert_eq!(round_trip(Syntax::BCDice, "3x2D6+1"), "(((2d6) + 1) # 3)");
+        round_trip(Syntax::BCDice, "K20+5@10");
+        round_trip(Syntax::BCDice, "10DX+2@8");
+        round_trip(Syntax::BCDice, "CHOICE(金, 銀, 銅)");
+    }
+
+    // 全ての部分式の元の文字列を、外側から順に並べる
+    fn spans(expr: &super::Expr0, res: &mut Vec<String>) {
+        use super::{Expr0, Term};
+        res.push(String::from(expr.span().text()));
+        match expr {
+            Expr0::Expr0 { left, right, .. } => {
+                spans(left, res);
+                spans(right, res);
+            }
+            Expr0::Fn { value, .. } | Expr0::Def { value, .. } => spans(value, res),
+            Expr0::Term(Term::Expr0(expr), _) => spans(expr, res),
+            Expr0::Term(Term::Array(exprs), _) => exprs.iter().for_each(|expr| spans(expr, res)),
+            Expr0::Term(_, _) => {}
+        }
+    }
+
+    fn source_texts(syntax: Syntax, src: &str) -> Vec<String> {
+        let mut res = vec![];
+        for expr in parser::parse(&syntax, src).unwrap().iter() {
+            spans(expr, &mut res);
+        }
+        res
+    }
+
+    #[test]
+    fn record_spans() {
+        assert_eq!(
+            source_texts(Syntax::Native, "x := (2d6 + 1) * 2; \\a b -> a"),
+            [
+                "x := (2d6 + 1) * 2",
+                "(2d6 + 1) * 2",
+                "(2d6 + 1)",
+                "2d6 + 1",
+                "2d6",
+                "2",
+                "6",
+                "1",
+                "2",
+                "\\a b -> a",
+                "b -> a",
+                "a"
+            ]
+        );
+        assert_eq!(
+            source_texts(Syntax::Native, "max_of 1 [1d6, 3]"),
+            [
+                "max_of 1 [1d6, 3]",
+                "max_of 1",
+                "max_of",
+                "1",
+                "[1d6, 3]",
+                "1d6",
+                "1",
+                "6",
+                "3"
+            ]
+        );
+        // BCDiceのコマンドから作った式は、コマンド全体の位置を持つ
+        assert_eq!(
+            source_texts(Syntax::BCDice, "-2D6+1"),
+            ["-2D6+1", "-2D6", "-", "2D6", "2D6", "2D6", "1"]
+        );
+        assert!(source_texts(Syntax::BCDice, "CC<=50 目星")
+            .iter()
+            .all(|text| text == "CC<=50" || text == "50"));
+    }
+}
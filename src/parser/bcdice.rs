@@ -0,0 +1,217 @@
+use super::{ast, call, integer, operate, options};
+use async_std::sync::Arc;
+
+// BCDice形式のコマンドを、このボットの構文木に変換する
+peg::parser! {
+    pub grammar context(source: &Arc<String>) for str {
+        pub rule parse() -> ast::Proc
+            = dlm()? expr:command() comment()? {
+                ast::Proc::new(vec![Arc::new(expr)])
+            }
+
+        pub rule without_comment() -> ast::Proc
+            = dlm()? expr:command() dlm()? {
+                ast::Proc::new(vec![Arc::new(expr)])
+            }
+
+        rule command() -> ast::Expr0
+            = choice()
+            / start:position!() i("CCB") "<=" target:arith() {
+                let span = ast::Span::new(source, start, target.span().end);
+                call("ccb", vec![target], &span)
+            }
+            / start:position!() i("CC") n:cc_dice()? "<=" target:arith() {
+                let span = ast::Span::new(source, start, target.span().end);
+                call("cc", vec![integer(n.unwrap_or(0), &span), target], &span)
+            }
+            / start:position!() i("K") rate:nat() opts:rating_option()* end:position!() {
+                let span = ast::Span::new(source, start, end);
+                call("k", vec![options([vec![("rate", rate)], opts].concat(), &span)], &span)
+            }
+            / start:position!() count:nat() i("DX") critical:nat()? opts:dx_option()* end:position!() {
+                let span = ast::Span::new(source, start, end);
+                let critical = critical.map(|n| ("critical", n)).into_iter().collect();
+                call("dx", vec![options([vec![("count", count)], critical, opts].concat(), &span)], &span)
+            }
+            / start:position!() n:nat() i("x") expr:compare() {
+                let span = ast::Span::new(source, start, expr.span().end);
+                operate("#", expr, integer(n, &span))
+            }
+            / compare()
+
+        rule choice() -> ast::Expr0
+            = start:position!() i("choice") items:(
+                "[" items:$((!"]" [_])*) "]" { items }
+                / "(" items:$((!")" [_])*) ")" { items }
+            ) end:position!() {
+                let span = ast::Span::new(source, start, end);
+                let items = items
+                    .split(',')
+                    .map(|item| Arc::new(ast::Expr0::Term(ast::Term::Literal(ast::Literal::String(Arc::new(String::from(item.trim())))), span.clone()))
+                    )
+                    .collect();
+                call("choice", vec![ast::Expr0::Term(ast::Term::Array(items), span.clone())], &span)
+            }
+
+        rule compare() -> ast::Expr0
+            = left:arith() operator:compare_operator() right:arith() { operate(operator, left, right) }
+            / arith()
+
+        rule compare_operator() -> &'static str
+            = ">=" { ">=" }
+            / "<=" { "<=" }
+            / "<>" { "!=" }
+            / ">" { ">" }
+            / "<" { "<" }
+            / "=" { "==" }
+
+        rule arith() -> ast::Expr0
+            = precedence! {
+                left:(@) "+" right:@ { operate("+", left, right) }
+                left:(@) "-" right:@ { operate("-", left, right) }
+                --
+                left:(@) "*" right:@ { operate("*", left, right) }
+                left:(@) "/" right:@ { operate("/", left, right) }
+                --
+                "-" right:@ {
+                    // "-"は右の式の直前にある
+                    let start = right.span().start.saturating_sub(1);
+                    let span = ast::Span::new(source, start, start + 1);
+                    operate("-", integer(0, &span), right)
+                }
+                --
+                term:term() { term }
+            }
+
+        // precedence!の中では位置を取れないので、項は別の規則にする
+        rule term() -> ast::Expr0
+            = start:position!() "(" expr:arith() ")" end:position!() {
+                ast::Expr0::Term(ast::Term::Expr0(Arc::new(expr)), ast::Span::new(source, start, end))
+            }
+            / start:position!() count:nat()? i("D") sides:nat() end:position!() {
+                let span = ast::Span::new(source, start, end);
+                operate("d", integer(count.unwrap_or(1), &span), integer(sides, &span))
+            }
+            / start:position!() count:nat() i("B") sides:nat() end:position!() {
+                let span = ast::Span::new(source, start, end);
+                operate("b", integer(count, &span), integer(sides, &span))
+            }
+            / start:position!() n:nat() end:position!() { integer(n, &ast::Span::new(source, start, end)) }
+
+        rule rating_option() -> (&'static str, i64)
+            = "@" n:nat() { ("critical", n) }
+            / i("r") n:nat() { ("rate_up", n) }
+            / "$" n:signed() { ("crit_ray", n) }
+            / n:signed() { ("modifier", n) }
+
+        rule dx_option() -> (&'static str, i64)
+            = "@" n:nat() { ("critical", n) }
+            / n:signed() { ("modifier", n) }
+
+        rule cc_dice() -> i64
+            = "(" n:nat() ")" { n }
+            / "(-" n:nat() ")" { -n }
+
+        rule signed() -> i64
+            = "+" n:nat() { n }
+            / "-" n:nat() { -n }
+
+        rule nat() -> i64
+            = n:$(['0'..='9']+) {? n.parse().or(Err("integer")) }
+
+        // BCDiceでは、コマンドの後に空白を挟んだ文字列はコメントとして扱われる
+        rule comment() = dlm() [_]*
+
+        rule i(literal: &'static str)
+            = input:$([_]*<{literal.chars().count()}>) {?
+                if input.eq_ignore_ascii_case(literal) { Ok(()) } else { Err(literal) }
+            }
+
+        rule dlm() = quiet!{[' ' | '\n' | '\t']+}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::{self, ast, Syntax};
+    use crate::sheet::{Field, Sheet};
+    use crate::{game_system, render, roll, runtime};
+    use rand::SeedableRng;
+
+    // 部分式の位置は元の文字列によって変わるので、表示した経過を比べる
+    fn evalute(proc: ast::Proc) -> (Vec<String>, String) {
+        let mut env = runtime::Environment::new();
+        async_std::task::block_on(runtime::built_in_function::set_default(&mut env));
+        async_std::task::block_on(game_system::set_default(&mut env, None));
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut log = vec![];
+        let begin_time = std::time::Instant::now();
+        let val = proc.evalute(&mut env, &mut rng, &mut log, &begin_time);
+        let log = log.iter().map(render::log_line).collect();
+        (log, format!("{}", val))
+    }
+
+    // BCDiceのコマンドと、それに相当するこのボットの式
+    const CONFORMANCE: &[(&str, &str)] = &[
+        ("2D6", "2d6"),
+        ("D6", "1d6"),
+        ("2d6-1", "2d6-1"),
+        ("2D6+3>=10", "2d6+3>=10"),
+        ("1D100<=50", "1d100<=50"),
+        ("2D6=7", "2d6==7"),
+        ("2D6<>7", "2d6!=7"),
+        ("2D6>7", "2d6>7"),
+        ("(2D6+1)*2", "(2d6+1)*2"),
+        ("3B6", "3b6"),
+        ("3B6>=4", "3b6>=4"),
+        ("3x2D6+1", "(2d6+1)#3"),
+        ("choice[a,b,c]", r#"choice ["a", "b", "c"]"#),
+        ("CHOICE(金, 銀, 銅)", r#"choice ["金", "銀", "銅"]"#),
+        ("CC<=60", "cc 0 60"),
+        ("CC(1)<=60", "cc 1 60"),
+        ("CC(-2)<=60", "cc (0 - 2) 60"),
+        ("CCB<=70", "ccb 70"),
+        ("ccb<=70", "ccb 70"),
+        ("K20+5@10", "k {rate: 20, modifier: 5, critical: 10}"),
+        ("k30$+1r5", "k {rate: 30, crit_ray: 1, rate_up: 5}"),
+        ("10DX+2@8", "dx {count: 10, modifier: 2, critical: 8}"),
+        ("5dx7", "dx {count: 5, critical: 7}"),
+        ("2D6 攻撃判定", "2d6"),
+    ];
+
+    #[test]
+    fn conformance() {
+        for (bcdice, native) in CONFORMANCE {
+            let actual = parser::parse(&Syntax::BCDice, bcdice)
+                .unwrap_or_else(|err| panic!("{} : {}", bcdice, err));
+            let expected = parser::parse(&Syntax::Native, native)
+                .unwrap_or_else(|err| panic!("{} : {}", native, err));
+            assert_eq!(evalute(actual), evalute(expected), "{}", bcdice);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_syntax() {
+        for src in &["2D6+", "攻撃 2D6", "x :="] {
+            assert!(parser::parse(&Syntax::BCDice, src).is_err(), "{}", src);
+        }
+    }
+
+    #[test]
+    fn fall_back_to_native_syntax() {
+        let mut me = Sheet::new();
+        me.insert(String::from("skill_spot"), Field::Integer(60));
+        let macros = [String::from("atk := 2d6+4")];
+        let roll = |src| {
+            let rolled = roll::evaluate(&Syntax::BCDice, None, &macros, Some(&me), 0, src)
+                .unwrap_or_else(|err| panic!("{} : {}", src, err));
+            format!("{}", rolled.value)
+        };
+        assert_eq!(roll("x := 3; x * 2"), "6");
+        assert_eq!(roll("me.skill_spot"), "60");
+        assert_eq!(roll("1d100 <= 100 + me.skill_spot"), "成功");
+        assert_eq!(roll("2D6 + 1 <= 1"), "失敗");
+        assert_eq!(roll("atk * 0"), "0");
+        assert_eq!(roll("max_of 1 [1, 2]"), "[2]");
+    }
+}
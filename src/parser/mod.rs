@@ -1,9 +1,105 @@
 use async_std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 pub mod ast;
+mod bcdice;
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub enum Syntax {
+    #[default]
+    Native,
+    BCDice,
+}
+
+impl Syntax {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Native => "Native",
+            Self::BCDice => "BCDice",
+        }
+    }
+
+    pub fn find(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "native" => Some(Self::Native),
+            "bcdice" => Some(Self::BCDice),
+            _ => None,
+        }
+    }
+}
+
+pub fn parse(
+    syntax: &Syntax,
+    src: &str,
+) -> Result<ast::Proc, peg::error::ParseError<peg::str::LineCol>> {
+    let source = Arc::new(String::from(src));
+    match syntax {
+        Syntax::Native => context::parse(src, &source),
+        // 1d100 <= me.xのような式をコメント付きのコマンドとして読まないよう、
+        // コメントのないBCDiceのコマンド、このボットの式、コメント付きのコマンドの順に試す
+        Syntax::BCDice => bcdice::context::without_comment(src, &source)
+            .or_else(|_| context::parse(src, &source))
+            .or_else(|_| bcdice::context::parse(src, &source)),
+    }
+}
+
+// 二項演算の位置は、左右の式を含む範囲
+fn operate(operator: &str, left: ast::Expr0, right: ast::Expr0) -> ast::Expr0 {
+    let span = left.span().join(right.span());
+    ast::Expr0::Expr0 {
+        left: Arc::new(left),
+        right: Arc::new(right),
+        operator: String::from(operator),
+        span,
+    }
+}
+
+// 以下の関数で作る式には、元になったコマンド全体の位置を付ける
+fn integer(n: i64, span: &ast::Span) -> ast::Expr0 {
+    ast::Expr0::Term(ast::Term::Literal(ast::Literal::Integer(n)), span.clone())
+}
+
+// 同じオプションが複数回指定された場合、criticalは後の指定を優先し、それ以外は合算する
+fn options(options: Vec<(&'static str, i64)>, span: &ast::Span) -> ast::Expr0 {
+    let mut record = HashMap::new();
+    for (key, n) in options {
+        let val = record.entry(Arc::new(String::from(key))).or_insert(0);
+        if key == "critical" {
+            *val = n;
+        } else {
+            *val += n;
+        }
+    }
+    ast::Expr0::Term(
+        ast::Term::Record(
+            record
+                .into_iter()
+                .map(|(key, n)| (key, Arc::new(integer(n, span))))
+                .collect(),
+        ),
+        span.clone(),
+    )
+}
+
+fn call(name: &str, args: Vec<ast::Expr0>, span: &ast::Span) -> ast::Expr0 {
+    let func = ast::Expr0::Term(
+        ast::Term::Literal(ast::Literal::Ident(ast::Ident::Strict(Arc::new(
+            String::from(name),
+        )))),
+        span.clone(),
+    );
+    args.into_iter()
+        .fold(func, |left, right| ast::Expr0::Expr0 {
+            left: Arc::new(left),
+            right: Arc::new(right),
+            operator: String::from(" "),
+            span: span.clone(),
+        })
+}
 
 peg::parser! {
-    pub grammar context() for str {
+    pub grammar context(source: &Arc<String>) for str {
         pub rule parse() ->  ast::Proc
             = precedence! {
                 dlm()? p: proc() dlm()? { p }
@@ -12,61 +108,122 @@ peg::parser! {
             }
 
         rule proc() -> ast::Proc
-            = exprs: expr0() ++ (dlm()? ";" dlm()?) { ast::Proc::new(exprs.into_iter().map(|expr| Arc::new(expr)).collect())}
+            = exprs: expr0() ++ (dlm()? ";" dlm()?) { ast::Proc::new(exprs.into_iter().map(Arc::new).collect())}
 
         rule expr0() -> ast::Expr0
             = precedence! {
-                i:ident() dlm()? ":=" dlm()? value: expr0() {ast::Expr0::Def {ident:Arc::new(i), value: Arc::new(value)}}
+                def:definition() { def }
                 --
-                left:(@) dlm() right:@ {ast::Expr0::Expr0{left: Arc::new(left), right: Arc::new(right), operator: String::from(" ")}}
+                left:(@) dlm() right:@ { operate(" ", left, right) }
                 --
-                "\\" dlm()? lambda:lambda() { lambda }
+                lambda:function() { lambda }
                 --
-                left:(@) dlm()? "#" dlm()? right:@ {ast::Expr0::Expr0{left: Arc::new(left), right: Arc::new(right), operator: String::from("#")}}
+                left:(@) dlm()? "#" dlm()? right:@ { operate("#", left, right) }
                 --
-                left:(@) dlm()? "@==" dlm()? right:@ {ast::Expr0::Expr0{left: Arc::new(left), right: Arc::new(right), operator: String::from("@==")}}
-                left:(@) dlm()? "@!=" dlm()? right:@ {ast::Expr0::Expr0{left: Arc::new(left), right: Arc::new(right), operator: String::from("@!=")}}
-                left:(@) dlm()? "@<=" dlm()? right:@ {ast::Expr0::Expr0{left: Arc::new(left), right: Arc::new(right), operator: String::from("@<=")}}
-                left:(@) dlm()? "@>=" dlm()? right:@ {ast::Expr0::Expr0{left: Arc::new(left), right: Arc::new(right), operator: String::from("@>=")}}
+                left:(@) dlm()? "@==" dlm()? right:@ { operate("@==", left, right) }
+                left:(@) dlm()? "@!=" dlm()? right:@ { operate("@!=", left, right) }
+                left:(@) dlm()? "@<=" dlm()? right:@ { operate("@<=", left, right) }
+                left:(@) dlm()? "@>=" dlm()? right:@ { operate("@>=", left, right) }
                 --
-                left:(@) dlm()? "@<" dlm()? right:@ {ast::Expr0::Expr0{left: Arc::new(left), right: Arc::new(right), operator: String::from("@<")}}
-                left:(@) dlm()? "@>" dlm()? right:@ {ast::Expr0::Expr0{left: Arc::new(left), right: Arc::new(right), operator: String::from("@>")}}
+                left:(@) dlm()? "@<" dlm()? right:@ { operate("@<", left, right) }
+                left:(@) dlm()? "@>" dlm()? right:@ { operate("@>", left, right) }
                 --
-                left:(@) dlm()? "@" dlm()? right:@ {ast::Expr0::Expr0{left: Arc::new(left), right: Arc::new(right), operator: String::from("@")}}
+                left:(@) dlm()? "@" dlm()? right:@ { operate("@", left, right) }
                 --
-                left:(@) dlm()? "==" dlm()? right:@ {ast::Expr0::Expr0{left: Arc::new(left), right: Arc::new(right), operator: String::from("==")}}
-                left:(@) dlm()? "!=" dlm()? right:@ {ast::Expr0::Expr0{left: Arc::new(left), right: Arc::new(right), operator: String::from("!=")}}
-                left:(@) dlm()? "<=" dlm()? right:@ {ast::Expr0::Expr0{left: Arc::new(left), right: Arc::new(right), operator: String::from("<=")}}
-                left:(@) dlm()? ">=" dlm()? right:@ {ast::Expr0::Expr0{left: Arc::new(left), right: Arc::new(right), operator: String::from(">=")}}
+                left:(@) dlm()? "==" dlm()? right:@ { operate("==", left, right) }
+                left:(@) dlm()? "!=" dlm()? right:@ { operate("!=", left, right) }
+                left:(@) dlm()? "<=" dlm()? right:@ { operate("<=", left, right) }
+                left:(@) dlm()? ">=" dlm()? right:@ { operate(">=", left, right) }
                 --
-                left:(@) dlm()? "<" dlm()? right:@ {ast::Expr0::Expr0{left: Arc::new(left), right: Arc::new(right), operator: String::from("<")}}
-                left:(@) dlm()? ">" dlm()? right:@ {ast::Expr0::Expr0{left: Arc::new(left), right: Arc::new(right), operator: String::from(">")}}
+                left:(@) dlm()? "<" dlm()? right:@ { operate("<", left, right) }
+                left:(@) dlm()? ">" dlm()? right:@ { operate(">", left, right) }
                 --
-                left:(@) dlm()? "+" dlm()? right:@ {ast::Expr0::Expr0{left: Arc::new(left), right: Arc::new(right), operator: String::from("+")}}
-                left:(@) dlm()? "-" dlm()? right:@ {ast::Expr0::Expr0{left: Arc::new(left), right: Arc::new(right), operator: String::from("-")}}
+                left:(@) dlm()? "+" dlm()? right:@ { operate("+", left, right) }
+                left:(@) dlm()? "-" dlm()? right:@ { operate("-", left, right) }
                 --
-                left:(@) dlm()? "*" dlm()? right:@ {ast::Expr0::Expr0{left: Arc::new(left), right: Arc::new(right), operator: String::from("*")}}
-                left:(@) dlm()? "/" dlm()? right:@ {ast::Expr0::Expr0{left: Arc::new(left), right: Arc::new(right), operator: String::from("/")}}
+                left:(@) dlm()? "*" dlm()? right:@ { operate("*", left, right) }
+                left:(@) dlm()? "/" dlm()? right:@ { operate("/", left, right) }
                 --
-                left:(@) dlm()? "." dlm()? right:@ {ast::Expr0::Expr0{left: Arc::new(left), right: Arc::new(right), operator: String::from(".")}}
+                left:(@) dlm()? "." dlm()? right:@ { operate(".", left, right) }
                 --
-                left:(@) dlm()? ("d"/"D") dlm()? right:@ {ast::Expr0::Expr0{left: Arc::new(left), right: Arc::new(right), operator: String::from("d")}}
-                left:(@) dlm()? ("b"/"B") dlm()? right:@ {ast::Expr0::Expr0{left: Arc::new(left), right: Arc::new(right), operator: String::from("b")}}
+                left:(@) dlm()? ("d"/"D") dlm()? right:@ { operate("d", left, right) }
+                left:(@) dlm()? ("b"/"B") dlm()? right:@ { operate("b", left, right) }
                 --
-                term:term() {ast::Expr0::Term(term)}
+                command:command() { command }
+                term:spanned_term() { term }
+            }
+
+        // precedence!の中では位置を取れないので、位置を付ける式は別の規則にする
+        rule definition() -> ast::Expr0
+            = start:position!() i:ident() dlm()? ":=" dlm()? value: expr0() {
+                let span = ast::Span::new(source, start, value.span().end);
+                ast::Expr0::Def {ident:Arc::new(i), value: Arc::new(value), span}
             }
 
-        rule lambda() -> ast::Expr0
+        rule function() -> ast::Expr0
+            = start:position!() "\\" dlm()? lambda:lambda(start) { lambda }
+
+        rule spanned_term() -> ast::Expr0
+            = start:position!() term:term() end:position!() {ast::Expr0::Term(term, ast::Span::new(source, start, end))}
+
+        rule command() -> ast::Expr0
+            = start:position!() ("CC" / "cc") n:cc_dice()? dlm()? "<=" dlm()? target:expr0() {
+                let span = ast::Span::new(source, start, target.span().end);
+                call("cc", vec![integer(n.unwrap_or(0), &span), target], &span)
+            }
+            / start:position!() "K" rate:integer() opts:rating_option()* end:position!() {
+                let span = ast::Span::new(source, start, end);
+                call("k", vec![options([vec![("rate", rate)], opts].concat(), &span)], &span)
+            }
+            / start:position!() count:integer() "DX" critical:integer()? opts:dx_option()* end:position!() {
+                let span = ast::Span::new(source, start, end);
+                let critical = critical.map(|n| ("critical", n)).into_iter().collect();
+                call("dx", vec![options([vec![("count", count)], critical, opts].concat(), &span)], &span)
+            }
+
+        rule rating_option() -> (&'static str, i64)
             = precedence! {
-                arg:strict_ident() dlm()? "->" dlm()? value:expr0() {ast::Expr0::Fn {arg, value: Arc::new(value)}}
-                arg:strict_ident() dlm() args:lambda() {ast::Expr0::Fn {arg, value: Arc::new(args)}}
+                "@" n:integer() { ("critical", n) }
+                ("r" / "R") n:integer() { ("rate_up", n) }
+                "$" n:integer_with_sign() { ("crit_ray", n) }
+                n:integer_with_sign() { ("modifier", n) }
+            }
+
+        rule dx_option() -> (&'static str, i64)
+            = precedence! {
+                "@" n:integer() { ("critical", n) }
+                n:integer_with_sign() { ("modifier", n) }
+            }
+
+        rule cc_dice() -> i64
+            = "(" dlm()? n:(integer() / integer_with_sign()) dlm()? ")" { n }
+
+        // 桁あふれする整数は0として扱わずに構文エラーにする
+        rule integer() -> i64
+            = n:$(['0'..='9']+) {? n.parse().or(Err("integer")) }
+
+        rule integer_with_sign() -> i64
+            = "+" n:integer() { n }
+            / "-" n:integer() { -n }
+
+        // startは関数の定義の始まり。引数が複数ある場合、内側の関数は次の引数から始まる
+        rule lambda(start: usize) -> ast::Expr0
+            = precedence! {
+                arg:strict_ident() dlm()? "->" dlm()? value:expr0() {
+                    let span = ast::Span::new(source, start, value.span().end);
+                    ast::Expr0::Fn {arg, value: Arc::new(value), span}
+                }
+                arg:strict_ident() dlm() next:position!() args:lambda(next) {
+                    let span = ast::Span::new(source, start, args.span().end);
+                    ast::Expr0::Fn {arg, value: Arc::new(args), span}
+                }
             }
 
         rule term() -> ast::Term
             = precedence! {
                 "(" dlm()? expr:expr0() dlm()? ")" {ast::Term::Expr0(Arc::new(expr))}
                 "{" dlm()? p:proc() dlm()? "}" {ast::Term::Proc(p)}
-                "[" dlm()? exprs:expr0() ** (dlm()? "," dlm()?) dlm()? "]" {ast::Term::Array(exprs.into_iter().map(|exp0| Arc::new(exp0)).collect())}
+                "[" dlm()? exprs:expr0() ** (dlm()? "," dlm()?) dlm()? "]" {ast::Term::Array(exprs.into_iter().map(Arc::new).collect())}
                 "{" dlm()? pairs:key_value() ** (dlm()? "," dlm()?) dlm()? "}" {ast::Term::Record(pairs.into_iter().map(|(key, exp0)| (key, Arc::new(exp0))).collect())}
                 --
                 literal:literal() {ast::Term::Literal(literal)}
@@ -77,7 +234,8 @@ peg::parser! {
 
         rule literal() -> ast::Literal
             = precedence! {
-                n:$(['0'..='9']+) { ast::Literal::Integer(n.parse().unwrap_or(0)) }
+                n:integer() { ast::Literal::Integer(n) }
+                "\"" s:$((!"\"" [_])*) "\"" { ast::Literal::String(Arc::new(String::from(s))) }
                 i:ident() {ast::Literal::Ident(i)}
             }
 
@@ -93,3 +251,33 @@ peg::parser! {
         rule dlm() = quiet!{[' ' | '\n' | '\t']+}
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reject_misplaced_sign() {
+        for src in &["-3DX", "K20+-3", "10DX@-8", "2DX--1", "CC(+-1)<=50"] {
+            assert!(parse(&Syntax::Native, src).is_err(), "{}", src);
+        }
+        // 負の値の威力表は引かず、Kという名前からの引き算として扱う
+        assert_eq!(
+            parse(&Syntax::Native, "K-5").unwrap().to_string(),
+            "(K - 5)"
+        );
+        for src in &["K20-3", "K20+3$-1", "3DX+1@8", "CC(-2)<=60", "CC(1)<=60"] {
+            assert!(parse(&Syntax::Native, src).is_ok(), "{}", src);
+        }
+    }
+
+    #[test]
+    fn reject_overflow() {
+        for syntax in &[Syntax::Native, Syntax::BCDice] {
+            assert!(parse(syntax, "9223372036854775807").is_ok());
+            assert!(parse(syntax, "9223372036854775808").is_err());
+            assert!(parse(syntax, "3d99999999999999999999").is_err());
+            assert!(parse(syntax, "K20+99999999999999999999").is_err());
+        }
+    }
+}
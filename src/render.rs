@@ -0,0 +1,402 @@
+use crate::locale::{self, Language, Message};
+use crate::runtime::{trace::Trace, Level, LogEntry, Value};
+use async_std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+// 埋め込みの色
+const COLOR_NEUTRAL: u32 = 0x5865f2;
+const COLOR_SUCCESS: u32 = 0x2ecc71;
+const COLOR_FAILURE: u32 = 0xe74c3c;
+const COLOR_CRITICAL: u32 = 0xf1c40f;
+const COLOR_FUMBLE: u32 = 0x992d22;
+
+// Discordの埋め込みの文字数制限
+const TITLE_LIMIT: usize = 256;
+pub const FIELD_LIMIT: usize = 1024;
+pub const FIELDS_LIMIT: usize = 25;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Style {
+    Text,
+    Embed,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Critical,
+    Success,
+    Failure,
+    Fumble,
+}
+
+impl Outcome {
+    fn label(self) -> String {
+        match self {
+            Self::Critical => Message::Critical.text(),
+            Self::Success => Message::Success.text(),
+            Self::Failure => Message::Failure.text(),
+            Self::Fumble => Message::Fumble.text(),
+        }
+    }
+
+    fn from_level(level: Level) -> Self {
+        match level {
+            Level::Critical | Level::ClassicCritical => Self::Critical,
+            Level::Fumble | Level::ClassicFumble | Level::AutomaticFailure => Self::Fumble,
+            level if level.is_success() => Self::Success,
+            _ => Self::Failure,
+        }
+    }
+
+    fn color(self) -> u32 {
+        match self {
+            Self::Critical => COLOR_CRITICAL,
+            Self::Success => COLOR_SUCCESS,
+            Self::Failure => COLOR_FAILURE,
+            Self::Fumble => COLOR_FUMBLE,
+        }
+    }
+}
+
+// 1回分のロール結果
+pub struct Rolled {
+    pub input: String,
+    pub log: Vec<LogEntry>,
+    pub value: Arc<Value>,
+    // 振り直しに使う乱数のシード
+    pub seed: u64,
+    pub note: &'static str,
+    // 評価したときの言語。結果もこの言語で表示する
+    pub language: Language,
+    // トレースを取って評価した場合だけ、評価の過程を持つ
+    pub trace: Option<Trace>,
+}
+
+impl Rolled {
+    // 結果が判定のレコードなら、最後の判定の成功度
+    fn level(&self) -> Option<Level> {
+        match self.value.as_ref() {
+            Value::Record(record) if record.contains_key(&String::from("level")) => {
+                self.log.iter().rev().find_map(|entry| match entry {
+                    LogEntry::Roll(event) => event.level,
+                    _ => None,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    // 成否の判定。成否を持たない値の場合はNone
+    pub fn outcome(&self) -> Option<Outcome> {
+        if let Some(level) = self.level() {
+            return Some(Outcome::from_level(level));
+        }
+        match self.value.as_ref() {
+            Value::Boolean(true) => Some(Outcome::Success),
+            Value::Boolean(false) => Some(Outcome::Failure),
+            Value::Record(record) => {
+                let get = |key: &str| record.get(&String::from(key)).map(Arc::as_ref);
+
+                if matches!(get("fumble"), Some(Value::Boolean(true))) {
+                    Some(Outcome::Fumble)
+                } else if matches!(get("critical"), Some(Value::Integer(n)) if *n > 0)
+                    || matches!(get("critical"), Some(Value::Boolean(true)))
+                {
+                    Some(Outcome::Critical)
+                } else {
+                    match get("success") {
+                        Some(Value::Boolean(true)) => Some(Outcome::Success),
+                        Some(Value::Boolean(false)) => Some(Outcome::Failure),
+                        _ => None,
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+
+    // 結果がエラーの場合、エラーになった部分式
+    fn error_source(&self) -> String {
+        if !self.value.is_err() {
+            return String::new();
+        }
+        self.log
+            .iter()
+            .find_map(|entry| match entry {
+                LogEntry::Error { span, .. } => Some(Message::ErrorSource(&span.text).text()),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn text(&self) -> String {
+        locale::using(self.language, || self.format_text())
+    }
+
+    fn format_text(&self) -> String {
+        let mut res = format!("{}\n", self.input);
+
+        if !self.value.is_err() {
+            for a_line in &self.log {
+                res += format!(" -> {}", log_line(a_line)).as_str();
+            }
+        }
+
+        res += format!(" -> {}{}", self.value, self.error_source()).as_str();
+
+        if !self.note.is_empty() {
+            res += "\n";
+            res += self.note;
+        };
+
+        res
+    }
+
+    pub fn embed(&self) -> serde_json::Value {
+        locale::using(self.language, || self.format_embed())
+    }
+
+    fn format_embed(&self) -> serde_json::Value {
+        let outcome = self.outcome();
+        let color = match outcome {
+            Some(outcome) => outcome.color(),
+            None if self.value.is_err() => COLOR_FAILURE,
+            None => COLOR_NEUTRAL,
+        };
+
+        let mut fields = vec![];
+        if !self.log.is_empty() && !self.value.is_err() {
+            fields.push(json!({
+                "name": Message::DiceField.text(),
+                "value": truncate(
+                    &self.log.iter().map(log_line).collect::<Vec<_>>().join("\n"),
+                    FIELD_LIMIT
+                ),
+            }));
+        }
+        fields.push(json!({
+            "name": Message::ResultField.text(),
+            "value": truncate(&format!("{}{}", self.value, self.error_source()), FIELD_LIMIT),
+            "inline": true,
+        }));
+        if let Some(outcome) = outcome {
+            // CoCの成功度のように、詳しい判定があればそちらを表示する
+            let label = match self.level() {
+                Some(level) => Message::Level(level).text(),
+                None => outcome.label(),
+            };
+            let label = match outcome {
+                Outcome::Critical | Outcome::Fumble => Message::Emphasized(&label).text(),
+                _ => label,
+            };
+            fields.push(json!({
+                "name": Message::OutcomeField.text(),
+                "value": label,
+                "inline": true,
+            }));
+        }
+
+        let mut embed = json!({
+            "title": truncate(&self.input, TITLE_LIMIT),
+            "color": color,
+            "fields": fields,
+        });
+        if !self.note.is_empty() {
+            embed["footer"] = json!({ "text": self.note });
+        }
+        embed
+    }
+}
+
+// 経過の1行を表示する。ダイスは「合計 [出目]」、選んだ出目は「[出目] → [選んだ出目]」。
+// 判定の場合は後ろに成功度を付ける
+pub fn log_line(entry: &LogEntry) -> String {
+    match entry {
+        LogEntry::Roll(event) => {
+            let res = if !event.dropped.is_empty() {
+                format!("{:?} → {:?}", event.faces, event.kept)
+            } else {
+                match event.total {
+                    Some(total) => format!("{} {:?}", total, event.faces),
+                    None => format!("{:?}", event.faces),
+                }
+            };
+            match event.level {
+                Some(level) => format!("{} → {}", res, Message::Level(level).text()),
+                None => res,
+            }
+        }
+        LogEntry::Error { span, message } => {
+            format!("{} → {}", span.text, Message::Error(message).text())
+        }
+        LogEntry::Text(text) => text.clone(),
+    }
+}
+
+// 文字数の上限を超える場合は末尾を切り詰める
+pub fn truncate(text: &str, limit: usize) -> String {
+    if text.chars().count() <= limit {
+        String::from(text)
+    } else {
+        let mut res: String = text.chars().take(limit - 1).collect();
+        res.push('…');
+        res
+    }
+}
+
+// limit文字以下ずつに分割する。できるだけ改行かログの区切りで分ける。
+// コードブロックの途中で分ける場合は、前の塊で閉じて次の塊で開き直す
+pub fn split(text: &str, limit: usize) -> Vec<String> {
+    const FENCE: &str = "```";
+    // 閉じるための「\n```」と開き直すための「```\n」の分を空けておく
+    let reserved = if text.contains(FENCE) { 4 } else { 0 };
+
+    let mut res = vec![];
+    let mut rest = text;
+    let mut fenced = false;
+
+    loop {
+        let reopen = if fenced { "```\n" } else { "" };
+        let limit = limit - reopen.len();
+        if rest.chars().count() <= limit {
+            break;
+        }
+
+        let end = rest
+            .char_indices()
+            .nth(limit - reserved)
+            .map_or(rest.len(), |(idx, _)| idx);
+        let head = &rest[..end];
+        let cut = head
+            .rfind('\n')
+            .or_else(|| head.rfind(" -> "))
+            .filter(|idx| *idx > 0)
+            .unwrap_or(end);
+        let chunk = &rest[..cut];
+
+        let open = fenced ^ (chunk.matches(FENCE).count() % 2 == 1);
+        let close = if open { "\n```" } else { "" };
+        res.push(format!("{}{}{}", reopen, chunk, close));
+        fenced = open;
+        rest = rest[cut..].trim_start_matches('\n');
+    }
+    if !rest.is_empty() {
+        let reopen = if fenced { "```\n" } else { "" };
+        res.push(format!("{}{}", reopen, rest));
+    }
+
+    res
+}
+
+// limit文字を超える場合は、先頭の式と末尾の結果だけを残して途中を省略する
+pub fn summarize(text: &str, limit: usize) -> String {
+    let omitted = format!("\n{}\n…", Message::Omitted.text());
+
+    let count = text.chars().count();
+    if count <= limit {
+        return String::from(text);
+    }
+
+    let head = truncate(text.lines().next().unwrap_or_default(), limit / 4);
+    let tail_len = limit - head.chars().count() - omitted.chars().count();
+    let tail: String = text.chars().skip(count - tail_len).collect();
+    format!("{}{}{}", head, omitted, tail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+    use crate::roll;
+
+    #[test]
+    fn judge_by_level() {
+        let mut seen = vec![];
+        for seed in 0..300 {
+            let rolled =
+                roll::evaluate(&parser::Syntax::Native, None, &[], None, seed, "ccb 50").unwrap();
+            let level = rolled.level().unwrap();
+            let outcome = rolled.outcome().unwrap();
+            assert!(outcome == Outcome::from_level(level));
+            let success = matches!(outcome, Outcome::Critical | Outcome::Success);
+            assert_eq!(success, level.is_success());
+            if !seen.contains(&level) {
+                seen.push(level);
+            }
+        }
+        // 第6版の全ての成功度が出る
+        assert_eq!(seen.len(), 4);
+    }
+
+    #[test]
+    fn truncate_multibyte_text() {
+        assert_eq!(truncate("クリティカル", 6), "クリティカル");
+        assert_eq!(truncate("クリティカル", 5), "クリティ…");
+        assert_eq!(truncate("a漢字b", 3), "a漢…");
+    }
+
+    #[test]
+    fn split_at_line_breaks() {
+        let text = "あいう\nえおか\nきく";
+        assert_eq!(split(text, 8), ["あいう\nえおか", "きく"]);
+        assert_eq!(split(text, 100), [text]);
+        // 区切りがない場合は文字の境界で分ける
+        assert_eq!(split("あいうえおかき", 3), ["あいう", "えおか", "き"]);
+        assert_eq!(split("2d6 -> 7 -> 成功", 12), ["2d6 -> 7", " -> 成功"]);
+        assert!(split("", 10).is_empty());
+    }
+
+    #[test]
+    fn close_code_blocks_across_chunks() {
+        let rows: Vec<_> = (1..=30)
+            .map(|n| format!("{:>2}：{}", n, "■".repeat(n % 7)))
+            .collect();
+        let text = format!("分布\n```\n{}\n```\n平均3.5", rows.join("\n"));
+        let chunks = split(&text, 60);
+        assert!(chunks.len() > 2);
+        for chunk in &chunks {
+            assert!(chunk.chars().count() <= 60, "{}", chunk);
+            assert_eq!(chunk.matches("```").count() % 2, 0, "{}", chunk);
+        }
+        assert!(chunks[0].starts_with("分布\n```\n"));
+        assert!(chunks[1].starts_with("```\n"));
+        assert!(chunks.last().unwrap().ends_with("```\n平均3.5"));
+
+        // 開き直したコードブロックの印を除けば、元の文字列に戻る
+        let joined: Vec<_> = chunks
+            .iter()
+            .enumerate()
+            .map(|(idx, chunk)| {
+                let chunk = if idx > 0 {
+                    chunk.strip_prefix("```\n").unwrap_or(chunk)
+                } else {
+                    chunk
+                };
+                if idx + 1 < chunks.len() {
+                    chunk.strip_suffix("\n```").unwrap_or(chunk)
+                } else {
+                    chunk
+                }
+            })
+            .collect();
+        assert_eq!(joined.join("\n"), text);
+    }
+
+    #[test]
+    fn summarize_long_text() {
+        let text = format!("{}\n{}\n結果：成功", "1d100<=50", "経過\n".repeat(1000));
+        let summary = summarize(&text, 100);
+        assert_eq!(summary.chars().count(), 100);
+        assert!(summary.starts_with("1d100<=50\n（長すぎるため途中を省略しました）"));
+        assert!(summary.ends_with("結果：成功"));
+        assert_eq!(summarize("短い", 100), "短い");
+
+        let long_head = format!("{}\n結果", "あ".repeat(200));
+        let summary = summarize(&long_head, 100);
+        assert_eq!(summary.chars().count(), 100);
+        assert!(summary.starts_with(&format!("{}…", "あ".repeat(24))));
+    }
+}
@@ -0,0 +1,155 @@
+use crate::game_system::{self, GameSystem};
+use crate::locale;
+use crate::parser::{self, ast};
+use crate::render::Rolled;
+use crate::runtime;
+use crate::sheet::{self, Sheet};
+use async_std::sync::Arc;
+use rand::SeedableRng;
+
+// ダイス式を現在の言語で評価する。同じ引数とシードからは同じ結果が得られる
+pub fn evaluate(
+    syntax: &parser::Syntax,
+    system: Option<&dyn GameSystem>,
+    macros: &[String],
+    me: Option<&Sheet>,
+    seed: u64,
+    content: &str,
+) -> Result<Rolled, String> {
+    run(syntax, system, macros, me, seed, content, false)
+}
+
+// evaluateと同じ結果を、評価の過程を記録しながら求める
+pub fn trace(
+    syntax: &parser::Syntax,
+    system: Option<&dyn GameSystem>,
+    macros: &[String],
+    me: Option<&Sheet>,
+    seed: u64,
+    content: &str,
+) -> Result<Rolled, String> {
+    run(syntax, system, macros, me, seed, content, true)
+}
+
+fn run(
+    syntax: &parser::Syntax,
+    system: Option<&dyn GameSystem>,
+    macros: &[String],
+    me: Option<&Sheet>,
+    seed: u64,
+    content: &str,
+    traced: bool,
+) -> Result<Rolled, String> {
+    match parser::parse(syntax, content) {
+        Ok(exp0) => {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+            let mut env = environment(system, macros, me);
+            let mut log = vec![];
+            let begin_time = std::time::Instant::now();
+
+            // マクロの定義はトレースに含めない
+            let (value, trace) = if traced {
+                let (res, trace) = runtime::trace::tracing(|| {
+                    exp0.evalute(&mut env, &mut rng, &mut log, &begin_time)
+                });
+                (res, Some(trace))
+            } else {
+                (
+                    exp0.evalute(&mut env, &mut rng, &mut log, &begin_time),
+                    None,
+                )
+            };
+
+            Ok(Rolled {
+                input: String::from(content),
+                log,
+                value,
+                seed,
+                note: include_str!("./msg"),
+                language: locale::current(),
+                trace,
+            })
+        }
+        Err(err) => Err(format!("{:?}", err)),
+    }
+}
+
+// 組み込み関数、ゲームシステムのコマンド、me、マクロを定義した環境を作る
+pub fn environment(
+    system: Option<&dyn GameSystem>,
+    macros: &[String],
+    me: Option<&Sheet>,
+) -> runtime::Environment {
+    let mut env = runtime::Environment::new();
+    async_std::task::block_on(runtime::built_in_function::set_default(&mut env));
+    async_std::task::block_on(game_system::set_default(&mut env, system));
+
+    if let Some(me) = me {
+        async_std::task::block_on(env.insert(
+            Arc::new(ast::Ident::Strict(Arc::new(String::from("me")))),
+            Arc::new(sheet::to_value(me)),
+        ));
+    }
+
+    // 保存されているマクロはx?と同じく、参照されたときに評価する
+    for definition in macros {
+        if let Ok(proc) = parser::parse(&parser::Syntax::Native, definition) {
+            for expr in proc.iter() {
+                if let ast::Expr0::Def { ident, value, .. } = expr.as_ref() {
+                    async_std::task::block_on(env.insert(
+                        Arc::clone(ident),
+                        Arc::new(runtime::Value::Lazy(Arc::clone(value))),
+                    ));
+                }
+            }
+        }
+    }
+
+    env
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::LogEntry;
+
+    #[test]
+    fn roll_macro_when_referenced() {
+        let macros = [String::from("atk := 2d6+4")];
+        let roll =
+            |content| evaluate(&parser::Syntax::Native, None, &macros, None, 0, content).unwrap();
+        let events = |rolled: &Rolled| -> Vec<_> {
+            rolled
+                .log
+                .iter()
+                .filter_map(|entry| match entry {
+                    LogEntry::Roll(event) => Some(event.clone()),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        // マクロのダイスが経過に残る
+        let rolled = roll("atk");
+        let single = events(&rolled);
+        assert_eq!(single.len(), 1);
+        assert_eq!(single[0].faces.len(), 2);
+        assert_eq!(
+            rolled.value.as_integer(),
+            Some(single[0].faces.iter().sum::<i64>() + 4)
+        );
+
+        // 参照するたびに振り直す
+        let rolled = roll("atk + atk");
+        let double = events(&rolled);
+        assert_eq!(double.len(), 2);
+        assert_eq!(double[0].faces, single[0].faces);
+        let sum: i64 = double.iter().flat_map(|event| &event.faces).sum();
+        assert_eq!(rolled.value.as_integer(), Some(sum + 8));
+        assert_ne!(double[0].faces, double[1].faces);
+
+        // 使わないマクロは振らない
+        let rolled = roll("1");
+        assert!(events(&rolled).is_empty());
+    }
+}
@@ -1,19 +1,23 @@
+use crate::locale::Message;
 use crate::parser::ast;
 use async_std::sync::Arc;
 use std::collections::HashMap;
 
 pub mod built_in_function;
 mod environment;
+mod event;
+pub mod trace;
 
 pub use environment::Environment;
 pub use environment::Value;
+pub use event::{Level, LogEntry, RollEvent, SourceSpan};
 
 const TIME_LIMIT: u128 = 1000;
 
 macro_rules! check_tle {
     ($t:expr) => {
         if (std::time::Instant::now() - *$t).as_millis() > TIME_LIMIT {
-            return Arc::new(Value::Err(format!("TLE (Limit :{} ms)", TIME_LIMIT)));
+            return Arc::new(Value::Err(Message::TimeLimit(TIME_LIMIT).text()));
         }
     };
 }
@@ -28,14 +32,20 @@ impl std::fmt::Debug for Value {
         match self {
             Self::None => write!(f, "None"),
             Self::Integer(val) => write!(f, "{}", val),
-            Self::Boolean(true) => write!(f, "成功"),
-            Self::Boolean(false) => write!(f, "失敗"),
+            Self::Boolean(true) => write!(f, "{}", Message::Success.text()),
+            Self::Boolean(false) => write!(f, "{}", Message::Failure.text()),
             Self::String(val) => write!(f, "{}", val.as_str()),
             Self::Array(vals) => {
                 write!(f, "{}", Self::fmt_array(vals))
             }
             Self::Record(vals) => {
-                write!(f, "{:?}", vals)
+                let mut vals: Vec<_> = vals.iter().collect();
+                vals.sort_by(|x, y| x.0.cmp(y.0));
+                let vals: Vec<_> = vals
+                    .into_iter()
+                    .map(|(key, val)| format!("{}: {:?}", key, val))
+                    .collect();
+                write!(f, "{{{}}}", vals.join(", "))
             }
             Self::Fn { arg, .. } => {
                 write!(f, "fn {}", arg.as_str())
@@ -47,7 +57,7 @@ impl std::fmt::Debug for Value {
                 write!(f, "Lazy")
             }
             Self::Err(err) => {
-                write!(f, "エラー：{}", err.as_str())
+                write!(f, "{}", Message::Error(err).text())
             }
         }
     }
@@ -64,12 +74,12 @@ impl ast::Proc {
         &self,
         env: &mut Environment,
         rng: &mut impl rand::Rng,
-        log: &mut Vec<String>,
+        log: &mut Vec<LogEntry>,
         begin_time: &std::time::Instant,
     ) -> Arc<Value> {
         let mut res = Arc::new(Value::None);
         for expr in self.iter() {
-            res = expr.evalute(env, rng, log, &begin_time);
+            res = expr.evalute(env, rng, log, begin_time);
         }
         res
     }
@@ -80,11 +90,55 @@ impl ast::Expr0 {
         &self,
         env: &mut Environment,
         rng: &mut impl rand::Rng,
-        log: &mut Vec<String>,
+        log: &mut Vec<LogEntry>,
+        begin_time: &std::time::Instant,
+    ) -> Arc<Value> {
+        // 数や文字列、関数の定義は、ダイスを振ることもエラーになることもないので記録しない
+        let traced = match self {
+            Self::Expr0 { .. } | Self::Def { .. } => true,
+            Self::Term(ast::Term::Literal(literal), _) => matches!(literal, ast::Literal::Ident(_)),
+            Self::Term(ast::Term::Proc(_), _) => true,
+            _ => false,
+        };
+        if !traced {
+            return self.evalute_node(env, rng, log, begin_time);
+        }
+        trace::enter(|| String::from(self.span().text()));
+        let mark = log.len();
+        let val = self.evalute_node(env, rng, log, begin_time);
+        // この式の評価中に振られ、まだ位置のないダイスは、この式によるものとする
+        for entry in &mut log[mark..] {
+            if let LogEntry::Roll(event) = entry {
+                if event.span.is_none() {
+                    event.span = Some(SourceSpan::from(self.span()));
+                }
+            }
+        }
+        if let Value::Err(err) = val.as_ref() {
+            // 最初にエラーになった、最も内側の式だけを記録する
+            if !log
+                .iter()
+                .any(|entry| matches!(entry, LogEntry::Error { .. }))
+            {
+                log.push(LogEntry::Error {
+                    span: SourceSpan::from(self.span()),
+                    message: err.clone(),
+                });
+            }
+        }
+        trace::leave(&val);
+        val
+    }
+
+    fn evalute_node(
+        &self,
+        env: &mut Environment,
+        rng: &mut impl rand::Rng,
+        log: &mut Vec<LogEntry>,
         begin_time: &std::time::Instant,
     ) -> Arc<Value> {
         match self {
-            Self::Def { ident, value } => {
+            Self::Def { ident, value, .. } => {
                 if ident.is_strict() {
                     let value = value.evalute(env, rng, log, begin_time);
                     async_std::task::block_on(env.insert(Arc::clone(ident), value));
@@ -95,7 +149,7 @@ impl ast::Expr0 {
                 }
                 Arc::new(Value::None)
             }
-            Self::Fn { arg, value } => {
+            Self::Fn { arg, value, .. } => {
                 let env = async_std::task::block_on(env.capture());
                 let arg = Arc::clone(arg);
                 let value = Arc::clone(value);
@@ -105,6 +159,7 @@ impl ast::Expr0 {
                 left,
                 right,
                 operator,
+                ..
             } => match operator.as_str() {
                 "#" => {
                     let right = right.evalute(env, rng, log, begin_time);
@@ -148,20 +203,41 @@ impl ast::Expr0 {
 
                         check_tle!(begin_time);
 
+                        trace::enter(|| format!("{}回目", res.len() + 1));
                         rep = left.evalute(env, rng, log, begin_time);
                         cmp = right.evalute(env, rng, log, begin_time);
+                        trace::leave(&rep);
                         res.push(Arc::clone(&rep));
                     }
 
                     Arc::new(Value::Array(res))
                 }
+                "." => {
+                    let left = left.evalute(env, rng, log, begin_time);
+                    // レコードの後ろにフィールド名を書いた場合は、そのフィールドの値を取り出す
+                    // 識別子にできない名前のフィールドは me."目星" のように文字列で指定する
+                    if let (Value::Record(record), Self::Term(ast::Term::Literal(literal), _)) =
+                        (left.as_ref(), right.as_ref())
+                    {
+                        let key = match literal {
+                            ast::Literal::Ident(ident) => Some(ident.name()),
+                            ast::Literal::String(key) => Some(Arc::clone(key)),
+                            ast::Literal::Integer(_) => None,
+                        };
+                        if let Some(val) = key.and_then(|key| record.get(&key)) {
+                            return Arc::clone(val);
+                        }
+                    }
+                    let right = right.evalute(env, rng, log, begin_time);
+                    Self::operate(".", left, right, rng, log, begin_time)
+                }
                 op => {
                     let left = left.evalute(env, rng, log, begin_time);
                     let right = right.evalute(env, rng, log, begin_time);
                     Self::operate(op, left, right, rng, log, begin_time)
                 }
             },
-            Self::Term(term) => term.evalute(env, rng, log, begin_time),
+            Self::Term(term, _) => term.evalute(env, rng, log, begin_time),
         }
     }
 
@@ -171,7 +247,7 @@ impl ast::Expr0 {
         right: &Self,
         env: &mut Environment,
         rng: &mut impl rand::Rng,
-        log: &mut Vec<String>,
+        log: &mut Vec<LogEntry>,
         begin_time: &std::time::Instant,
     ) -> Arc<Value> {
         let mut rep = left.evalute(env, rng, log, begin_time);
@@ -184,8 +260,10 @@ impl ast::Expr0 {
         {
             check_tle!(begin_time);
 
+            trace::enter(|| format!("{}回目", res.len() + 1));
             rep = left.evalute(env, rng, log, begin_time);
             cmp = right.evalute(env, rng, log, begin_time);
+            trace::leave(&rep);
             res.push(Arc::clone(&rep));
         }
 
@@ -197,7 +275,7 @@ impl ast::Expr0 {
         left: Arc<Value>,
         right: Arc<Value>,
         rng: &mut impl rand::Rng,
-        log: &mut Vec<String>,
+        log: &mut Vec<LogEntry>,
         begin_time: &std::time::Instant,
     ) -> Arc<Value> {
         match op {
@@ -205,7 +283,7 @@ impl ast::Expr0 {
                 Value::Fn { env, arg, value } => {
                     Self::call_fn(right, Arc::clone(arg), value, env, rng, log, begin_time)
                 }
-                Value::BuiltInFunction { implement, .. } => implement(right),
+                Value::BuiltInFunction { implement, .. } => implement(right, rng, log),
                 _ => Arc::new(Value::None),
             },
             "==" => Self::compare(log, &left, &right, &mut |left, right| {
@@ -286,18 +364,20 @@ impl ast::Expr0 {
                 Value::Fn { env, arg, value } => {
                     Self::call_fn(left, Arc::clone(arg), value, env, rng, log, begin_time)
                 }
-                Value::BuiltInFunction { implement, .. } => implement(left),
+                Value::BuiltInFunction { implement, .. } => implement(left, rng, log),
                 _ => Arc::new(Value::None),
             },
             "b" => {
                 if let (Some(left), Some(right)) = (left.as_integer(), right.as_integer()) {
-                    let mut res = vec![];
+                    let mut faces = vec![];
                     for _ in 0..left {
+                        check_tle!(begin_time);
                         let d: f64 = rng.sample(rand::distributions::OpenClosed01);
-                        res.push(Arc::new(Value::Integer((d * right as f64).ceil() as i64)));
+                        faces.push((d * right as f64).ceil() as i64);
                     }
+                    let res: Vec<_> = faces.iter().map(|d| Arc::new(Value::Integer(*d))).collect();
 
-                    log.push(format!("{:?}", &res));
+                    log.push(LogEntry::Roll(RollEvent::new("b", right, faces, None)));
 
                     Arc::new(Value::Array(res))
                 } else {
@@ -308,6 +388,7 @@ impl ast::Expr0 {
                 if let (Some(left), Some(right)) = (left.as_integer(), right.as_integer()) {
                     let mut res = vec![];
                     for _ in 0..left {
+                        check_tle!(begin_time);
                         let d: f64 = rng.sample(rand::distributions::OpenClosed01);
                         res.push((d * right as f64).ceil() as i64);
                     }
@@ -317,7 +398,7 @@ impl ast::Expr0 {
                         sum += *d;
                     }
 
-                    log.push(format!("{} {:?}", sum, &res));
+                    log.push(LogEntry::Roll(RollEvent::new("d", right, res, Some(sum))));
 
                     Arc::new(Value::Integer(sum))
                 } else {
@@ -329,7 +410,7 @@ impl ast::Expr0 {
     }
 
     fn compare(
-        log: &mut Vec<String>,
+        log: &mut Vec<LogEntry>,
         left: &Value,
         right: &Value,
         operator: &mut impl FnMut(&ValueElement, &ValueElement) -> Value,
@@ -337,7 +418,7 @@ impl ast::Expr0 {
         if let (Some(left), Some(right)) = (left.as_element(), right.as_element()) {
             Arc::new(operator(&left, &right))
         } else if let Some(left) = left.as_array() {
-            log.push(Value::fmt_array(left));
+            log.push(LogEntry::Text(Value::fmt_array(left)));
             let value: Vec<Arc<Value>> = left
                 .iter()
                 .map(|item| Self::compare(log, item, right, operator))
@@ -360,14 +441,16 @@ impl ast::Expr0 {
         value: &ast::Expr0,
         scoped_env: &Environment,
         rng: &mut impl rand::Rng,
-        log: &mut Vec<String>,
+        log: &mut Vec<LogEntry>,
         begin_time: &std::time::Instant,
     ) -> Arc<Value> {
         check_tle!(begin_time);
+        trace::enter(|| format!("\\{} -> {} ← {:?}", arg, value.span().text(), argv));
         let mut scoped_env = async_std::task::block_on(scoped_env.capture());
         async_std::task::block_on(scoped_env.insert(Arc::new(ast::Ident::Strict(arg)), argv));
         let val = value.evalute(&mut scoped_env, rng, log, begin_time);
         async_std::task::block_on(scoped_env.free());
+        trace::leave(&val);
         val
     }
 }
@@ -377,7 +460,7 @@ impl ast::Term {
         &self,
         env: &mut Environment,
         rng: &mut impl rand::Rng,
-        log: &mut Vec<String>,
+        log: &mut Vec<LogEntry>,
         begin_time: &std::time::Instant,
     ) -> Arc<Value> {
         match self {
@@ -408,14 +491,15 @@ impl ast::Literal {
         &self,
         env: &mut Environment,
         rng: &mut impl rand::Rng,
-        log: &mut Vec<String>,
+        log: &mut Vec<LogEntry>,
         begin_time: &std::time::Instant,
     ) -> Arc<Value> {
         match self {
             Self::Integer(val) => Arc::new(Value::Integer(*val)),
+            Self::String(val) => Arc::new(Value::String(Arc::clone(val))),
             Self::Ident(ident) => {
                 let val =
-                    async_std::task::block_on(env.get(&ident)).unwrap_or(Arc::new(Value::None));
+                    async_std::task::block_on(env.get(ident)).unwrap_or(Arc::new(Value::None));
                 if let Value::Lazy(expr) = val.as_ref() {
                     expr.evalute(env, rng, log, begin_time)
                 } else {
@@ -428,17 +512,11 @@ impl ast::Literal {
 
 impl Value {
     fn is_boolean(&self) -> bool {
-        match self {
-            Self::Boolean(_) => true,
-            _ => false,
-        }
+        matches!(self, Self::Boolean(_))
     }
 
     pub fn is_err(&self) -> bool {
-        match self {
-            Self::Err(_) => true,
-            _ => false,
-        }
+        matches!(self, Self::Err(_))
     }
 
     fn as_boolean(&self) -> Option<bool> {
@@ -448,23 +526,23 @@ impl Value {
         }
     }
 
-    fn as_integer(&self) -> Option<i64> {
+    pub fn as_integer(&self) -> Option<i64> {
         match self {
             Self::Integer(val) => Some(*val),
             _ => None,
         }
     }
 
-    fn as_array(&self) -> Option<&Vec<Arc<Value>>> {
+    pub fn as_array(&self) -> Option<&Vec<Arc<Value>>> {
         match self {
             Self::Array(val) => Some(val),
             _ => None,
         }
     }
 
-    fn as_record(&self) -> Option<&HashMap<Arc<String>, Arc<Value>>> {
+    pub fn as_record(&self) -> Option<&HashMap<Arc<String>, Arc<Value>>> {
         match self {
-            Self::Record(val) => Some(&val),
+            Self::Record(val) => Some(val),
             _ => None,
         }
     }
@@ -483,7 +561,7 @@ impl Value {
                 .iter()
                 .filter(|val| val.as_boolean().unwrap_or(false))
                 .count();
-            format!("{}成功", hit_num)
+            Message::Successes(hit_num).text()
         } else {
             format!("{:?}", vals)
         }
@@ -505,3 +583,32 @@ impl ValueElement {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::parser;
+    use crate::roll;
+    use crate::sheet::{Field, Sheet};
+
+    fn roll(content: &str) -> String {
+        let mut me = Sheet::new();
+        me.insert(String::from("hp"), Field::Integer(12));
+        me.insert(String::from("skill_spot"), Field::Integer(60));
+        me.insert(String::from("目星"), Field::Integer(70));
+        me.insert(String::from("name"), Field::String(String::from("アリス")));
+        let rolled =
+            roll::evaluate(&parser::Syntax::Native, None, &[], Some(&me), 0, content).unwrap();
+        format!("{}", rolled.value)
+    }
+
+    #[test]
+    fn read_sheet_fields() {
+        assert_eq!(roll("me.hp"), "12");
+        assert_eq!(roll("me.hp - 2 * 3"), "6");
+        assert_eq!(roll("me.name"), "アリス");
+        assert_eq!(roll(r#"me."目星""#), "70");
+        assert_eq!(roll("(me.skill_spot)d1"), "60");
+        assert_eq!(roll("me.mp"), "None");
+        assert_eq!(roll("hp := 1; me.hp"), "12");
+    }
+}
@@ -1,41 +1,77 @@
 use super::Environment;
 use super::Value;
+use super::{LogEntry, RollEvent};
 use crate::parser::ast;
 use async_std::sync::Arc;
+use rand::seq::SliceRandom;
 
 macro_rules! func {
-    ($help:ident ; $arg:ident -> $($args:ident ->)+ $implement:block) => {{
+    ($help:ident ; $rng:ident, $log:ident ; $arg:ident -> $($args:ident ->)+ $implement:block) => {{
         Arc::new(Value::BuiltInFunction {
             help: Arc::clone(&$help),
             implement: Box::new({
-                let help = Arc::clone(&$help);
-                move |$arg| func!($help; $($args ->)+ $implement)
+                let $help = Arc::clone(&$help);
+                move |$arg: Arc<Value>, _: &mut dyn rand::RngCore, _: &mut Vec<$crate::runtime::LogEntry>| {
+                    $crate::runtime::built_in_function::func!($help; $rng, $log; $($args ->)+ $implement)
+                }
             }),
         })
     }};
 
-    ($help:ident ; $arg:ident -> $implement:block) => {{
+    ($help:ident ; $rng:ident, $log:ident ; $arg:ident -> $implement:block) => {{
         Arc::new(Value::BuiltInFunction {
             help: Arc::clone(&$help),
-            implement: Box::new(move |$arg| $implement),
+            implement: Box::new(
+                move |$arg: Arc<Value>, $rng: &mut dyn rand::RngCore, $log: &mut Vec<$crate::runtime::LogEntry>| {
+                    $implement
+                },
+            ),
         })
     }};
 }
 
 macro_rules! def_func {
-    ($name:literal $help:ident in $env:ident; $($args:ident ->)+ $implement:block) => {{
-        let val = func!($help; $($args ->)+ $implement);
+    ($name:literal $help:ident in $env:ident with $rng:ident, $log:ident; $($args:ident ->)+ $implement:block) => {{
+        let val = $crate::runtime::built_in_function::func!($help; $rng, $log; $($args ->)+ $implement);
         $env.insert(
             Arc::new(ast::Ident::Strict(Arc::new(String::from($name)))),
             val,
         )
         .await;
     }};
+
+    ($name:literal $help:ident in $env:ident; $($args:ident ->)+ $implement:block) => {{
+        $crate::runtime::built_in_function::def_func!($name $help in $env with _rng, _log; $($args ->)+ $implement)
+    }};
 }
 
+pub(crate) use def_func;
+pub(crate) use func;
+
+// helpで一覧を表示するときの組み込み関数の分類
+pub const CATEGORIES: &[(&str, &[&str])] = &[
+    ("数値", &["max", "min"]),
+    (
+        "配列",
+        &["max_of", "min_of", "s", "choice", "shuffle", "sample"],
+    ),
+    ("表", &["table"]),
+    ("その他", &["help"]),
+];
+
 pub async fn set_default(env: &mut Environment) {
     //max
-    let help = Arc::new(String::from(""));
+    let help = Arc::new(String::from(
+        "\n\
+        max : Integer -> Integer -> Integer\n\
+        \n\
+        ［説明］\n\
+        2つの引数のうち大きい方を返します。\n\
+        \n\
+        ［使用例］\n\
+        max 1d6 1d6 //1d6を2回振って大きい方を使う\n\
+        max 0 (1d6-3) //0未満にならないようにする",
+    ));
     def_func!("max" help in env ; a -> b -> {
         if let (Some(a), Some(b)) = (a.as_integer(), b.as_integer()) {
             Arc::new(Value::Integer(a.max(b)))
@@ -45,24 +81,49 @@ pub async fn set_default(env: &mut Environment) {
     });
 
     //max_of
-    let help = Arc::new(String::from(""));
-    def_func!("max_of" help in env ; a -> b -> {
+    let help = Arc::new(String::from(
+        "\n\
+        max_of : Integer -> Array -> Array\n\
+        \n\
+        ［説明］\n\
+        第2引数の配列から、大きい方から第1引数の個数だけ要素を選びます。\n\
+        選んだ要素は元の順番のまま返します。\n\
+        \n\
+        ［使用例］\n\
+        max_of 3 4B6 //4B6の大きい方から3個を選ぶ\n\
+        max_of 1 2B20 //2B20の大きい方を選ぶ（有利）",
+    ));
+    def_func!("max_of" help in env with _rng, log; a -> b -> {
         if let (Some(a), Some(b)) = (a.as_integer(), b.as_array()) {
-            let bi: Vec<_> = b.iter().filter_map(|x| x.as_integer()).collect();
-            if b.len() == bi.len() && a > 0{
-                let mut bi: Vec<_> = bi.into_iter().enumerate().collect();
+            let faces: Vec<_> = b.iter().filter_map(|x| x.as_integer()).collect();
+            if b.len() == faces.len() && a > 0{
+                let mut bi: Vec<_> = faces.iter().copied().enumerate().collect();
                 bi.sort_by(|x, y| if x.1 != y.1 { y.1.cmp(&x.1) } else { x.0.cmp(&y.0) });
-                let mut bi: Vec<_> = bi.drain(0..(a as usize)).collect();
-                bi.sort_by(|x, y| x.0.cmp(&y.0));
-                let bi = bi.into_iter().map(|(_, x)| Arc::new(Value::Integer(x))).collect();
-                return Arc::new(Value::Array(bi));
+                let mut dropped: Vec<_> = bi.split_off((a as usize).min(bi.len()));
+                bi.sort_by_key(|x| x.0);
+                dropped.sort_by_key(|x| x.0);
+                let kept: Vec<_> = bi.into_iter().map(|(_, x)| x).collect();
+                let dropped = dropped.into_iter().map(|(_, x)| x).collect();
+                let res = kept.iter().map(|x| Arc::new(Value::Integer(*x))).collect();
+                log.push(LogEntry::Roll(RollEvent::keep("max_of", faces, kept, dropped)));
+                return Arc::new(Value::Array(res));
             }
         }
         Arc::new(Value::None)
     });
 
     // min
-    let help = Arc::new(String::from(""));
+    let help = Arc::new(String::from(
+        "\n\
+        min : Integer -> Integer -> Integer\n\
+        \n\
+        ［説明］\n\
+        2つの引数のうち小さい方を返します。\n\
+        \n\
+        ［使用例］\n\
+        min 1d6 1d6 //1d6を2回振って小さい方を使う\n\
+        min 10 (2d6+5) //10を超えないようにする",
+    ));
     def_func!("min" help in env ; a -> b -> {
         if let (Some(a), Some(b)) = (a.as_integer(), b.as_integer()) {
             Arc::new(Value::Integer(a.min(b)))
@@ -72,17 +133,32 @@ pub async fn set_default(env: &mut Environment) {
     });
 
     // min_of
-    let help = Arc::new(String::from(""));
-    def_func!("min_of" help in env ; a -> b -> {
+    let help = Arc::new(String::from(
+        "\n\
+        min_of : Integer -> Array -> Array\n\
+        \n\
+        ［説明］\n\
+        第2引数の配列から、小さい方から第1引数の個数だけ要素を選びます。\n\
+        選んだ要素は元の順番のまま返します。\n\
+        \n\
+        ［使用例］\n\
+        min_of 2 3B6 //3B6の小さい方から2個を選ぶ\n\
+        min_of 1 2B20 //2B20の小さい方を選ぶ（不利）",
+    ));
+    def_func!("min_of" help in env with _rng, log; a -> b -> {
         if let (Some(a), Some(b)) = (a.as_integer(), b.as_array()) {
-            let bi: Vec<_> = b.iter().filter_map(|x| x.as_integer()).collect();
-            if b.len() == bi.len() && a > 0{
-                let mut bi: Vec<_> = bi.into_iter().enumerate().collect();
+            let faces: Vec<_> = b.iter().filter_map(|x| x.as_integer()).collect();
+            if b.len() == faces.len() && a > 0{
+                let mut bi: Vec<_> = faces.iter().copied().enumerate().collect();
                 bi.sort_by(|x, y| if x.1 != y.1 { x.1.cmp(&y.1) } else { x.0.cmp(&y.0) });
-                let mut bi: Vec<_> = bi.drain(0..(a as usize)).collect();
-                bi.sort_by(|x, y| x.0.cmp(&y.0));
-                let bi = bi.into_iter().map(|(_, x)| Arc::new(Value::Integer(x))).collect();
-                return Arc::new(Value::Array(bi));
+                let mut dropped: Vec<_> = bi.split_off((a as usize).min(bi.len()));
+                bi.sort_by_key(|x| x.0);
+                dropped.sort_by_key(|x| x.0);
+                let kept: Vec<_> = bi.into_iter().map(|(_, x)| x).collect();
+                let dropped = dropped.into_iter().map(|(_, x)| x).collect();
+                let res = kept.iter().map(|x| Arc::new(Value::Integer(*x))).collect();
+                log.push(LogEntry::Roll(RollEvent::keep("min_of", faces, kept, dropped)));
+                return Arc::new(Value::Array(res));
             }
         }
         Arc::new(Value::None)
@@ -104,7 +180,7 @@ pub async fn set_default(env: &mut Environment) {
         if let Some(a) = a.as_array() {
             let mut ai: Vec<_> = a.iter().filter_map(|x| x.as_integer()).collect();
             if a.len() == ai.len(){
-                ai.sort_by(|x, y| x.cmp(&y));
+                ai.sort();
                 let ai = ai.into_iter().map(|x| Arc::new(Value::Integer(x))).collect();
                 return Arc::new(Value::Array(ai));
             }
@@ -112,5 +188,168 @@ pub async fn set_default(env: &mut Environment) {
         Arc::new(Value::None)
     });
 
+    // choice
+    let help = Arc::new(String::from(
+        "\n\
+        choice : Array -> Any\n\
+        \n\
+        ［説明］\n\
+        引数として渡された配列から要素を1つ無作為に選びます。\n\
+        \n\
+        ［使用例］\n\
+        choice [\"アリス\", \"ボブ\", \"キャロル\"] //NPCの名前を1つ選ぶ",
+    ));
+    def_func!("choice" help in env with rng, log; a -> {
+        if let Some(a) = a.as_array() {
+            if let Some(x) = a.choose(rng) {
+                log.push(LogEntry::Text(format!("{:?} → {:?}", a, x)));
+                return Arc::clone(x);
+            }
+        }
+        Arc::new(Value::None)
+    });
+
+    // shuffle
+    let help = Arc::new(String::from(
+        "\n\
+        shuffle : Array -> Array\n\
+        \n\
+        ［説明］\n\
+        引数として渡された配列を無作為に並び替えます。\n\
+        \n\
+        ［使用例］\n\
+        shuffle [1, 2, 3, 4, 5] //行動順を決める",
+    ));
+    def_func!("shuffle" help in env with rng, log; a -> {
+        if let Some(a) = a.as_array() {
+            let mut a = a.clone();
+            a.shuffle(rng);
+            log.push(LogEntry::Text(format!("{:?}", &a)));
+            return Arc::new(Value::Array(a));
+        }
+        Arc::new(Value::None)
+    });
+
+    // sample
+    let help = Arc::new(String::from(
+        "\n\
+        sample : Integer -> Array -> Array\n\
+        \n\
+        ［説明］\n\
+        第2引数の配列から、第1引数の個数だけ要素を重複なく無作為に選びます。\n\
+        \n\
+        ［使用例］\n\
+        sample 2 [\"アリス\", \"ボブ\", \"キャロル\"] //3人から2人を選ぶ",
+    ));
+    def_func!("sample" help in env with rng, log; n -> a -> {
+        if let (Some(n), Some(a)) = (n.as_integer(), a.as_array()) {
+            if n >= 0 && n as usize <= a.len() {
+                let res: Vec<_> = a.choose_multiple(rng, n as usize).map(Arc::clone).collect();
+                log.push(LogEntry::Text(format!("{:?}", &res)));
+                return Arc::new(Value::Array(res));
+            }
+        }
+        Arc::new(Value::None)
+    });
+
+    // table
+    let help = Arc::new(String::from(
+        "\n\
+        table : Integer -> Array -> Any\n\
+        \n\
+        ［説明］\n\
+        第1引数の値を第2引数の表から引きます。\n\
+        表の各行は [下限, 上限, 結果] もしくは [値, 結果] の形式で指定します。\n\
+        \n\
+        ［使用例］\n\
+        table 2d6 [[2, 5, \"ゴブリン\"], [6, 9, \"オーク\"], [10, 12, \"ドラゴン\"]] //2d6で遭遇表を振る",
+    ));
+    def_func!("table" help in env with _rng, log; x -> a -> {
+        if let (Some(x), Some(a)) = (x.as_integer(), a.as_array()) {
+            for row in a {
+                let row = row.as_array().and_then(|row| match row.as_slice() {
+                    [lo, hi, res] => lo.as_integer().zip(hi.as_integer()).map(|r| (r, res)),
+                    [v, res] => v.as_integer().map(|v| ((v, v), res)),
+                    _ => None,
+                });
+                if let Some(((lo, hi), res)) = row {
+                    if lo <= x && x <= hi {
+                        log.push(LogEntry::Text(format!("{} → {:?}", x, res)));
+                        return Arc::clone(res);
+                    }
+                }
+            }
+        }
+        Arc::new(Value::None)
+    });
+
     //at
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::parser;
+    use crate::roll;
+
+    fn roll(content: &str) -> String {
+        let rolled = roll::evaluate(&parser::Syntax::Native, None, &[], None, 0, content).unwrap();
+        format!("{:?}", rolled.value)
+    }
+
+    #[test]
+    fn choose_from_array() {
+        assert_eq!(roll("choice []"), "None");
+        assert_eq!(roll("choice [7]"), "7");
+        for seed in 0..20 {
+            let rolled = roll::evaluate(
+                &parser::Syntax::Native,
+                None,
+                &[],
+                None,
+                seed,
+                "choice [1, 2, 3]",
+            )
+            .unwrap();
+            assert!(["1", "2", "3"].contains(&format!("{:?}", rolled.value).as_str()));
+        }
+    }
+
+    #[test]
+    fn shuffle_array() {
+        assert_eq!(roll("shuffle []"), roll("[]"));
+        let mut shuffled: Vec<_> = roll("shuffle [1, 2, 3, 4, 5]")
+            .trim_matches(|c| c == '[' || c == ']')
+            .split(", ")
+            .map(String::from)
+            .collect();
+        shuffled.sort();
+        assert_eq!(shuffled, ["1", "2", "3", "4", "5"]);
+    }
+
+    #[test]
+    fn sample_from_array() {
+        assert_eq!(roll("sample 0 []"), roll("[]"));
+        assert_eq!(roll("sample 3 [1, 2, 3]").len(), roll("[1, 2, 3]").len());
+        assert_eq!(roll("sample 4 [1, 2, 3]"), "None");
+        assert_eq!(roll("sample (0 - 1) [1, 2, 3]"), "None");
+        let sampled = roll("sample 2 [1, 2, 3]");
+        let sampled: Vec<_> = sampled
+            .trim_matches(|c| c == '[' || c == ']')
+            .split(", ")
+            .collect();
+        assert_eq!(sampled.len(), 2);
+        assert_ne!(sampled[0], sampled[1]);
+    }
+
+    #[test]
+    fn look_up_table() {
+        let table = "[[2, 5, \"ゴブリン\"], [6, 9, \"オーク\"], [10, \"ドラゴン\"]]";
+        assert_eq!(roll(&format!("table 2 {}", table)), "ゴブリン");
+        assert_eq!(roll(&format!("table 5 {}", table)), "ゴブリン");
+        assert_eq!(roll(&format!("table 6 {}", table)), "オーク");
+        assert_eq!(roll(&format!("table 10 {}", table)), "ドラゴン");
+        assert_eq!(roll(&format!("table 11 {}", table)), "None");
+        assert_eq!(roll("table 1 []"), "None");
+        assert_eq!(roll("table 1 [[1], [1, 2, 3, 4], 1]"), "None");
+    }
+}
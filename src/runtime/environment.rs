@@ -1,3 +1,5 @@
+use super::LogEntry;
+use crate::locale::Message;
 use crate::parser::ast;
 use async_std::sync::{Arc, Mutex};
 use std::collections::HashMap;
@@ -15,6 +17,9 @@ struct Bind {
     rc: usize,
 }
 
+pub type BuiltInImplement =
+    dyn Fn(Arc<Value>, &mut dyn rand::RngCore, &mut Vec<LogEntry>) -> Arc<Value> + Send + Sync;
+
 pub enum Value {
     None,
     Integer(i64),
@@ -29,14 +34,18 @@ pub enum Value {
     },
     BuiltInFunction {
         help: Arc<String>,
-        implement: Box<dyn Fn(Arc<Value>) -> Arc<Value>>,
+        implement: Box<BuiltInImplement>,
     },
     Lazy(Arc<ast::Expr0>),
     Err(String),
 }
 
 impl Environment {
-    fn build_in_help(val: Arc<Value>) -> Arc<Value> {
+    fn build_in_help(
+        val: Arc<Value>,
+        _: &mut dyn rand::RngCore,
+        _: &mut Vec<LogEntry>,
+    ) -> Arc<Value> {
         if let Value::BuiltInFunction { help, .. } = val.as_ref() {
             Arc::new(Value::String(Arc::clone(help)))
         } else {
@@ -52,7 +61,16 @@ impl Environment {
         };
         async_std::task::block_on(this.append_build_in_function(
             Arc::new(String::from("help")),
-            Arc::new(String::from(r#""#)),
+            Arc::new(String::from(
+                "\n\
+                help : Fn -> String\n\
+                \n\
+                ［説明］\n\
+                組み込み関数の説明を返します。\n\
+                \n\
+                ［使用例］\n\
+                help max_of //max_ofの説明を表示",
+            )),
             Self::build_in_help,
         ));
         this
@@ -79,8 +97,8 @@ impl Environment {
     pub async fn insert(&mut self, ident: Arc<ast::Ident>, val: Arc<Value>) {
         let bind = Bind {
             parent: self.head,
-            ident: ident,
-            val: val,
+            ident,
+            val,
             rc: 0,
         };
 
@@ -110,10 +128,9 @@ impl Environment {
                 if *(bind.ident) == *ident {
                     return Some(Arc::clone(&bind.val));
                 } else if *bind.ident.name() == *ident.name() {
-                    return Some(Arc::new(Value::Err(format!(
-                        "{0}と{0}?を併用することはできません",
-                        ident.name().as_str()
-                    ))));
+                    return Some(Arc::new(Value::Err(
+                        Message::MixedIdent(ident.name().as_str()).text(),
+                    )));
                 } else {
                     idx = bind.parent;
                 }
@@ -124,6 +141,25 @@ impl Environment {
         None
     }
 
+    // 定義されている組み込み関数の名前と説明を、定義した順に返す
+    pub async fn built_in_functions(&self) -> Vec<(Arc<String>, Arc<String>)> {
+        let binds = self.binds.lock().await;
+        let mut res: Vec<(Arc<String>, Arc<String>)> = vec![];
+        let mut idx = self.head;
+        while let Some(bind) = binds.get(idx.wrapping_sub(1)).and_then(Option::as_ref) {
+            if let Value::BuiltInFunction { help, .. } = bind.val.as_ref() {
+                let name = bind.ident.name();
+                // 後から定義したもので隠されているものは除く
+                if res.iter().all(|(defined, _)| *defined != name) {
+                    res.push((name, Arc::clone(help)));
+                }
+            }
+            idx = bind.parent;
+        }
+        res.reverse();
+        res
+    }
+
     pub async fn free(&self) {
         let mut idx = self.head;
         let mut binds = self.binds.lock().await;
@@ -148,7 +184,10 @@ impl Environment {
         &mut self,
         name: Arc<String>,
         help: Arc<String>,
-        implement: impl Fn(Arc<Value>) -> Arc<Value> + 'static,
+        implement: impl Fn(Arc<Value>, &mut dyn rand::RngCore, &mut Vec<LogEntry>) -> Arc<Value>
+            + Send
+            + Sync
+            + 'static,
     ) {
         let val = Value::BuiltInFunction {
             help,
@@ -0,0 +1,218 @@
+use crate::parser::ast;
+use serde::{Deserialize, Serialize};
+
+// 経過の元になった部分式。startとendは、textが書かれた式の中での位置（バイト単位）
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceSpan {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+impl From<&ast::Span> for SourceSpan {
+    fn from(span: &ast::Span) -> Self {
+        Self {
+            start: span.start,
+            end: span.end,
+            text: String::from(span.text()),
+        }
+    }
+}
+
+// ゲームシステムの判定の成功度。表示名は表示するときの言語で決める
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Level {
+    Critical,
+    Extreme,
+    Hard,
+    Regular,
+    Failure,
+    Fumble,
+    // 第6版の技能ロール
+    ClassicCritical,
+    ClassicSuccess,
+    ClassicFumble,
+    // ソード・ワールドの1ゾロ
+    AutomaticFailure,
+}
+
+impl Level {
+    pub fn is_success(self) -> bool {
+        !matches!(
+            self,
+            Self::Failure | Self::Fumble | Self::ClassicFumble | Self::AutomaticFailure
+        )
+    }
+}
+
+// ダイスを振った、または振ったダイスから選んだ1回分の記録
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RollEvent {
+    // 振った演算子か関数の名前（d、b、max_of など）
+    pub operator: String,
+    pub count: usize,
+    // ダイスの面数。振り終わった出目から選んだ場合はNone
+    pub sides: Option<i64>,
+    pub faces: Vec<i64>,
+    // 結果に使った出目と除いた出目
+    pub kept: Vec<i64>,
+    pub dropped: Vec<i64>,
+    // 出目の合計が結果になる場合はその合計
+    pub total: Option<i64>,
+    // ダイスを振った部分式
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub span: Option<SourceSpan>,
+    // 判定の場合は成功度
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<Level>,
+}
+
+impl RollEvent {
+    // 振った出目を全て使う
+    pub fn new(operator: &str, sides: i64, faces: Vec<i64>, total: Option<i64>) -> Self {
+        Self {
+            operator: String::from(operator),
+            count: faces.len(),
+            sides: Some(sides),
+            kept: faces.clone(),
+            faces,
+            dropped: vec![],
+            total,
+            span: None,
+            level: None,
+        }
+    }
+
+    // 振り終わった出目から一部を選ぶ
+    pub fn keep(operator: &str, faces: Vec<i64>, kept: Vec<i64>, dropped: Vec<i64>) -> Self {
+        Self {
+            operator: String::from(operator),
+            count: faces.len(),
+            sides: None,
+            faces,
+            kept,
+            dropped,
+            total: None,
+            span: None,
+            level: None,
+        }
+    }
+}
+
+// 評価の経過の1行分
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LogEntry {
+    Roll(RollEvent),
+    // 最初にエラーになった部分式
+    Error { span: SourceSpan, message: String },
+    // ダイス以外の経過。比較した配列や、ゲームシステムの判定など
+    Text(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+    use crate::render;
+    use crate::roll;
+
+    fn roll(content: &str) -> Vec<LogEntry> {
+        roll::evaluate(&parser::Syntax::Native, None, &[], None, 0, content)
+            .unwrap()
+            .log
+    }
+
+    #[test]
+    fn record_each_term() {
+        let log = roll("2d6 + 3d4");
+        let events: Vec<_> = log
+            .iter()
+            .filter_map(|entry| match entry {
+                LogEntry::Roll(event) => Some(event),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!((events[0].count, events[0].sides), (2, Some(6)));
+        assert_eq!((events[1].count, events[1].sides), (3, Some(4)));
+        for event in events {
+            assert_eq!(event.operator, "d");
+            assert_eq!(event.total, Some(event.faces.iter().sum()));
+            assert_eq!(
+                render::log_line(&LogEntry::Roll(event.clone())),
+                format!("{} {:?}", event.faces.iter().sum::<i64>(), event.faces)
+            );
+        }
+    }
+
+    #[test]
+    fn record_kept_and_dropped() {
+        let log = roll("max_of 3 4b6");
+        let (rolled, kept) = match log.as_slice() {
+            [LogEntry::Roll(rolled), LogEntry::Roll(kept)] => (rolled, kept),
+            _ => panic!("{:?}", log),
+        };
+        assert_eq!(rolled.operator, "b");
+        assert_eq!(kept.operator, "max_of");
+        assert_eq!(kept.faces, rolled.faces);
+        assert_eq!((kept.kept.len(), kept.dropped.len()), (3, 1));
+        assert!(kept.kept.iter().all(|face| *face >= kept.dropped[0]));
+    }
+
+    #[test]
+    fn read_text_entries() {
+        let log: Vec<LogEntry> = serde_json::from_str(r#"["7 [3, 4]"]"#).unwrap();
+        assert_eq!(log, vec![LogEntry::Text(String::from("7 [3, 4]"))]);
+        let event = LogEntry::Roll(RollEvent::new("d", 6, vec![3, 4], Some(7)));
+        let json = serde_json::to_string(&vec![event.clone()]).unwrap();
+        let log: Vec<LogEntry> = serde_json::from_str(&json).unwrap();
+        assert_eq!(log, vec![event]);
+    }
+
+    #[test]
+    fn attribute_to_source() {
+        let spans: Vec<_> = roll("2d6; max_of 1 2b20")
+            .into_iter()
+            .filter_map(|entry| match entry {
+                LogEntry::Roll(RollEvent { operator, span, .. }) => {
+                    span.map(|span| (operator, span.text))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            spans,
+            [
+                (String::from("d"), String::from("2d6")),
+                (String::from("b"), String::from("2b20")),
+                (String::from("max_of"), String::from("max_of 1 2b20")),
+            ]
+        );
+    }
+
+    #[test]
+    fn point_to_error() {
+        let rolled = roll::evaluate(
+            &parser::Syntax::Native,
+            None,
+            &[],
+            None,
+            0,
+            "x := 1; 1d6 + x?",
+        )
+        .unwrap();
+        assert!(rolled.log.contains(&LogEntry::Error {
+            span: SourceSpan {
+                start: 14,
+                end: 16,
+                text: String::from("x?"),
+            },
+            message: String::from("xとx?を併用することはできません"),
+        }));
+        let rolled =
+            roll::evaluate(&parser::Syntax::Native, None, &[], None, 0, "x := 1; x?").unwrap();
+        assert!(rolled.text().contains("（x?で発生）"));
+    }
+}
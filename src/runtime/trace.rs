@@ -0,0 +1,253 @@
+use super::Value;
+use std::cell::RefCell;
+
+// 記録する評価の数の上限。これを超えた評価は数だけ数える
+const NODE_LIMIT: usize = 10_000;
+// 式と値を表示する文字数の上限
+const LABEL_LIMIT: usize = 60;
+const VALUE_LIMIT: usize = 60;
+
+// 1回分の評価。子は評価の途中で行われた評価
+pub struct Node {
+    pub label: String,
+    pub value: String,
+    pub children: Vec<Node>,
+}
+
+// 記録した評価の木
+#[derive(Default)]
+pub struct Trace {
+    pub nodes: Vec<Node>,
+    // 記録の上限を超えたために省略した評価の数
+    pub omitted: usize,
+}
+
+struct Tracer {
+    // 評価中の式。先頭は全体をまとめる根
+    stack: Vec<Node>,
+    nodes: usize,
+    // 上限を超えたために記録していない、評価中の式の数
+    skipped: usize,
+    omitted: usize,
+}
+
+thread_local! {
+    // トレースを取っている間だけSomeになる
+    static TRACER: RefCell<Option<Tracer>> = const { RefCell::new(None) };
+}
+
+// 評価を始める。labelはトレースを取っているときだけ作る
+pub fn enter(label: impl FnOnce() -> String) {
+    TRACER.with(|tracer| {
+        if let Some(tracer) = tracer.borrow_mut().as_mut() {
+            if tracer.skipped > 0 || tracer.nodes >= NODE_LIMIT {
+                tracer.skipped += 1;
+                tracer.omitted += 1;
+            } else {
+                tracer.nodes += 1;
+                tracer.stack.push(Node {
+                    label: truncate(&label(), LABEL_LIMIT),
+                    value: String::new(),
+                    children: vec![],
+                });
+            }
+        }
+    });
+}
+
+// enterで始めた評価を、その値とともに終える
+pub fn leave(value: &Value) {
+    TRACER.with(|tracer| {
+        if let Some(tracer) = tracer.borrow_mut().as_mut() {
+            if tracer.skipped > 0 {
+                tracer.skipped -= 1;
+            } else if tracer.stack.len() > 1 {
+                if let Some(mut node) = tracer.stack.pop() {
+                    node.value = truncate(&format!("{:?}", value), VALUE_LIMIT);
+                    if let Some(parent) = tracer.stack.last_mut() {
+                        parent.children.push(node);
+                    }
+                }
+            }
+        }
+    });
+}
+
+// fの実行中の評価を記録する
+pub fn tracing<T>(f: impl FnOnce() -> T) -> (T, Trace) {
+    TRACER.with(|tracer| {
+        *tracer.borrow_mut() = Some(Tracer {
+            stack: vec![Node {
+                label: String::new(),
+                value: String::new(),
+                children: vec![],
+            }],
+            nodes: 0,
+            skipped: 0,
+            omitted: 0,
+        })
+    });
+    let res = f();
+    let tracer = TRACER.with(|tracer| tracer.borrow_mut().take());
+    match tracer {
+        Some(mut tracer) => {
+            // 途中で打ち切られた評価は、値のないまま親に付ける
+            while tracer.stack.len() > 1 {
+                if let Some(node) = tracer.stack.pop() {
+                    if let Some(parent) = tracer.stack.last_mut() {
+                        parent.children.push(node);
+                    }
+                }
+            }
+            let nodes = tracer
+                .stack
+                .pop()
+                .map_or_else(Vec::new, |root| root.children);
+            let trace = Trace {
+                nodes,
+                omitted: tracer.omitted,
+            };
+            (res, trace)
+        }
+        None => (res, Trace::default()),
+    }
+}
+
+impl Trace {
+    // 評価の木をインデントして表示する。depthより深い評価とlines行を超える分は省略する
+    pub fn render(&self, depth: usize, lines: usize) -> String {
+        let mut res = vec![];
+        for node in &self.nodes {
+            render_node(node, "", "", depth, &mut res);
+        }
+        if res.len() > lines {
+            let rest = res.len() - lines;
+            res.truncate(lines);
+            res.push(format!("…（残り{}行を省略しました）", rest));
+        }
+        if self.omitted > 0 {
+            res.push(format!(
+                "…（記録の上限を超えた{}件の評価を省略しました）",
+                self.omitted
+            ));
+        }
+        res.join("\n")
+    }
+}
+
+// prefixはこの行の先頭に、indentは子の行の先頭に付ける
+fn render_node(node: &Node, prefix: &str, indent: &str, depth: usize, res: &mut Vec<String>) {
+    if node.value.is_empty() {
+        res.push(format!("{}{}", prefix, node.label));
+    } else {
+        res.push(format!("{}{} → {}", prefix, node.label, node.value));
+    }
+
+    if node.children.is_empty() {
+        return;
+    }
+    if depth <= 1 {
+        res.push(format!(
+            "{}└ …（{}件の評価を省略しました）",
+            indent,
+            count(&node.children)
+        ));
+        return;
+    }
+    for (idx, child) in node.children.iter().enumerate() {
+        let (branch, next) = if idx + 1 == node.children.len() {
+            ("└ ", "  ")
+        } else {
+            ("├ ", "│ ")
+        };
+        render_node(
+            child,
+            &format!("{}{}", indent, branch),
+            &format!("{}{}", indent, next),
+            depth - 1,
+            res,
+        );
+    }
+}
+
+fn count(nodes: &[Node]) -> usize {
+    nodes.iter().map(|node| 1 + count(&node.children)).sum()
+}
+
+fn truncate(text: &str, limit: usize) -> String {
+    if text.chars().count() <= limit {
+        String::from(text)
+    } else {
+        let mut res: String = text.chars().take(limit - 1).collect();
+        res.push('…');
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+    use crate::roll;
+
+    fn trace(content: &str) -> Trace {
+        roll::trace(&parser::Syntax::Native, None, &[], None, 0, content)
+            .unwrap()
+            .trace
+            .unwrap()
+    }
+
+    #[test]
+    fn record_lookups_and_calls() {
+        let rendered = trace("x := 1d6; f := \\y -> y * 2; f x").render(8, 200);
+        let x = rendered
+            .lines()
+            .nth(1)
+            .unwrap()
+            .trim_start_matches("└ 1d6 → ");
+        let expected = format!(
+            "x := 1d6 → None\n\
+            └ 1d6 → {0}\n\
+            f := \\y -> y * 2 → None\n\
+            f x → {1}\n\
+            ├ f → fn y\n\
+            ├ x → {0}\n\
+            └ \\y -> y * 2 ← {0} → {1}\n\
+            \x20 └ y * 2 → {1}\n\
+            \x20   └ y → {0}",
+            x,
+            x.parse::<i64>().unwrap() * 2
+        );
+        assert_eq!(rendered, expected);
+    }
+
+    #[test]
+    fn record_iterations() {
+        let rendered = trace("1d2 @>= 2").render(8, 200);
+        let iterations = rendered
+            .lines()
+            .filter(|line| line.contains("回目"))
+            .count();
+        let rolls = trace("1d2 @>= 2").nodes[0].value.matches(',').count();
+        assert_eq!(iterations, rolls);
+    }
+
+    #[test]
+    fn limit_depth_and_lines() {
+        let trace = trace("((((1d6 + 1) + 1) + 1) + 1)");
+        assert_eq!(
+            trace.render(2, 200).lines().last(),
+            Some("  └ …（3件の評価を省略しました）")
+        );
+        assert_eq!(
+            trace.render(8, 2).lines().last(),
+            Some("…（残り3行を省略しました）")
+        );
+        assert!(
+            roll::evaluate(&parser::Syntax::Native, None, &[], None, 0, "1d6")
+                .unwrap()
+                .trace
+                .is_none()
+        );
+    }
+}
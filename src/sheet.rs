@@ -0,0 +1,52 @@
+use crate::runtime::Value;
+use async_std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Field {
+    Integer(i64),
+    String(String),
+}
+
+// キャラクターシート。ダイスを振るときにはmeという名前のレコードとして使える
+pub type Sheet = BTreeMap<String, Field>;
+
+pub fn to_value(sheet: &Sheet) -> Value {
+    Value::Record(
+        sheet
+            .iter()
+            .map(|(key, field)| {
+                let val = match field {
+                    Field::Integer(n) => Value::Integer(*n),
+                    Field::String(s) => Value::String(Arc::new(s.clone())),
+                };
+                (Arc::new(key.clone()), Arc::new(val))
+            })
+            .collect(),
+    )
+}
+
+// 整数と文字列だけを持つレコードをキャラクターシートにする
+pub fn from_value(val: &Value) -> Option<Sheet> {
+    if let Value::Record(record) = val {
+        record
+            .iter()
+            .map(|(key, val)| match val.as_ref() {
+                Value::Integer(n) => Some((key.to_string(), Field::Integer(*n))),
+                Value::String(s) => Some((key.to_string(), Field::String(s.to_string()))),
+                _ => None,
+            })
+            .collect()
+    } else {
+        None
+    }
+}
+
+// meの後ろに書けるフィールド名かどうか
+pub fn is_field_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
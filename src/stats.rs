@@ -0,0 +1,425 @@
+use crate::chart::{Bar, Chart};
+use crate::history::Entry;
+use crate::locale::Message;
+use crate::render::{self, Outcome};
+use crate::runtime::{LogEntry, RollEvent};
+use serde_json::json;
+use std::collections::BTreeMap;
+
+// 出目の分布を集計するダイスの面数の範囲
+const SIDES_LIMIT: i64 = 1000;
+// 出目の分布を棒グラフで表示するダイスの面数の上限
+const LISTED_SIDES_LIMIT: i64 = 20;
+// 偏りがあると判定する有意水準
+const SIGNIFICANCE: f64 = 0.01;
+
+const COLOR: u32 = 0x5865f2;
+
+#[derive(Default)]
+struct Tally {
+    rolls: usize,
+    critical: usize,
+    success: usize,
+    failure: usize,
+    fumble: usize,
+}
+
+// ダイスの記録から集計した統計
+pub struct Report {
+    title: String,
+    rolls: usize,
+    // 面数 → 出目ごとの回数
+    faces: BTreeMap<i64, Vec<u64>>,
+    // ユーザー名 → 判定の結果の回数
+    players: BTreeMap<String, Tally>,
+}
+
+impl Report {
+    pub fn new(title: String, entries: &[Entry]) -> Self {
+        let mut faces: BTreeMap<i64, Vec<u64>> = BTreeMap::new();
+        let mut players: BTreeMap<String, Tally> = BTreeMap::new();
+
+        // 公開されていないシークレットダイスは集計しない
+        let entries: Vec<_> = entries.iter().filter(|entry| !entry.secret).collect();
+        for entry in &entries {
+            // 振ったダイスだけを数え、振った出目から選んだ記録は数えない
+            for a_line in &entry.log {
+                let (sides, rolled) = match a_line {
+                    LogEntry::Roll(RollEvent {
+                        sides: Some(sides),
+                        faces,
+                        ..
+                    }) => (*sides, faces),
+                    _ => continue,
+                };
+                if !(2..=SIDES_LIMIT).contains(&sides) {
+                    continue;
+                }
+                let counts = faces
+                    .entry(sides)
+                    .or_insert_with(|| vec![0; sides as usize]);
+                for face in rolled {
+                    if (1..=sides).contains(face) {
+                        counts[*face as usize - 1] += 1;
+                    }
+                }
+            }
+
+            let tally = players.entry(entry.user_name.clone()).or_default();
+            tally.rolls += 1;
+            match entry.outcome {
+                Some(Outcome::Critical) => tally.critical += 1,
+                Some(Outcome::Success) => tally.success += 1,
+                Some(Outcome::Failure) => tally.failure += 1,
+                Some(Outcome::Fumble) => tally.fumble += 1,
+                None => (),
+            }
+        }
+
+        Self {
+            title,
+            rolls: entries.len(),
+            faces,
+            players,
+        }
+    }
+
+    // 面数ごとの見出しと内容
+    fn dice_fields(&self) -> Vec<(String, String)> {
+        self.faces
+            .iter()
+            .map(|(sides, counts)| {
+                let total: u64 = counts.iter().sum();
+                let sum: u64 = counts
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, count)| (idx as u64 + 1) * count)
+                    .sum();
+                let mean = sum as f64 / total.max(1) as f64;
+                let expected = (*sides as f64 + 1.0) / 2.0;
+
+                let mut res = Message::Mean { mean, expected }.text();
+                if *sides <= LISTED_SIDES_LIMIT {
+                    res += format!("\n```\n{}\n```", chart(counts).text()).as_str();
+                }
+
+                let (chi_square, df, p) = chi_square_test(counts);
+                let verdict = if p < SIGNIFICANCE {
+                    Message::Biased
+                } else {
+                    Message::Unbiased
+                };
+                res += format!(
+                    "\n{} → {}",
+                    Message::ChiSquare { chi_square, df, p }.text(),
+                    verdict.text()
+                )
+                .as_str();
+                // 期待度数が5未満のカイ二乗検定は近似が悪い
+                if total < 5 * *sides as u64 {
+                    res += &Message::FewRolls.text();
+                }
+
+                let name = Message::DiceCount {
+                    sides: *sides,
+                    count: total,
+                };
+                (name.text(), res)
+            })
+            .collect()
+    }
+
+    // 出目の分布を棒グラフにする面数と、そのグラフ
+    pub fn charts(&self) -> Vec<(i64, Chart)> {
+        self.faces
+            .iter()
+            .filter(|(sides, _)| **sides <= LISTED_SIDES_LIMIT)
+            .map(|(sides, counts)| (*sides, chart(counts)))
+            .collect()
+    }
+
+    fn player_lines(&self) -> Vec<String> {
+        self.players
+            .iter()
+            .map(|(name, tally)| {
+                Message::PlayerTally {
+                    name,
+                    rolls: tally.rolls,
+                    critical: tally.critical,
+                    fumble: tally.fumble,
+                    success: tally.success,
+                    failure: tally.failure,
+                }
+                .text()
+            })
+            .collect()
+    }
+
+    pub fn text(&self) -> String {
+        let mut res = format!("{}\n{}", self.title, Message::Rolls(self.rolls).text());
+        for (name, value) in self.dice_fields() {
+            res += format!("\n{}\n{}", name, value).as_str();
+        }
+        res += format!(
+            "\n{}",
+            Message::Heading(&Message::CriticalsField.text()).text()
+        )
+        .as_str();
+        for line in self.player_lines() {
+            res += format!("\n{}", line).as_str();
+        }
+        res
+    }
+
+    pub fn embed(&self) -> serde_json::Value {
+        // 参加者が多い場合は、クリティカル／ファンブルを複数のフィールドに分ける
+        let players: Vec<_> = render::split(&self.player_lines().join("\n"), render::FIELD_LIMIT)
+            .into_iter()
+            .enumerate()
+            .map(|(idx, value)| {
+                let name = if idx == 0 {
+                    Message::CriticalsField.text()
+                } else {
+                    Message::CriticalsFieldContinued.text()
+                };
+                json!({ "name": name, "value": value })
+            })
+            .take(render::FIELDS_LIMIT)
+            .collect();
+        let mut fields: Vec<_> = self
+            .dice_fields()
+            .into_iter()
+            .take(render::FIELDS_LIMIT - players.len())
+            .map(|(name, value)| {
+                json!({ "name": name, "value": render::truncate(&value, render::FIELD_LIMIT) })
+            })
+            .collect();
+        fields.extend(players);
+        json!({
+            "title": self.title,
+            "description": Message::Rolls(self.rolls).text(),
+            "color": COLOR,
+            "fields": fields,
+        })
+    }
+}
+
+fn chart(counts: &[u64]) -> Chart {
+    Chart {
+        bars: counts
+            .iter()
+            .enumerate()
+            .map(|(idx, count)| Bar {
+                label: format!("{}", idx + 1),
+                value: *count as f64,
+                caption: format!("{}", count),
+            })
+            .collect(),
+    }
+}
+
+// 全ての出目が等しい確率で出るという仮説に対するカイ二乗検定。統計量、自由度、p値を返す
+fn chi_square_test(counts: &[u64]) -> (f64, usize, f64) {
+    let total: u64 = counts.iter().sum();
+    let df = counts.len() - 1;
+    if total == 0 {
+        return (0.0, df, 1.0);
+    }
+    let expected = total as f64 / counts.len() as f64;
+    let chi_square: f64 = counts
+        .iter()
+        .map(|count| (*count as f64 - expected).powi(2) / expected)
+        .sum();
+    (chi_square, df, gamma_q(df as f64 / 2.0, chi_square / 2.0))
+}
+
+// 正則化された上側不完全ガンマ関数 Q(a, x)
+fn gamma_q(a: f64, x: f64) -> f64 {
+    const ITERATION_LIMIT: usize = 1000;
+    const EPS: f64 = 1e-12;
+
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let ln_prefix = a * x.ln() - x - ln_gamma(a);
+
+    if x < a + 1.0 {
+        // 級数展開で P(a, x) を求める
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        for n in 1..ITERATION_LIMIT {
+            term *= x / (a + n as f64);
+            sum += term;
+            if term.abs() < sum.abs() * EPS {
+                break;
+            }
+        }
+        1.0 - sum * ln_prefix.exp()
+    } else {
+        // 連分数展開（Lentz法）で Q(a, x) を求める
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for n in 1..ITERATION_LIMIT {
+            let an = -(n as f64) * (n as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < EPS {
+                break;
+            }
+        }
+        h * ln_prefix.exp()
+    }
+}
+
+// ガンマ関数の対数（Lanczos近似）
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.180_091_729_471_46,
+        -86.505_320_329_416_77,
+        24.014_098_240_830_91,
+        -1.231_739_572_450_155,
+        0.001_208_650_973_866_179,
+        -0.000_005_395_239_384_953,
+    ];
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let mut ser = 1.000_000_000_190_015;
+    for (idx, coefficient) in COEFFICIENTS.iter().enumerate() {
+        ser += coefficient / (x + 1.0 + idx as f64);
+    }
+    -tmp + (2.506_628_274_631_000_5 * ser / x).ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+    use crate::roll;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn chi_square_p_values() {
+        // カイ二乗分布表の値
+        assert_close(gamma_q(5.0 / 2.0, 11.070 / 2.0), 0.05);
+        assert_close(gamma_q(5.0 / 2.0, 15.086 / 2.0), 0.01);
+        assert_close(gamma_q(1.0 / 2.0, 3.841 / 2.0), 0.05);
+        assert_close(gamma_q(99.0 / 2.0, 123.225 / 2.0), 0.05);
+        assert_close(gamma_q(9.0 / 2.0, 0.0), 1.0);
+    }
+
+    #[test]
+    fn skip_unrevealed_secrets() {
+        let entry = |secret: bool, faces: &[i64]| -> Entry {
+            serde_json::from_value(json!({
+                "timestamp": "2021-01-01T00:00:00Z",
+                "channel_id": 1,
+                "user_id": 1,
+                "user_name": "アリス",
+                "input": "2d6",
+                "syntax": "Native",
+                "log": [LogEntry::Roll(RollEvent::new("d", 6, faces.to_vec(), None))],
+                "value": "7",
+                "seed": 0,
+                "secret": secret,
+            }))
+            .unwrap()
+        };
+        let report = Report::new(
+            String::new(),
+            &[entry(false, &[3, 4]), entry(true, &[6, 6])],
+        );
+        assert_eq!(report.rolls, 1);
+        assert_eq!(report.players["アリス"].rolls, 1);
+        assert_eq!(report.faces[&6], [0, 0, 1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn count_dice_in_log() {
+        let rolled = roll::evaluate(
+            &parser::Syntax::Native,
+            None,
+            &[],
+            None,
+            0,
+            "[max_of 1 2b20, cc 1 50]",
+        )
+        .unwrap();
+        let entry: Entry = serde_json::from_value(json!({
+            "timestamp": "2021-01-01T00:00:00Z",
+            "channel_id": 1,
+            "user_id": 1,
+            "user_name": "アリス",
+            "input": rolled.input,
+            "syntax": "Native",
+            "log": rolled.log,
+            "value": format!("{}", rolled.value),
+            "seed": 0,
+        }))
+        .unwrap();
+        let report = Report::new(String::new(), &[entry]);
+        // 選んだ出目は振ったダイスとして数えず、ゲームシステムのダイスは数える
+        assert_eq!(report.faces[&20].iter().sum::<u64>(), 2);
+        assert_eq!(report.faces[&100].iter().sum::<u64>(), 2);
+    }
+
+    #[test]
+    fn split_long_player_fields() {
+        let entries: Vec<Entry> = (0..200)
+            .map(|idx| {
+                serde_json::from_value(json!({
+                    "timestamp": "2021-01-01T00:00:00Z",
+                    "channel_id": 1,
+                    "user_id": idx,
+                    "user_name": format!("プレイヤー{}", idx),
+                    "input": "1d6",
+                    "syntax": "Native",
+                    "log": [LogEntry::Roll(RollEvent::new("d", 6, vec![1], Some(1)))],
+                    "value": "1",
+                    "seed": 0,
+                    "secret": false,
+                }))
+                .unwrap()
+            })
+            .collect();
+        let embed = Report::new(String::new(), &entries).embed();
+        let fields = embed["fields"].as_array().unwrap();
+        assert!(fields.len() > 2);
+        assert!(fields.len() <= render::FIELDS_LIMIT);
+        for field in fields {
+            let value = field["value"].as_str().unwrap();
+            assert!(value.chars().count() <= render::FIELD_LIMIT);
+        }
+    }
+
+    #[test]
+    fn uniform_counts_are_fair() {
+        let (chi_square, df, p) = chi_square_test(&[10, 10, 10, 10, 10, 10]);
+        assert_close(chi_square, 0.0);
+        assert_eq!(df, 5);
+        assert_close(p, 1.0);
+
+        let (_, _, p) = chi_square_test(&[60, 0, 0, 0, 0, 0]);
+        assert!(p < SIGNIFICANCE);
+    }
+}
@@ -0,0 +1,69 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tokio::sync::{RwLock, RwLockReadGuard};
+
+// 保存先のディレクトリ。環境変数DICEBOT_DATA_DIRで変更できる
+pub fn data_dir() -> PathBuf {
+    std::env::var("DICEBOT_DATA_DIR")
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .map_or_else(|| PathBuf::from("data"), PathBuf::from)
+}
+
+// JSONファイルに永続化されるデータ
+pub struct Store<T> {
+    path: PathBuf,
+    data: RwLock<T>,
+}
+
+impl<T: Serialize + DeserializeOwned + Default> Store<T> {
+    pub fn open(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let data = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|why| {
+                println!("Failed to parse {}: {:?}", path.display(), why);
+                T::default()
+            }),
+            Err(_) => T::default(),
+        };
+        Self {
+            path,
+            data: RwLock::new(data),
+        }
+    }
+
+    pub async fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.data.read().await
+    }
+
+    // データを更新し、ファイルに書き出す。書き終えるまでロックを持ち、古い内容で上書きしないようにする
+    pub async fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let mut data = self.data.write().await;
+        let res = f(&mut data);
+        let saved = match serde_json::to_string_pretty(&*data) {
+            Ok(json) => {
+                let path = self.path.clone();
+                // ハンドラーを止めないよう、別のスレッドで書き込む
+                tokio::task::spawn_blocking(move || save(&path, &json))
+                    .await
+                    .unwrap_or_else(|why| Err(std::io::Error::other(why)))
+            }
+            Err(why) => Err(why.into()),
+        };
+        if let Err(why) = saved {
+            println!("Failed to save {}: {:?}", self.path.display(), why);
+        }
+        res
+    }
+}
+
+fn save(path: &Path, json: &str) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // 書き込み途中で落ちてもファイルが壊れないよう、一時ファイルに書いてから置き換える
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, json)?;
+    std::fs::rename(&tmp, path)
+}